@@ -1,11 +1,17 @@
+mod compat_palette;
 mod keycode;
+mod palette;
+mod speed;
 
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use serde::Deserialize;
 
+pub use self::compat_palette::CompatPaletteConfig;
 pub use self::keycode::Keycode;
+pub use self::palette::PaletteConfig;
+pub use self::speed::Speed;
 
 /// Pinout configuration structure
 #[derive(Debug, Clone, Deserialize)]
@@ -24,6 +30,45 @@ pub struct AppConfig {
     /// Power switches configuration
     #[serde(rename = "powerswitch", default)]
     pub power_switches: Vec<PowerSwitchConfig>,
+    /// Outputs driven by the rumble motor of the cartridge
+    #[serde(rename = "rumble", default)]
+    pub rumble: Vec<RumbleConfig>,
+    /// Rewind configuration; rewind is disabled if not set
+    #[serde(default)]
+    pub rewind: Option<RewindConfig>,
+    /// Emulation speed configuration
+    #[serde(default)]
+    pub speed: SpeedConfig,
+    /// Battery save configuration
+    #[serde(default)]
+    pub battery_save: BatterySaveConfig,
+    /// Shutdown triggered by the power switches
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    /// Boot ROMs to run before the games
+    #[serde(default)]
+    pub boot_rom: BootRomConfig,
+    /// Colors of classic games on the color hardware
+    #[serde(default)]
+    pub compat_palette: CompatPaletteConfig,
+    /// Colors of classic games
+    #[serde(default)]
+    pub palette: PaletteConfig,
+    /// Time followed by the clock of the cartridges which have one
+    #[serde(default)]
+    pub rtc_clock: RtcClockConfig,
+    /// How the picture is drawn
+    #[serde(default)]
+    pub renderer: RendererConfig,
+    /// Debug views of the video memory
+    #[serde(default)]
+    pub vram_viewer: VramViewerConfig,
+    /// Screenshots taken with a button combo
+    #[serde(default)]
+    pub screenshot: ScreenshotConfig,
+    /// Video recordings started and stopped with a button combo
+    #[serde(default)]
+    pub recording: RecordingConfig,
 }
 
 impl AppConfig {
@@ -80,6 +125,16 @@ impl KeyConfig {
     }
 }
 
+/// Configuration for a GPIO output driven by the rumble motor of the cartridge
+#[derive(Debug, Clone, Deserialize)]
+pub struct RumbleConfig {
+    /// GPIO pin number
+    pub gpio: u8,
+    /// Whether the output is active low; if true, the pin is driven low while rumbling
+    #[serde(default)]
+    pub active_low: bool,
+}
+
 /// Configuration for an individual power switch
 #[derive(Debug, Clone, Deserialize)]
 pub struct PowerSwitchConfig {
@@ -87,6 +142,351 @@ pub struct PowerSwitchConfig {
     pub gpio: u8,
     /// Whether the switch is active low; if true, switch is active when GPIO is low
     pub active_low: Option<bool>,
+    debounce_ms: Option<u64>,
+    /// Action on press, or on a short press if `long_press` is set
+    #[serde(default)]
+    pub action: PowerActionConfig,
+    /// Action when the switch is held
+    pub long_press: Option<LongPressConfig>,
+}
+
+impl PowerSwitchConfig {
+    /// Debounce time
+    pub fn debounce(&self) -> Option<Duration> {
+        self.debounce_ms.map(Duration::from_millis)
+    }
+}
+
+/// Time followed by the clock of the cartridge
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RtcClockConfig {
+    /// The clock of the system, even while the emulator is not running
+    #[default]
+    Wall,
+    /// The emulated time, which stops while suspended and follows the emulation speed
+    Emulated,
+}
+
+impl RtcClockConfig {
+    /// Clock for the emulator
+    pub fn clock(&self) -> rboy::RtcClock {
+        match self {
+            Self::Wall => rboy::RtcClock::Wall,
+            Self::Emulated => rboy::RtcClock::Emulated,
+        }
+    }
+}
+
+/// How the picture is drawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RendererConfig {
+    /// A line at a time, fast enough for the slowest boards
+    #[default]
+    Scanline,
+    /// A pixel at a time, showing the effects made in the middle of the lines
+    Fifo,
+}
+
+impl RendererConfig {
+    /// Renderer for the emulator
+    pub fn renderer(&self) -> rboy::Renderer {
+        match self {
+            Self::Scanline => rboy::Renderer::Scanline,
+            Self::Fifo => rboy::Renderer::PixelFifo,
+        }
+    }
+}
+
+/// Action carried out by a power switch
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerActionConfig {
+    /// Stop the emulator, write the saves and run the `[shutdown]` command
+    #[default]
+    Shutdown,
+    /// Exit the application
+    Exit,
+    /// Return to the game menu
+    Menu,
+    /// Pause or resume the emulation
+    Suspend,
+    /// Run a custom command with its arguments
+    Command(Vec<String>),
+}
+
+impl PowerActionConfig {
+    /// Power action for the input listener
+    pub fn action(&self) -> rboy::input::PowerAction {
+        use rboy::input::PowerAction;
+
+        match self {
+            Self::Shutdown => PowerAction::Shutdown,
+            Self::Exit => PowerAction::Exit,
+            Self::Menu => PowerAction::Menu,
+            Self::Suspend => PowerAction::Suspend,
+            Self::Command(command) => PowerAction::Command(command.clone()),
+        }
+    }
+}
+
+/// Configuration for the long press of a power switch
+#[derive(Debug, Clone, Deserialize)]
+pub struct LongPressConfig {
+    /// how long the switch must be held, in milliseconds
+    #[serde(default = "LongPressConfig::default_duration_ms")]
+    duration_ms: u64,
+    pub action: PowerActionConfig,
+}
+
+impl LongPressConfig {
+    fn default_duration_ms() -> u64 {
+        2000
+    }
+
+    /// How long the switch must be held
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+}
+
+/// Configuration for rewinding gameplay
+#[derive(Debug, Clone, Deserialize)]
+pub struct RewindConfig {
+    /// Keys to hold together to play the game backwards
+    pub combo: Vec<Keycode>,
+    /// A snapshot is recorded every `interval_frames` frames
+    pub interval_frames: u32,
+    /// Maximum number of snapshots to keep
+    pub capacity: usize,
+}
+
+impl RewindConfig {
+    /// Keys of the rewind combo
+    pub fn combo(&self) -> Vec<rboy::KeypadKey> {
+        self.combo.iter().map(Keycode::keycode).collect()
+    }
+}
+
+/// Configuration for the emulation speed
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpeedConfig {
+    /// Speed the emulator starts with
+    #[serde(default)]
+    pub default: Speed,
+    /// Keys to hold together to switch to the next speed in `steps`
+    #[serde(default)]
+    pub combo: Vec<Keycode>,
+    /// Speeds cycled through by the combo
+    #[serde(default = "SpeedConfig::default_steps")]
+    pub steps: Vec<Speed>,
+}
+
+impl SpeedConfig {
+    fn default_steps() -> Vec<Speed> {
+        vec![
+            Speed::Multiplier(1.0),
+            Speed::Multiplier(2.0),
+            Speed::Multiplier(4.0),
+            Speed::Uncapped,
+        ]
+    }
+
+    /// Keys of the speed combo
+    pub fn combo(&self) -> Vec<rboy::KeypadKey> {
+        self.combo.iter().map(Keycode::keycode).collect()
+    }
+
+    /// Speed which follows `current` in `steps`
+    pub fn next_step(&self, current: Speed) -> Speed {
+        let next = self
+            .steps
+            .iter()
+            .position(|s| *s == current)
+            .map(|i| i + 1)
+            .unwrap_or_default();
+        self.steps
+            .get(next % self.steps.len().max(1))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+impl Default for SpeedConfig {
+    fn default() -> Self {
+        Self {
+            default: Speed::default(),
+            combo: Vec::new(),
+            steps: Self::default_steps(),
+        }
+    }
+}
+
+/// Configuration of the debug views of the video memory
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VramViewerConfig {
+    /// Keys to hold together to show or hide the views, paged through with LEFT and RIGHT
+    #[serde(default)]
+    pub combo: Vec<Keycode>,
+}
+
+impl VramViewerConfig {
+    /// Keys of the viewer combo
+    pub fn combo(&self) -> Vec<rboy::KeypadKey> {
+        self.combo.iter().map(Keycode::keycode).collect()
+    }
+}
+
+/// Configuration of the screenshots
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ScreenshotConfig {
+    /// Keys to hold together to save a picture of the screen
+    #[serde(default)]
+    pub combo: Vec<Keycode>,
+    /// Directory of the pictures; `screenshots` in the ROMs directory if not set
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+}
+
+impl ScreenshotConfig {
+    /// Keys of the screenshot combo
+    pub fn combo(&self) -> Vec<rboy::KeypadKey> {
+        self.combo.iter().map(Keycode::keycode).collect()
+    }
+
+    /// Directory the pictures are saved into
+    pub fn directory(&self, roms_directory: &Path) -> PathBuf {
+        self.directory
+            .clone()
+            .unwrap_or_else(|| roms_directory.join("screenshots"))
+    }
+}
+
+/// Configuration of the video recordings
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RecordingConfig {
+    /// Keys to hold together to start or stop recording the screen and the sound
+    #[serde(default)]
+    pub combo: Vec<Keycode>,
+    /// Directory of the videos; `recordings` in the ROMs directory if not set
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+}
+
+impl RecordingConfig {
+    /// Keys of the recording combo
+    pub fn combo(&self) -> Vec<rboy::KeypadKey> {
+        self.combo.iter().map(Keycode::keycode).collect()
+    }
+
+    /// Directory the videos are saved into
+    pub fn directory(&self, roms_directory: &Path) -> PathBuf {
+        self.directory
+            .clone()
+            .unwrap_or_else(|| roms_directory.join("recordings"))
+    }
+}
+
+/// Configuration for writing the battery backed RAM of the cartridge
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatterySaveConfig {
+    /// delay between a change of the RAM and its write to the save file, in milliseconds
+    #[serde(default = "BatterySaveConfig::default_flush_interval_ms")]
+    flush_interval_ms: u64,
+    /// Number of backups of the previous save file to keep.
+    ///
+    /// They are rotated once per session, on the first write of the save file after the game
+    /// was loaded, so that they hold the saves of the previous sessions rather than the last few
+    /// flushes.
+    #[serde(default)]
+    pub backups: usize,
+    /// Extension of the save file, next to the ROM
+    #[serde(default = "BatterySaveConfig::default_extension")]
+    pub extension: String,
+}
+
+impl BatterySaveConfig {
+    fn default_flush_interval_ms() -> u64 {
+        5000
+    }
+
+    fn default_extension() -> String {
+        "gbsave".to_string()
+    }
+
+    /// Delay between a change of the RAM and its write to the save file
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_millis(self.flush_interval_ms)
+    }
+}
+
+impl Default for BatterySaveConfig {
+    fn default() -> Self {
+        Self {
+            flush_interval_ms: Self::default_flush_interval_ms(),
+            backups: 0,
+            extension: Self::default_extension(),
+        }
+    }
+}
+
+/// Paths of the boot ROMs; games start right away when not set
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BootRomConfig {
+    /// Boot ROM of the classic Game Boy
+    pub dmg: Option<PathBuf>,
+    /// Boot ROM of the Game Boy Color, also used for classic games
+    pub cgb: Option<PathBuf>,
+}
+
+impl BootRomConfig {
+    /// Path of the boot ROM for a device running in `mode`
+    pub fn path(&self, mode: rboy::GbMode) -> Option<&Path> {
+        match mode {
+            rboy::GbMode::Classic => self.dmg.as_deref(),
+            rboy::GbMode::Color | rboy::GbMode::ColorAsClassic => self.cgb.as_deref(),
+        }
+    }
+}
+
+/// Configuration for the shutdown triggered by a power switch
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShutdownConfig {
+    /// Command run once the emulator stopped, with its arguments
+    #[serde(default = "ShutdownConfig::default_command")]
+    pub command: Vec<String>,
+    /// time given to the shutdown command before it is killed, in milliseconds
+    #[serde(default = "ShutdownConfig::default_timeout_ms")]
+    timeout_ms: u64,
+    /// Save state slot written before shutting down, if any
+    #[serde(default)]
+    pub autosave_slot: Option<u8>,
+}
+
+impl ShutdownConfig {
+    fn default_command() -> Vec<String> {
+        vec!["shutdown".to_string(), "-h".to_string(), "now".to_string()]
+    }
+
+    fn default_timeout_ms() -> u64 {
+        10000
+    }
+
+    /// Time given to the shutdown command before it is killed
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            command: Self::default_command(),
+            timeout_ms: Self::default_timeout_ms(),
+            autosave_slot: None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -95,6 +495,7 @@ mod tests {
     use rboy::KeypadKey;
     use tempfile::NamedTempFile;
 
+    use super::palette::Color;
     use super::*;
 
     #[test]
@@ -120,9 +521,130 @@ mod tests {
         assert_eq!(config.keys[1].repeat_delay_ms, Some(300));
         assert_eq!(config.keys[1].repeat_rate_ms, Some(80));
 
+        assert_eq!(config.rumble.len(), 1);
+        assert_eq!(config.rumble[0].gpio, 18);
+        assert!(!config.rumble[0].active_low);
+
         assert_eq!(config.power_switches.len(), 1);
         assert_eq!(config.power_switches[0].gpio, 27);
         assert_eq!(config.power_switches[0].active_low, Some(false));
+        assert_eq!(config.power_switches[0].action, PowerActionConfig::Menu);
+        let long_press = config.power_switches[0].long_press.as_ref().unwrap();
+        assert_eq!(long_press.duration(), Duration::from_millis(3000));
+        assert_eq!(
+            long_press.action,
+            PowerActionConfig::Command(vec!["echo".to_string(), "hello".to_string()])
+        );
+
+        let rewind = config.rewind.unwrap();
+        assert_eq!(rewind.combo(), vec![KeypadKey::Select, KeypadKey::Left]);
+        assert_eq!(rewind.interval_frames, 10);
+        assert_eq!(rewind.capacity, 60);
+
+        assert_eq!(config.speed.default, Speed::Multiplier(1.0));
+        assert_eq!(
+            config.speed.combo(),
+            vec![KeypadKey::Select, KeypadKey::Right]
+        );
+        assert_eq!(
+            config.speed.steps,
+            vec![
+                Speed::Multiplier(0.5),
+                Speed::Multiplier(1.0),
+                Speed::Multiplier(4.0),
+                Speed::Uncapped
+            ]
+        );
+
+        assert_eq!(
+            config.battery_save.flush_interval(),
+            Duration::from_millis(2000)
+        );
+        assert_eq!(config.battery_save.backups, 3);
+        assert_eq!(config.battery_save.extension, "sav");
+
+        assert_eq!(config.shutdown.command, vec!["poweroff"]);
+        assert_eq!(config.shutdown.timeout(), Duration::from_millis(3000));
+        assert_eq!(config.shutdown.autosave_slot, Some(9));
+
+        assert_eq!(
+            config.compat_palette,
+            CompatPaletteConfig::Manual(rboy::CompatPalette::LeftA)
+        );
+        assert_eq!(config.rtc_clock, RtcClockConfig::Emulated);
+        assert_eq!(config.renderer, RendererConfig::Fifo);
+
+        assert_eq!(config.palette.default, "dmg");
+        assert_eq!(
+            config.palette.combo(),
+            vec![KeypadKey::Select, KeypadKey::Down]
+        );
+        let custom = config.palette.custom[0].palette();
+        assert_eq!(config.palette.custom[0].name, "lcd");
+        assert_eq!(custom.bg, [0xE0F8D0, 0x88C070, 0x346856, 0x081820]);
+        assert_eq!(custom.obj0, custom.bg);
+        assert_eq!(custom.obj1, [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]);
+
+        assert_eq!(
+            config.vram_viewer.combo(),
+            vec![KeypadKey::Select, KeypadKey::Start, KeypadKey::B]
+        );
+        assert_eq!(
+            config.screenshot.combo(),
+            vec![KeypadKey::Select, KeypadKey::Start, KeypadKey::A]
+        );
+        assert_eq!(
+            config.screenshot.directory(&config.roms_directory),
+            PathBuf::from("/home/pi/screenshots")
+        );
+        assert_eq!(
+            config.recording.combo(),
+            vec![KeypadKey::Select, KeypadKey::Start, KeypadKey::Up]
+        );
+        assert_eq!(
+            config.recording.directory(&config.roms_directory),
+            PathBuf::from("/home/pi/recordings")
+        );
+
+        assert_eq!(config.boot_rom.path(rboy::GbMode::Classic), None);
+        assert_eq!(
+            config.boot_rom.path(rboy::GbMode::ColorAsClassic),
+            Some(Path::new("/opt/boot/cgb_boot.bin"))
+        );
+    }
+
+    #[test]
+    fn test_should_cycle_speed_steps() {
+        let config = SpeedConfig::default();
+        assert_eq!(
+            config.next_step(Speed::Multiplier(1.0)),
+            Speed::Multiplier(2.0)
+        );
+        assert_eq!(config.next_step(Speed::Uncapped), Speed::Multiplier(1.0));
+        // unknown speeds restart from the first step
+        assert_eq!(
+            config.next_step(Speed::Multiplier(0.25)),
+            Speed::Multiplier(1.0)
+        );
+    }
+
+    #[test]
+    fn test_should_reject_speed_out_of_range() {
+        assert!(toml::from_str::<SpeedConfig>("default = 16").is_err());
+        assert!(toml::from_str::<SpeedConfig>("default = \"0.1x\"").is_err());
+        assert_eq!(
+            toml::from_str::<SpeedConfig>("default = \"2x\"")
+                .unwrap()
+                .default,
+            Speed::Multiplier(2.0)
+        );
+    }
+
+    #[test]
+    fn test_should_reject_invalid_color() {
+        assert_eq!("#9bbc0f".parse(), Ok(Color(0x9BBC0F)));
+        assert!("9BBC0F".parse::<Color>().is_err());
+        assert!("#9BBC0".parse::<Color>().is_err());
     }
 
     #[test]
@@ -137,14 +659,42 @@ mod tests {
 
     #[test]
     fn test_should_parse_config_without_arrays() {
-        let _config: AppConfig = toml::from_str(CONFIG_WNO_ARRAYS).unwrap();
+        let config: AppConfig = toml::from_str(CONFIG_WNO_ARRAYS).unwrap();
+        assert!(config.rewind.is_none());
+        assert_eq!(config.speed.default, Speed::Multiplier(1.0));
+        assert_eq!(
+            config.battery_save.flush_interval(),
+            Duration::from_millis(5000)
+        );
+        assert_eq!(config.battery_save.backups, 0);
+        assert_eq!(config.battery_save.extension, "gbsave");
+        assert_eq!(config.shutdown.command, vec!["shutdown", "-h", "now"]);
+        assert_eq!(config.shutdown.autosave_slot, None);
+        assert_eq!(config.compat_palette, CompatPaletteConfig::Auto);
+        assert_eq!(config.rtc_clock, RtcClockConfig::Wall);
+        assert_eq!(config.renderer, RendererConfig::Scanline);
+        assert_eq!(config.palette.default, "gray");
+        assert!(config.vram_viewer.combo.is_empty());
+        assert!(config.screenshot.combo.is_empty());
+        assert_eq!(
+            config.screenshot.directory(&config.roms_directory),
+            config.roms_directory.join("screenshots")
+        );
+        assert!(config.recording.combo.is_empty());
+        assert_eq!(
+            config.recording.directory(&config.roms_directory),
+            config.roms_directory.join("recordings")
+        );
     }
 
-    const DEFAULT_CONFIG: &str = r#"
+    const DEFAULT_CONFIG: &str = r##"
 roms_directory = "./roms"
 default_debounce_ms = 20 # default debounce time in milliseconds
 default_active_low = true # default active_low setting for keys; if true, key is active when GPIO is low
 poll_interval_ms = 5 # polling interval in milliseconds
+compat_palette = "left+a"
+rtc_clock = "emulated"
+renderer = "fifo"
 
 [[key]]
 gpio = 17
@@ -163,7 +713,55 @@ repeat_rate_ms = 80
 [[powerswitch]]
 gpio = 27
 active_low = false
-    "#;
+action = "menu"
+long_press = { action = { command = ["echo", "hello"] }, duration_ms = 3000 }
+
+[[rumble]]
+gpio = 18
+
+[rewind]
+combo = ["SELECT", "LEFT"]
+interval_frames = 10
+capacity = 60
+
+[speed]
+default = 1
+combo = ["SELECT", "RIGHT"]
+steps = [0.5, 1, 4, "uncapped"]
+
+[battery_save]
+flush_interval_ms = 2000
+backups = 3
+extension = "sav"
+
+[boot_rom]
+cgb = "/opt/boot/cgb_boot.bin"
+
+[shutdown]
+command = ["poweroff"]
+timeout_ms = 3000
+autosave_slot = 9
+
+[palette]
+default = "dmg"
+combo = ["SELECT", "DOWN"]
+
+[[palette.custom]]
+name = "lcd"
+bg = ["#E0F8D0", "#88C070", "#346856", "#081820"]
+obj1 = ["#FFFFFF", "#FF8484", "#943A3A", "#000000"]
+
+[vram_viewer]
+combo = ["SELECT", "START", "B"]
+
+[screenshot]
+combo = ["SELECT", "START", "A"]
+directory = "/home/pi/screenshots"
+
+[recording]
+combo = ["SELECT", "START", "UP"]
+directory = "/home/pi/recordings"
+    "##;
 
     const CONFIG_WNO_ARRAYS: &str = r#"
 default_debounce_ms = 20 # default debounce time in milliseconds
@@ -0,0 +1,59 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rboy::{CompatPalette, DmgPalette};
+
+/// Colors of classic games running in color mode, unless a CGB boot ROM picks them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompatPaletteConfig {
+    /// Palette the CGB boot ROM would pick for the game
+    #[default]
+    Auto,
+    /// Keep the classic gray scale
+    Gray,
+    /// Palette of a button combo, e.g. `up+a`
+    Manual(CompatPalette),
+}
+
+impl CompatPaletteConfig {
+    /// Colors to apply to a game, given the ones the boot ROM would pick
+    pub fn colors(&self, auto: DmgPalette) -> Option<DmgPalette> {
+        match self {
+            Self::Auto => Some(auto),
+            Self::Gray => None,
+            Self::Manual(palette) => Some(palette.colors()),
+        }
+    }
+}
+
+impl fmt::Display for CompatPaletteConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Gray => write!(f, "gray"),
+            Self::Manual(palette) => write!(f, "{palette}"),
+        }
+    }
+}
+
+impl FromStr for CompatPaletteConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "gray" => Ok(Self::Gray),
+            combo => CompatPalette::from_str(combo).map(Self::Manual),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for CompatPaletteConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        CompatPaletteConfig::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
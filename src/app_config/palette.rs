@@ -0,0 +1,114 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rboy::{DmgPalette, PalettePreset};
+use serde::Deserialize;
+
+use super::Keycode;
+
+/// Configuration of the colors of classic games
+#[derive(Debug, Clone, Deserialize)]
+pub struct PaletteConfig {
+    /// Palette of games without a saved choice: a preset or the name of a custom palette
+    #[serde(default = "PaletteConfig::default_name")]
+    pub default: String,
+    /// Keys to hold together to switch to the next palette
+    #[serde(default)]
+    pub combo: Vec<Keycode>,
+    /// User defined palettes, cycled through after the presets
+    #[serde(default)]
+    pub custom: Vec<CustomPaletteConfig>,
+}
+
+impl PaletteConfig {
+    fn default_name() -> String {
+        PalettePreset::Gray.to_string()
+    }
+
+    /// Keys of the palette combo
+    pub fn combo(&self) -> Vec<rboy::KeypadKey> {
+        self.combo.iter().map(Keycode::keycode).collect()
+    }
+
+    /// Names and colors of the presets followed by the custom palettes
+    pub fn palettes(&self) -> Vec<(String, DmgPalette)> {
+        PalettePreset::ALL
+            .into_iter()
+            .map(|preset| (preset.to_string(), preset.palette()))
+            .chain(
+                self.custom
+                    .iter()
+                    .map(|custom| (custom.name.clone(), custom.palette())),
+            )
+            .collect()
+    }
+}
+
+impl Default for PaletteConfig {
+    fn default() -> Self {
+        Self {
+            default: Self::default_name(),
+            combo: Vec::new(),
+            custom: Vec::new(),
+        }
+    }
+}
+
+/// A user defined palette
+#[derive(Debug, Clone, Deserialize)]
+pub struct CustomPaletteConfig {
+    /// Name used to select the palette
+    pub name: String,
+    /// Background colors, from lightest to darkest
+    pub bg: [Color; 4],
+    /// Colors of the first object palette; same as `bg` if not set
+    pub obj0: Option<[Color; 4]>,
+    /// Colors of the second object palette; same as `obj0` if not set
+    pub obj1: Option<[Color; 4]>,
+}
+
+impl CustomPaletteConfig {
+    /// Colors of the palette
+    pub fn palette(&self) -> DmgPalette {
+        let rgb = |colors: [Color; 4]| colors.map(|c| c.0);
+        let obj0 = self.obj0.unwrap_or(self.bg);
+        DmgPalette {
+            bg: rgb(self.bg),
+            obj0: rgb(obj0),
+            obj1: rgb(self.obj1.unwrap_or(obj0)),
+        }
+    }
+}
+
+/// A `0xRRGGBB` color, written as `"#RRGGBB"` in the configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color(pub u32);
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:06X}", self.0)
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .map(Color)
+            .ok_or_else(|| format!("Invalid color: {s}"))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Color {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Color::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
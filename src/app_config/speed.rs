@@ -0,0 +1,100 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Emulation speed, relative to the original hardware
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// Run at the given multiple of the original speed
+    Multiplier(f64),
+    /// Run as fast as possible
+    Uncapped,
+}
+
+impl Speed {
+    /// Slowest supported multiplier
+    pub const MIN: f64 = 0.25;
+    /// Fastest supported multiplier, besides [`Speed::Uncapped`]
+    pub const MAX: f64 = 8.0;
+
+    /// Scale the number of ticks to run in a period at normal speed
+    ///
+    /// When uncapped the budget is left as is, since periods are not waited for.
+    pub fn tick_budget(&self, ticks: u32) -> u32 {
+        match self {
+            Self::Multiplier(m) => (ticks as f64 * m).round() as u32,
+            Self::Uncapped => ticks,
+        }
+    }
+
+    /// Whether the emulator should wait for the end of each period
+    pub fn is_capped(&self) -> bool {
+        matches!(self, Self::Multiplier(_))
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Self::Multiplier(1.0)
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Multiplier(m) => write!(f, "{m}x"),
+            Self::Uncapped => write!(f, "uncapped"),
+        }
+    }
+}
+
+impl FromStr for Speed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        if s == "uncapped" {
+            return Ok(Self::Uncapped);
+        }
+        let multiplier: f64 = s
+            .trim_end_matches('x')
+            .parse()
+            .map_err(|_| format!("Invalid speed: {s}"))?;
+        Self::try_from(multiplier)
+    }
+}
+
+impl TryFrom<f64> for Speed {
+    type Error = String;
+
+    fn try_from(multiplier: f64) -> Result<Self, Self::Error> {
+        if (Self::MIN..=Self::MAX).contains(&multiplier) {
+            Ok(Self::Multiplier(multiplier))
+        } else {
+            Err(format!(
+                "Speed {multiplier} out of range ({}-{})",
+                Self::MIN,
+                Self::MAX
+            ))
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Speed {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum RawSpeed {
+            Number(f64),
+            Text(String),
+        }
+
+        match RawSpeed::deserialize(deserializer)? {
+            RawSpeed::Number(n) => Speed::try_from(n),
+            RawSpeed::Text(s) => Speed::from_str(&s),
+        }
+        .map_err(serde::de::Error::custom)
+    }
+}
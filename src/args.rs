@@ -3,6 +3,7 @@ mod log_level;
 use std::path::PathBuf;
 
 pub use self::log_level::LogLevel;
+use crate::debug_server::DebugEndpoint;
 
 /// rboy-legogb - rboy emulator adapted to run on Raspberry Pi with framebuffer and GPIO input
 #[derive(argh::FromArgs, Debug)]
@@ -10,6 +11,9 @@ pub struct Args {
     /// bytes per pixel for the framebuffer (default: 2)
     #[argh(option, default = "2")]
     pub bytes_per_pixel: usize,
+    /// serve the debugger on "stdin", or on the given unix socket path; the game starts paused
+    #[argh(option)]
+    pub debugger: Option<DebugEndpoint>,
     /// path to config file (default: /etc/rboy-legogb/config.toml)
     #[argh(option, default = "PathBuf::from(\"/etc/rboy-legogb/config.toml\")")]
     pub config: PathBuf,
@@ -28,6 +32,15 @@ pub struct Args {
     /// framebuffer width (default: 320)
     #[argh(option, default = "320")]
     pub width: usize,
+    /// write a trace of the instructions to this file, in the format of Gameboy Doctor
+    #[argh(option)]
+    pub trace: Option<PathBuf>,
+    /// most instructions written to the trace (default: 1000000)
+    #[argh(option, default = "1_000_000")]
+    pub trace_limit: usize,
+    /// read LY as 0x90 while tracing, to compare with the logs of Gameboy Doctor
+    #[argh(switch)]
+    pub trace_stub_ly: bool,
     /// path to ROM file
     #[argh(positional)]
     pub rom_path: Option<PathBuf>,
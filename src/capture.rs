@@ -0,0 +1,44 @@
+use std::path::{Path, PathBuf};
+
+/// Most screenshots or recordings kept per game
+const MAX_FILES: u32 = 10000;
+
+/// Path of the next screenshot or recording of the game with the given extension, numbered
+/// after the existing ones, creating the directory if needed
+pub fn next_path(directory: &Path, rom_file: &Path, extension: &str) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(directory)
+        .map_err(|e| anyhow::anyhow!("Failed to create directory {}: {e}", directory.display()))?;
+    let name = rom_file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    (1..MAX_FILES)
+        .map(|n| directory.join(format!("{name}-{n:04}.{extension}")))
+        .find(|path| !path.exists())
+        .ok_or_else(|| anyhow::anyhow!("Too many files in {}", directory.display()))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_should_number_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let directory = dir.path().join("screenshots");
+        let rom_file = Path::new("/roms/tetris.gb");
+
+        let first = next_path(&directory, rom_file, "png").unwrap();
+        assert_eq!(first, directory.join("tetris-0001.png"));
+        std::fs::write(&first, b"").unwrap();
+        assert_eq!(
+            next_path(&directory, rom_file, "png").unwrap(),
+            directory.join("tetris-0002.png")
+        );
+        assert_eq!(
+            next_path(&directory, rom_file, "avi").unwrap(),
+            directory.join("tetris-0001.avi")
+        );
+    }
+}
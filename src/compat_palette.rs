@@ -0,0 +1,348 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::palette::DmgPalette;
+
+/// Palette used by the Game Boy Color to color a game made for the classic Game Boy.
+///
+/// The variants are the palettes which can be picked by holding a button combo while the
+/// Game Boy Color boots, named after the combo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompatPalette {
+    /// Brown
+    Up,
+    /// Red
+    UpA,
+    /// Dark brown
+    UpB,
+    /// Blue
+    Left,
+    /// Dark blue
+    LeftA,
+    /// Gray
+    LeftB,
+    /// Pale yellow
+    Down,
+    /// Orange
+    DownA,
+    /// Yellow
+    DownB,
+    /// Green
+    Right,
+    /// Dark green, also used for games the boot ROM doesn't know
+    RightA,
+    /// Inverted
+    RightB,
+}
+
+/// Checksums of the titles known to the boot ROM. The first 65 are enough to recognize a
+/// game; the others are shared by several titles, told apart by `FOURTH_LETTERS`
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, // titles told apart by their fourth letter
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3, 0x46,
+    0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+
+/// Fourth letter of the titles with a shared checksum, matching the end of `TITLE_CHECKSUMS`
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// Titles recognized by their checksum alone
+const UNIQUE_CHECKSUMS: usize = 65;
+
+/// Index in `COMBINATIONS` of the palettes of each title, matching `TITLE_CHECKSUMS`
+const TITLE_COMBINATIONS: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29,
+    5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42, 6,
+    5, 33, 25, 42, 42, 40, 14, 16, 25, 42, 42, 5, 0, 39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39,
+    24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+/// Colors of the boot ROM, in RGB555 as in the palette memory, 4 per palette
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, 0x639F, 0x4279, 0x15B0, 0x04CB, 0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000, 0x7FFF, 0x421F, 0x1CF2, 0x0000, 0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000, 0x7FFF, 0x03EF, 0x01D6, 0x0000, 0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000, 0x67FF, 0x77AC, 0x1A13, 0x2D6B, 0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000, 0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0, 0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF, 0x7FFF, 0x01DF, 0x0112, 0x0000, 0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000, 0x299F, 0x001A, 0x000C, 0x0000, 0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120, 0x7FFF, 0x7EEB, 0x001F, 0x7C00, 0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000, 0x03FF, 0x001F, 0x000C, 0x0000, 0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF, 0x7FFF, 0x7E8C, 0x7C00, 0x0000, 0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+/// Palettes given to the games, as offsets in `COLORS` of the colors of OBJ0, OBJ1 and BG.
+///
+/// A few offsets are not at the start of a palette, shifting its colors by one.
+const COMBINATIONS: [[usize; 3]; 51] = [
+    [16, 16, 116],
+    [72, 72, 72],
+    [80, 80, 80],
+    [96, 96, 96],
+    [36, 36, 36],
+    [0, 0, 0],
+    [108, 108, 108],
+    [20, 20, 20],
+    [48, 48, 48],
+    [104, 104, 104],
+    [64, 32, 32],
+    [16, 112, 112],
+    [16, 8, 8],
+    [12, 16, 16],
+    [16, 116, 116],
+    [112, 16, 112],
+    [8, 68, 8],
+    [64, 64, 32],
+    [16, 16, 28],
+    [16, 16, 72],
+    [16, 16, 80],
+    [76, 76, 36],
+    [15, 15, 44],
+    [68, 68, 8],
+    [16, 16, 8],
+    [16, 16, 12],
+    [112, 112, 0],
+    [12, 12, 0],
+    [0, 0, 4],
+    [72, 88, 72],
+    [80, 88, 80],
+    [96, 88, 96],
+    [64, 88, 32],
+    [68, 16, 52],
+    [111, 0, 56],
+    [111, 16, 60],
+    [76, 88, 36],
+    [64, 112, 40],
+    [16, 92, 112],
+    [68, 88, 8],
+    [16, 0, 8],
+    [16, 112, 12],
+    [112, 12, 0],
+    [12, 112, 16],
+    [84, 112, 16],
+    [12, 112, 0],
+    [100, 12, 112],
+    [0, 112, 32],
+    [16, 12, 112],
+    [112, 12, 24],
+    [16, 112, 116],
+];
+
+impl CompatPalette {
+    /// All the palettes, in the order of the button combos
+    pub const ALL: [CompatPalette; 12] = [
+        Self::Up,
+        Self::UpA,
+        Self::UpB,
+        Self::Left,
+        Self::LeftA,
+        Self::LeftB,
+        Self::Down,
+        Self::DownA,
+        Self::DownB,
+        Self::Right,
+        Self::RightA,
+        Self::RightB,
+    ];
+
+    /// Palette used when the boot ROM doesn't recognize the game
+    pub const DEFAULT: CompatPalette = Self::RightA;
+
+    /// Colors of the palette
+    pub fn colors(self) -> DmgPalette {
+        combination_colors(match self {
+            Self::Up => 5,
+            Self::UpA => 43,
+            Self::UpB => 28,
+            Self::Left => 48,
+            Self::LeftA => 40,
+            Self::LeftB => 7,
+            Self::Down => 8,
+            Self::DownA => 3,
+            Self::DownB => 49,
+            Self::Right => 1,
+            Self::RightA => 0,
+            Self::RightB => 6,
+        })
+    }
+
+    /// Colors the boot ROM picks for a cartridge, given a function reading its ROM.
+    ///
+    /// Only games published by Nintendo are looked up, by the checksum of their title and, for
+    /// the checksums shared by several titles, its fourth letter. Other games get the colors
+    /// of [`CompatPalette::DEFAULT`].
+    pub fn cartridge_colors(read: impl Fn(u16) -> u8) -> DmgPalette {
+        let nintendo = match read(0x014B) {
+            0x33 => read(0x0144) == b'0' && read(0x0145) == b'1',
+            licensee => licensee == 0x01,
+        };
+        if !nintendo {
+            return Self::DEFAULT.colors();
+        }
+
+        let checksum = (0x0134..=0x0143).fold(0u8, |sum, a| sum.wrapping_add(read(a)));
+        let fourth_letter = read(0x0137);
+        let title = TITLE_CHECKSUMS
+            .iter()
+            .enumerate()
+            .position(|(i, c)| {
+                *c == checksum
+                    && (i < UNIQUE_CHECKSUMS
+                        || FOURTH_LETTERS[i - UNIQUE_CHECKSUMS] == fourth_letter)
+            })
+            .unwrap_or(0);
+        combination_colors(TITLE_COMBINATIONS[title] as usize)
+    }
+}
+
+/// Colors of an entry of `COMBINATIONS`
+fn combination_colors(index: usize) -> DmgPalette {
+    let palette = |offset: usize| -> [u32; 4] {
+        std::array::from_fn(|i| {
+            let color = COLORS[offset + i];
+            // spread the 5 bits of each component over 8 bits, rounding
+            let component = |shift: u16| (((color >> shift) & 0x1F) as u32 * 255 + 15) / 31;
+            (component(0) << 16) | (component(5) << 8) | component(10)
+        })
+    };
+    let [obj0, obj1, bg] = COMBINATIONS[index];
+    DmgPalette {
+        bg: palette(bg),
+        obj0: palette(obj0),
+        obj1: palette(obj1),
+    }
+}
+
+impl fmt::Display for CompatPalette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Up => "up",
+            Self::UpA => "up+a",
+            Self::UpB => "up+b",
+            Self::Left => "left",
+            Self::LeftA => "left+a",
+            Self::LeftB => "left+b",
+            Self::Down => "down",
+            Self::DownA => "down+a",
+            Self::DownB => "down+b",
+            Self::Right => "right",
+            Self::RightA => "right+a",
+            Self::RightB => "right+b",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for CompatPalette {
+    type Err = String;
+
+    /// Parse the button combo of a palette, e.g. `up+a`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|palette| palette.to_string() == s)
+            .ok_or_else(|| format!("Invalid compatibility palette: {s}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CompatPalette;
+    use crate::palette::DmgPalette;
+
+    fn header(title: &str, licensee: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x150];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title.as_bytes());
+        rom[0x14B] = licensee;
+        rom
+    }
+
+    fn colors(rom: &[u8]) -> DmgPalette {
+        CompatPalette::cartridge_colors(|a| rom[a as usize])
+    }
+
+    #[test]
+    fn palette_by_title() {
+        assert_eq!(
+            colors(&header("TETRIS", 0x01)),
+            CompatPalette::DownA.colors()
+        );
+
+        // red with green and red sprites, which no button combo gives
+        assert_eq!(
+            colors(&header("POKEMON RED", 0x01)),
+            DmgPalette {
+                bg: [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000],
+                obj0: [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000],
+                obj1: [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000],
+            }
+        );
+
+        let blue = DmgPalette {
+            bg: [0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000],
+            obj0: [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000],
+            obj1: [0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000],
+        };
+        assert_eq!(colors(&header("POKEMON BLUE", 0x01)), blue);
+        // same checksum as POKEMON BLUE, told apart by the fourth letter
+        assert_eq!(
+            colors(&header("VEGAS STAKES", 0x01)),
+            DmgPalette {
+                bg: [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000],
+                obj0: [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000],
+                obj1: [0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000],
+            }
+        );
+        // same checksum again, with a fourth letter the boot ROM doesn't know
+        assert_eq!(
+            colors(&header("POKMEON BLUE", 0x01)),
+            colors(&header("UNKNOWN", 0x01))
+        );
+        assert_eq!(
+            colors(&header("UNKNOWN", 0x01)),
+            CompatPalette::DEFAULT.colors()
+        );
+    }
+
+    #[test]
+    fn default_palette_for_other_publishers() {
+        assert_eq!(
+            colors(&header("TETRIS", 0x08)),
+            CompatPalette::DEFAULT.colors()
+        );
+
+        let mut rom = header("TETRIS", 0x33);
+        rom[0x144..0x146].copy_from_slice(b"01");
+        assert_eq!(colors(&rom), CompatPalette::DownA.colors());
+    }
+
+    #[test]
+    fn button_palettes_match_boot_rom_colors() {
+        assert_eq!(
+            CompatPalette::UpA.colors(),
+            DmgPalette {
+                bg: [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000],
+                obj0: [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000],
+                obj1: [0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000],
+            }
+        );
+        assert_eq!(
+            CompatPalette::RightB.colors(),
+            DmgPalette::uniform([0x000000, 0x008484, 0xFFDE00, 0xFFFFFF])
+        );
+    }
+
+    #[test]
+    fn parse_button_combo() {
+        for palette in CompatPalette::ALL {
+            assert_eq!(palette.to_string().parse(), Ok(palette));
+        }
+        assert_eq!("Down+B".parse(), Ok(CompatPalette::DownB));
+        assert!("up+start".parse::<CompatPalette>().is_err());
+    }
+}
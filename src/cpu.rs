@@ -1,10 +1,13 @@
 use serde::{Deserialize, Serialize};
 
+use crate::debugger::CpuState;
+use crate::disasm;
 use crate::mmu::Mmu;
 use crate::register::CpuFlag::{C, H, N, Z};
 use crate::register::Registers;
 use crate::serial::SerialCallback;
-use crate::{StrResult, mbc};
+use crate::trace::Tracer;
+use crate::{Result, mbc};
 
 #[derive(Serialize, Deserialize)]
 pub struct Cpu {
@@ -18,13 +21,18 @@ pub struct Cpu {
     ime: bool,
     setdi: u32,
     setei: u32,
+    /// An illegal opcode locked up the CPU until the next reset
+    #[serde(default)]
+    locked: bool,
+    #[serde(skip)]
+    pub(crate) tracer: Option<Tracer>,
 }
 
 impl Cpu {
     pub fn new(
         cart: Box<dyn mbc::Mbc + 'static>,
         serial_callback: Option<Box<dyn SerialCallback>>,
-    ) -> StrResult<Cpu> {
+    ) -> Result<Cpu> {
         let cpu_mmu = Mmu::new(cart, serial_callback)?;
         let registers = Registers::new(cpu_mmu.gbmode);
         Ok(Cpu {
@@ -34,6 +42,8 @@ impl Cpu {
             ime: true,
             setdi: 0,
             setei: 0,
+            locked: false,
+            tracer: None,
             mmu: cpu_mmu,
         })
     }
@@ -41,7 +51,7 @@ impl Cpu {
     pub fn new_cgb(
         cart: Box<dyn mbc::Mbc + 'static>,
         serial_callback: Option<Box<dyn SerialCallback>>,
-    ) -> StrResult<Cpu> {
+    ) -> Result<Cpu> {
         let cpu_mmu = Mmu::new_cgb(cart, serial_callback)?;
         let registers = Registers::new(cpu_mmu.gbmode);
         Ok(Cpu {
@@ -51,16 +61,50 @@ impl Cpu {
             ime: true,
             setdi: 0,
             setei: 0,
+            locked: false,
+            tracer: None,
             mmu: cpu_mmu,
         })
     }
 
+    /// Map a boot ROM and start executing it instead of the cartridge
+    pub fn set_boot_rom(&mut self, data: Vec<u8>) -> Result<()> {
+        self.mmu.set_boot_rom(data)?;
+        self.reg = Registers::new_boot();
+        Ok(())
+    }
+
+    /// Registers and execution state, for debugging
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            a: self.reg.a,
+            f: (self.reg.af() & 0xFF) as u8,
+            b: self.reg.b,
+            c: self.reg.c,
+            d: self.reg.d,
+            e: self.reg.e,
+            h: self.reg.h,
+            l: self.reg.l,
+            sp: self.reg.sp,
+            pc: self.reg.pc,
+            ime: self.ime,
+            halted: self.halted,
+            locked: self.locked,
+            rom_bank: self.mmu.mbc.rom_bank(),
+            wram_bank: self.mmu.wram_bank(),
+            vram_bank: self.mmu.gpu.vram_bank(),
+        }
+    }
+
     pub fn do_cycle(&mut self) -> u32 {
         let ticks = self.docycle() * 4;
         self.mmu.do_cycle(ticks)
     }
 
     fn docycle(&mut self) -> u32 {
+        if self.locked {
+            return 1;
+        }
         self.updateime();
         match self.handleinterrupt() {
             0 => {}
@@ -71,10 +115,22 @@ impl Cpu {
             // Emulate a noop instruction
             1
         } else {
+            // skip reading the state once the trace is complete
+            if self.tracer.as_ref().is_some_and(|t| !t.is_done()) {
+                self.trace();
+            }
             self.call()
         }
     }
 
+    fn trace(&mut self) {
+        let state = self.state();
+        let pcmem = [0, 1, 2, 3].map(|i| self.mmu.read(state.pc.wrapping_add(i)));
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace(&state, pcmem);
+        }
+    }
+
     fn fetchbyte(&mut self) -> u8 {
         let b = self.mmu.rb(self.reg.pc);
         if self.halt_bug {
@@ -1246,8 +1302,41 @@ impl Cpu {
                 self.reg.pc = 0x38;
                 4
             }
-            other => panic!("Instruction {:2X} is not implemented", other),
+            other => self.lock_up(other),
+        }
+    }
+
+    /// Illegal opcodes lock up the CPU, which then only runs again after a reset
+    fn lock_up(&mut self, opcode: u8) -> u32 {
+        let pc = self.reg.pc.wrapping_sub(1);
+        error!(
+            "Illegal opcode {opcode:02X} at {pc:04X} locked up the CPU\n{}",
+            self.crash_report(pc)
+        );
+        self.locked = true;
+        1
+    }
+
+    /// Registers, the code following `pc` and the top of the stack
+    fn crash_report(&mut self, pc: u16) -> String {
+        let mut report = self.state().to_string();
+        report += "\ncode:";
+        let mut address = pc;
+        for _ in 0..5 {
+            let instruction = disasm::decode(address, |a| self.mmu.read(a));
+            address = instruction.next_address();
+            report += &format!("\n  {instruction}");
         }
+        report += "\nstack:";
+        for i in 0..4 {
+            let address = self.reg.sp.wrapping_add(i * 2);
+            let value = u16::from_le_bytes([
+                self.mmu.read(address),
+                self.mmu.read(address.wrapping_add(1)),
+            ]);
+            report += &format!(" {value:04X}");
+        }
+        report
     }
 
     fn call_cb(&mut self) -> u32 {
@@ -2556,7 +2645,7 @@ mod test {
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
     use super::Cpu;
-    use crate::mbc;
+    use crate::{disasm, mbc};
 
     const CPUINSTRS: &'static str = "roms/cpu_instrs.gb";
     const CPU_SERIAL: &'static [u8] = b"cpu_instrs\n\n01:ok  02:ok  03:ok  04:ok  05:ok  06:ok  07:ok  08:ok  09:ok  10:ok  11:ok  \n\nPassed all tests\n";
@@ -2596,6 +2685,62 @@ mod test {
         }
     }
 
+    fn test_cpu() -> Cpu {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x00;
+        let cart = mbc::get_mbc(rom, true).unwrap();
+        Cpu::new(cart, None).unwrap()
+    }
+
+    #[test]
+    fn disassembler_matches_instructions() {
+        for prefixed in [false, true] {
+            for opcode in 0..=0xFFu8 {
+                let mut c = test_cpu();
+                let code = match prefixed {
+                    true => [0xCB, opcode, 0x00],
+                    false => [opcode, 0x10, 0xC0],
+                };
+                for (i, &byte) in code.iter().enumerate() {
+                    c.mmu.wb(0xC000 + i as u16, byte);
+                }
+                c.reg.pc = 0xC000;
+                c.reg.sp = 0xD000;
+
+                let instruction = disasm::decode(0xC000, |a| c.mmu.read(a));
+                let cycles = c.call() as u8;
+                if instruction.is_illegal() {
+                    assert!(c.locked, "{instruction}");
+                    continue;
+                }
+                match instruction.cycles_not_taken {
+                    Some(not_taken) if cycles == not_taken => {}
+                    _ => assert_eq!(cycles, instruction.cycles, "{instruction}"),
+                }
+                let jumps = matches!(
+                    instruction.mnemonic,
+                    "JR" | "JP" | "CALL" | "RET" | "RETI" | "RST"
+                );
+                if !jumps || cycles == instruction.cycles_not_taken.unwrap_or(0) {
+                    assert_eq!(c.reg.pc, instruction.next_address(), "{instruction}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn illegal_opcode_locks_up() {
+        let mut c = test_cpu();
+        c.mmu.wb(0xC000, 0xDD);
+        c.reg.pc = 0xC000;
+        c.do_cycle();
+        assert!(c.state().locked);
+        c.mmu.intf = 0x01;
+        c.mmu.inte = 0x01;
+        c.do_cycle();
+        assert_eq!(c.reg.pc, 0xC001);
+    }
+
     #[test]
     fn cpu_instrs_classic() {
         let mut sum_classic = 0_u32;
@@ -0,0 +1,447 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rboy::debugger::{DebugStop, Watchpoint};
+use rboy::device::Device;
+
+/// Largest memory dump printed by the `mem` command
+const MAX_DUMP: u32 = 0x1000;
+/// Most instructions printed by the `dis` command
+const MAX_INSTRUCTIONS: u16 = 0x100;
+
+const HELP: &str = "\
+break <addr>              stop before the instruction at addr
+delete <addr>             remove a breakpoint
+watch <addr>[-<end>] [r|w|rw]
+                          stop after an access to the memory (default: w)
+unwatch <n>               remove the watchpoint n of the list
+list                      list the breakpoints and watchpoints
+step, s                   run one instruction
+next, n                   run one instruction, or a whole CALL or RST
+continue, c               run until a breakpoint or watchpoint
+pause                     stop running
+regs, r                   show the registers
+mem <addr> [len]          dump memory (default: 16 bytes)
+dis [addr] [count]        disassemble (default: 8 instructions at PC)
+write <addr> <byte>       write memory
+help                      show this help
+Addresses and values are hexadecimal.";
+
+/// Where the debugger reads commands from and writes its output to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugEndpoint {
+    /// Standard input and output
+    Stdin,
+    /// Unix socket, serving one client at a time
+    Socket(PathBuf),
+}
+
+impl FromStr for DebugEndpoint {
+    type Err = &'static str;
+
+    fn from_str(input: &str) -> Result<DebugEndpoint, Self::Err> {
+        match input {
+            "" => Err("empty debugger endpoint"),
+            "stdin" | "-" => Ok(DebugEndpoint::Stdin),
+            path => Ok(DebugEndpoint::Socket(PathBuf::from(path))),
+        }
+    }
+}
+
+/// Command sent to the debugger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Break(u16),
+    Delete(u16),
+    Watch(Watchpoint),
+    Unwatch(usize),
+    List,
+    Step,
+    Next,
+    Continue,
+    Pause,
+    Registers,
+    Memory { address: u16, len: u32 },
+    Disassemble { address: Option<u16>, count: u16 },
+    Write { address: u16, value: u8 },
+    Help,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Command, Self::Err> {
+        let mut words = input.split_whitespace();
+        let name = words.next().ok_or("empty command")?;
+        let args: Vec<&str> = words.collect();
+        let arg = |i: usize| -> Result<&str, String> {
+            args.get(i)
+                .copied()
+                .ok_or_else(|| format!("missing argument for {name}"))
+        };
+
+        let command = match name {
+            "break" | "b" => Command::Break(parse_hex(arg(0)?)?),
+            "delete" | "d" => Command::Delete(parse_hex(arg(0)?)?),
+            "watch" | "w" => {
+                let (start, end) = match arg(0)?.split_once('-') {
+                    Some((start, end)) => (parse_hex(start)?, parse_hex(end)?),
+                    None => {
+                        let address = parse_hex(arg(0)?)?;
+                        (address, address)
+                    }
+                };
+                if end < start {
+                    return Err(format!("invalid range {}", arg(0)?));
+                }
+                let (on_read, on_write) = match args.get(1).copied().unwrap_or("w") {
+                    "r" => (true, false),
+                    "w" => (false, true),
+                    "rw" => (true, true),
+                    access => return Err(format!("invalid access {access}")),
+                };
+                Command::Watch(Watchpoint {
+                    start,
+                    end,
+                    on_read,
+                    on_write,
+                })
+            }
+            "unwatch" => Command::Unwatch(
+                arg(0)?
+                    .parse()
+                    .map_err(|_| format!("invalid index {}", args[0]))?,
+            ),
+            "list" | "l" => Command::List,
+            "step" | "s" => Command::Step,
+            "next" | "n" => Command::Next,
+            "continue" | "c" => Command::Continue,
+            "pause" | "p" => Command::Pause,
+            "regs" | "r" => Command::Registers,
+            "mem" | "m" => {
+                let len = match args.get(1) {
+                    Some(len) => u32::from(parse_hex(len)?).clamp(1, MAX_DUMP),
+                    None => 16,
+                };
+                Command::Memory {
+                    address: parse_hex(arg(0)?)?,
+                    len,
+                }
+            }
+            "dis" | "x" => Command::Disassemble {
+                address: args.first().map(|a| parse_hex(a)).transpose()?,
+                count: match args.get(1) {
+                    Some(count) => parse_hex(count)?.clamp(1, MAX_INSTRUCTIONS),
+                    None => 8,
+                },
+            },
+            "write" => {
+                let value = parse_hex(arg(1)?)?;
+                Command::Write {
+                    address: parse_hex(arg(0)?)?,
+                    value: u8::try_from(value).map_err(|_| format!("invalid byte {}", args[1]))?,
+                }
+            }
+            "help" | "h" | "?" => Command::Help,
+            _ => return Err(format!("unknown command {name}, try help")),
+        };
+        Ok(command)
+    }
+}
+
+/// Parse a hexadecimal value, with an optional `0x` or `$` prefix
+fn parse_hex(input: &str) -> Result<u16, String> {
+    let digits = input
+        .strip_prefix("0x")
+        .or_else(|| input.strip_prefix('$'))
+        .unwrap_or(input);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hexadecimal value {input}"))
+}
+
+/// Text interface to the debugger of the [`Device`], reading commands on a background thread.
+///
+/// The emulation starts paused so breakpoints can be set before the game runs.
+pub struct DebugServer {
+    commands: Receiver<Command>,
+    output: Arc<Mutex<Option<Box<dyn Write + Send>>>>,
+}
+
+impl DebugServer {
+    pub fn start(endpoint: &DebugEndpoint) -> anyhow::Result<DebugServer> {
+        let (sender, commands) = mpsc::channel();
+        let output: Arc<Mutex<Option<Box<dyn Write + Send>>>> = Arc::new(Mutex::new(None));
+
+        match endpoint {
+            DebugEndpoint::Stdin => {
+                *output.lock().unwrap() = Some(Box::new(std::io::stdout()));
+                let output = output.clone();
+                thread::spawn(move || read_commands(std::io::stdin().lock(), &sender, &output));
+                info!("Debugger listening on stdin");
+            }
+            DebugEndpoint::Socket(path) => {
+                // remove the socket left by a previous run
+                if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    let _ = std::fs::remove_file(path);
+                }
+                let listener = UnixListener::bind(path).map_err(|e| {
+                    anyhow::anyhow!("Failed to bind debugger socket {}: {e}", path.display())
+                })?;
+                let output = output.clone();
+                thread::spawn(move || {
+                    for stream in listener.incoming() {
+                        let stream = match stream {
+                            Ok(stream) => stream,
+                            Err(e) => {
+                                error!("Debugger connection failed: {e}");
+                                continue;
+                            }
+                        };
+                        match stream.try_clone() {
+                            Ok(writer) => *output.lock().unwrap() = Some(Box::new(writer)),
+                            Err(e) => {
+                                error!("Debugger connection failed: {e}");
+                                continue;
+                            }
+                        }
+                        info!("Debugger client connected");
+                        if !read_commands(BufReader::new(stream), &sender, &output) {
+                            break;
+                        }
+                        *output.lock().unwrap() = None;
+                        info!("Debugger client disconnected");
+                    }
+                });
+                info!("Debugger listening on {}", path.display());
+            }
+        }
+
+        Ok(DebugServer { commands, output })
+    }
+
+    fn reply(&self, text: &str) {
+        write_output(&self.output, text);
+    }
+
+    /// Tell that the emulation stopped, with the registers and the next instruction
+    fn stopped(&self, cpu: &mut Device, stop: DebugStop) {
+        let state = cpu.cpu_state();
+        let instruction = &cpu.disassemble(state.pc..=state.pc)[0];
+        self.reply(&format!("{stop}\n{state}\n{instruction}"));
+    }
+
+    /// Run one instruction, pausing when the debugger stops
+    pub fn cycle(&self, cpu: &mut Device, paused: &mut bool) -> u32 {
+        let (ticks, stop) = cpu.debug_cycle();
+        if let Some(stop) = stop {
+            *paused = true;
+            self.stopped(cpu, stop);
+        }
+        ticks
+    }
+
+    /// Execute the commands received since the last call
+    pub fn poll(&self, cpu: &mut Device, paused: &mut bool) {
+        while let Ok(command) = self.commands.try_recv() {
+            self.execute(cpu, command, paused);
+        }
+    }
+
+    fn execute(&self, cpu: &mut Device, command: Command, paused: &mut bool) {
+        match command {
+            Command::Break(pc) => {
+                cpu.add_breakpoint(pc);
+                self.reply(&format!("breakpoint at {pc:04X}"));
+            }
+            Command::Delete(pc) => match cpu.remove_breakpoint(pc) {
+                true => self.reply(&format!("deleted breakpoint at {pc:04X}")),
+                false => self.reply(&format!("no breakpoint at {pc:04X}")),
+            },
+            Command::Watch(watchpoint) => {
+                cpu.add_watchpoint(watchpoint);
+                self.reply(&format!("watchpoint {watchpoint}"));
+            }
+            Command::Unwatch(index) => match cpu.remove_watchpoint(index) {
+                Some(watchpoint) => self.reply(&format!("deleted watchpoint {watchpoint}")),
+                None => self.reply(&format!("no watchpoint {index}")),
+            },
+            Command::List => {
+                let mut text = String::new();
+                for pc in cpu.breakpoints() {
+                    text += &format!("break {pc:04X}\n");
+                }
+                for (i, watchpoint) in cpu.watchpoints().iter().enumerate() {
+                    text += &format!("watch {i}: {watchpoint}\n");
+                }
+                if text.is_empty() {
+                    text += "no breakpoints or watchpoints";
+                }
+                self.reply(text.trim_end());
+            }
+            Command::Step if *paused => {
+                let (_, stop) = cpu.step();
+                self.stopped(cpu, stop);
+            }
+            Command::Next if *paused => {
+                if let (_, Some(stop)) = cpu.step_over() {
+                    self.stopped(cpu, stop);
+                } else {
+                    // run until the call returns
+                    *paused = false;
+                    cpu.sync_audio();
+                }
+            }
+            Command::Step | Command::Next => self.reply("running, pause first"),
+            Command::Continue => {
+                if *paused {
+                    *paused = false;
+                    cpu.sync_audio();
+                }
+                self.reply("running");
+            }
+            Command::Pause => {
+                *paused = true;
+                self.stopped(cpu, DebugStop::Step);
+            }
+            Command::Registers => self.reply(&cpu.cpu_state().to_string()),
+            Command::Memory { address, len } => {
+                let mut text = String::new();
+                for row in (0..len).step_by(16) {
+                    let start = address.wrapping_add(row as u16);
+                    text += &format!("{start:04X}:");
+                    for i in row..(row + 16).min(len) {
+                        let byte = cpu.peek_byte(address.wrapping_add(i as u16));
+                        text += &format!(" {byte:02X}");
+                    }
+                    text += "\n";
+                }
+                self.reply(text.trim_end());
+            }
+            Command::Disassemble { address, count } => {
+                let mut address = address.unwrap_or(cpu.cpu_state().pc);
+                let mut text = String::new();
+                for _ in 0..count {
+                    let instruction = &cpu.disassemble(address..=address)[0];
+                    text += &format!("{instruction}\n");
+                    address = instruction.next_address();
+                }
+                self.reply(text.trim_end());
+            }
+            Command::Write { address, value } => {
+                cpu.poke_byte(address, value);
+                self.reply(&format!("{address:04X} = {:02X}", cpu.peek_byte(address)));
+            }
+            Command::Help => self.reply(HELP),
+        }
+    }
+}
+
+fn write_output(output: &Mutex<Option<Box<dyn Write + Send>>>, text: &str) {
+    let mut output = output.lock().unwrap();
+    if let Some(writer) = output.as_mut()
+        && writeln!(writer, "{text}")
+            .and_then(|_| writer.flush())
+            .is_err()
+    {
+        *output = None;
+    }
+}
+
+/// Forward the commands read line by line until the end of the input.
+///
+/// Returns false once the emulator stopped listening.
+fn read_commands(
+    input: impl BufRead,
+    sender: &Sender<Command>,
+    output: &Mutex<Option<Box<dyn Write + Send>>>,
+) -> bool {
+    for line in input.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse() {
+            Ok(command) => {
+                if sender.send(command).is_err() {
+                    return false;
+                }
+            }
+            Err(e) => write_output(output, &e),
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_parse_commands() {
+        assert_eq!("break 0150".parse(), Ok(Command::Break(0x150)));
+        assert_eq!("b $C000".parse(), Ok(Command::Break(0xC000)));
+        assert_eq!(
+            "watch 0xC000-C0FF rw".parse(),
+            Ok(Command::Watch(Watchpoint {
+                start: 0xC000,
+                end: 0xC0FF,
+                on_read: true,
+                on_write: true,
+            }))
+        );
+        assert_eq!(
+            "watch FF40".parse(),
+            Ok(Command::Watch(Watchpoint::write(0xFF40)))
+        );
+        assert_eq!(
+            "mem D000 20".parse(),
+            Ok(Command::Memory {
+                address: 0xD000,
+                len: 0x20
+            })
+        );
+        assert_eq!(
+            "write C000 42".parse(),
+            Ok(Command::Write {
+                address: 0xC000,
+                value: 0x42
+            })
+        );
+        assert_eq!(
+            "dis 150 4".parse(),
+            Ok(Command::Disassemble {
+                address: Some(0x150),
+                count: 4
+            })
+        );
+        assert_eq!(
+            "x".parse(),
+            Ok(Command::Disassemble {
+                address: None,
+                count: 8
+            })
+        );
+        assert_eq!(" n ".parse(), Ok(Command::Next));
+        assert!("write C000 100".parse::<Command>().is_err());
+        assert!("watch C0FF-C000".parse::<Command>().is_err());
+        assert!("break".parse::<Command>().is_err());
+        assert!("jump 100".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn test_should_parse_endpoint() {
+        assert_eq!("stdin".parse(), Ok(DebugEndpoint::Stdin));
+        assert_eq!(
+            "/tmp/rboy.sock".parse(),
+            Ok(DebugEndpoint::Socket(PathBuf::from("/tmp/rboy.sock")))
+        );
+        assert!("".parse::<DebugEndpoint>().is_err());
+    }
+}
@@ -0,0 +1,232 @@
+//! Types used to debug a running game through the [`Device`](crate::device::Device):
+//! breakpoints, watchpoints and the state of the CPU.
+
+use std::fmt;
+
+/// Kind of memory access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Write => write!(f, "write"),
+        }
+    }
+}
+
+/// Range of memory watched for reads, writes or both
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// First watched address
+    pub start: u16,
+    /// Last watched address, included
+    pub end: u16,
+    pub on_read: bool,
+    pub on_write: bool,
+}
+
+impl Watchpoint {
+    /// Watch the writes to a single address
+    pub fn write(address: u16) -> Watchpoint {
+        Watchpoint {
+            start: address,
+            end: address,
+            on_read: false,
+            on_write: true,
+        }
+    }
+
+    /// Watch the reads of a single address
+    pub fn read(address: u16) -> Watchpoint {
+        Watchpoint {
+            start: address,
+            end: address,
+            on_read: true,
+            on_write: false,
+        }
+    }
+
+    fn matches(&self, address: u16, access: Access) -> bool {
+        (self.start..=self.end).contains(&address)
+            && match access {
+                Access::Read => self.on_read,
+                Access::Write => self.on_write,
+            }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match (self.on_read, self.on_write) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            (false, true) => "w",
+            (false, false) => "-",
+        };
+        if self.start == self.end {
+            write!(f, "{:04X} {kind}", self.start)
+        } else {
+            write!(f, "{:04X}-{:04X} {kind}", self.start, self.end)
+        }
+    }
+}
+
+/// Access which matched a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub address: u16,
+    /// Value read or written
+    pub value: u8,
+    pub access: Access,
+    /// Address of the instruction which made the access
+    pub pc: u16,
+}
+
+/// Why the execution stopped while debugging
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugStop {
+    /// The instruction, or the call stepped over, completed
+    Step,
+    /// The next instruction is at a breakpoint
+    Breakpoint(u16),
+    /// A watched address was accessed
+    Watchpoint(WatchHit),
+    /// The CPU locked up on the illegal opcode at this address
+    Locked(u16),
+}
+
+impl fmt::Display for DebugStop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Step => write!(f, "step"),
+            Self::Breakpoint(pc) => write!(f, "breakpoint at {pc:04X}"),
+            Self::Watchpoint(hit) => write!(
+                f,
+                "watchpoint: {} of {:02X} at {:04X} by {:04X}",
+                hit.access, hit.value, hit.address, hit.pc
+            ),
+            Self::Locked(pc) => write!(f, "illegal opcode at {pc:04X}, the CPU locked up"),
+        }
+    }
+}
+
+/// Registers and execution state of the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuState {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    /// Interrupt master enable
+    pub ime: bool,
+    /// Waiting for an interrupt after HALT
+    pub halted: bool,
+    /// Locked up by an illegal opcode
+    pub locked: bool,
+    /// ROM bank mapped at 0x4000-0x7FFF
+    pub rom_bank: usize,
+    /// Work RAM bank mapped at 0xD000-0xDFFF
+    pub wram_bank: usize,
+    /// Video RAM bank mapped at 0x8000-0x9FFF
+    pub vram_bank: usize,
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |mask: u8, name: char| if self.f & mask != 0 { name } else { '-' };
+        writeln!(
+            f,
+            "AF={:02X}{:02X} BC={:02X}{:02X} DE={:02X}{:02X} HL={:02X}{:02X} SP={:04X} PC={:04X}",
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l, self.sp, self.pc
+        )?;
+        write!(
+            f,
+            "flags={}{}{}{} IME={} halted={} ROM={:02X} WRAM={} VRAM={}",
+            flag(0x80, 'Z'),
+            flag(0x40, 'N'),
+            flag(0x20, 'H'),
+            flag(0x10, 'C'),
+            self.ime as u8,
+            self.halted as u8,
+            self.rom_bank,
+            self.wram_bank,
+            self.vram_bank
+        )?;
+        if self.locked {
+            write!(f, " LOCKED")?;
+        }
+        Ok(())
+    }
+}
+
+/// Watchpoints checked by the MMU on every access, remembering the first one hit
+#[derive(Default)]
+pub(crate) struct Watchpoints {
+    points: Vec<Watchpoint>,
+    hit: Option<(u16, u8, Access)>,
+}
+
+impl Watchpoints {
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.points
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.points.push(watchpoint);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.points.len()).then(|| self.points.remove(index))
+    }
+
+    pub fn check(&mut self, address: u16, value: u8, access: Access) {
+        if self.hit.is_none() && self.points.iter().any(|w| w.matches(address, access)) {
+            self.hit = Some((address, value, access));
+        }
+    }
+
+    /// Take the access which hit a watchpoint since the last call
+    pub fn take_hit(&mut self) -> Option<(u16, u8, Access)> {
+        self.hit.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Access, Watchpoint, Watchpoints};
+
+    #[test]
+    fn first_matching_access_is_kept() {
+        let mut watchpoints = Watchpoints::default();
+        watchpoints.add(Watchpoint {
+            start: 0xC000,
+            end: 0xC0FF,
+            on_read: false,
+            on_write: true,
+        });
+
+        watchpoints.check(0xC010, 0x01, Access::Read);
+        watchpoints.check(0xC100, 0x02, Access::Write);
+        assert_eq!(watchpoints.take_hit(), None);
+
+        watchpoints.check(0xC010, 0x03, Access::Write);
+        watchpoints.check(0xC020, 0x04, Access::Write);
+        assert_eq!(watchpoints.take_hit(), Some((0xC010, 0x03, Access::Write)));
+        assert_eq!(watchpoints.take_hit(), None);
+    }
+}
@@ -1,27 +1,48 @@
-use std::path::Path;
+mod rewind;
+mod save_state;
+
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
+use self::rewind::RewindBuffer;
+pub use self::rewind::RewindConfig;
+pub use self::save_state::{SAVE_STATE_MAGIC, SAVE_STATE_VERSION, SaveStateHeader};
+use crate::compat_palette::CompatPalette;
 use crate::cpu::Cpu;
+use crate::debugger::{CpuState, DebugStop, WatchHit, Watchpoint};
+use crate::disasm::{self, Instruction};
 use crate::gbmode::GbMode;
+use crate::gpu::{Renderer, SCREEN_H, SCREEN_W, VramImage, VramView};
+use crate::header::CartridgeHeader;
 use crate::keypad::KeypadKey;
+use crate::mbc::{CameraSource, RtcClock, RumbleCallback};
+use crate::palette::DmgPalette;
 use crate::printer::GbPrinter;
+use crate::recorder::Recorder;
 use crate::serial::SerialCallback;
-use crate::{StrResult, mbc, serial, sound};
+use crate::trace::{TraceConfig, Tracer};
+use crate::{Error, Result, mbc, png, serial, sound};
 
 #[derive(Serialize, Deserialize)]
 pub struct Device {
     cpu: Cpu,
-    save_state: Option<String>,
-}
-
-impl Drop for Device {
-    fn drop(&mut self) {
-        if let Some(path) = &self.save_state {
-            let file = std::fs::File::create(path).unwrap();
-            ciborium::into_writer(&self.cpu, file).unwrap();
-        }
-    }
+    /// Path of the ROM the device was created from; save state slots are stored next to it
+    rom_path: Option<PathBuf>,
+    #[serde(skip)]
+    rewind: Option<RewindBuffer>,
+    /// Number of battery save backups, re-applied to restored states
+    #[serde(skip)]
+    save_backups: usize,
+    #[serde(skip)]
+    breakpoints: BTreeSet<u16>,
+    /// Return address and stack pointer of the call being stepped over
+    #[serde(skip)]
+    step_over: Option<(u16, u16)>,
+    #[serde(skip)]
+    recorder: Option<Recorder>,
 }
 
 pub struct StdoutPrinter;
@@ -38,53 +59,249 @@ impl SerialCallback for StdoutPrinter {
 }
 
 impl Device {
-    pub fn load_state(path: &str) -> Option<Box<Device>> {
-        let file = std::fs::File::open(path).ok()?;
-        let cpu = ciborium::de::from_reader(file).ok()?;
-        Some(Box::new(Device {
+    pub fn new(romname: &Path, skip_checksum: bool) -> Result<Device> {
+        let cart = mbc::FileBackedMBC::new(romname.to_path_buf(), skip_checksum)?;
+        Cpu::new(Box::new(cart), None).map(|cpu| Device {
             cpu,
-            save_state: Some(path.to_string()),
-        }))
+            rom_path: Some(romname.to_path_buf()),
+            rewind: None,
+            save_backups: 0,
+            breakpoints: BTreeSet::new(),
+            step_over: None,
+            recorder: None,
+        })
     }
 
-    pub fn new(
-        romname: &Path,
-        skip_checksum: bool,
-        save_state: Option<String>,
-    ) -> StrResult<Device> {
+    pub fn new_cgb(romname: &Path, skip_checksum: bool) -> Result<Device> {
         let cart = mbc::FileBackedMBC::new(romname.to_path_buf(), skip_checksum)?;
-        Cpu::new(Box::new(cart), None).map(|cpu| Device { cpu, save_state })
+        Cpu::new_cgb(Box::new(cart), None).map(|cpu| Device {
+            cpu,
+            rom_path: Some(romname.to_path_buf()),
+            rewind: None,
+            save_backups: 0,
+            breakpoints: BTreeSet::new(),
+            step_over: None,
+            recorder: None,
+        })
     }
 
-    pub fn new_cgb(
-        romname: &Path,
-        skip_checksum: bool,
-        save_state: Option<String>,
-    ) -> StrResult<Device> {
-        let cart = mbc::FileBackedMBC::new(romname.to_path_buf(), skip_checksum)?;
-        Cpu::new_cgb(Box::new(cart), None).map(|cpu| Device { cpu, save_state })
+    pub fn new_from_buffer(romdata: Vec<u8>, skip_checksum: bool) -> Result<Device> {
+        let cart = mbc::get_mbc(romdata, skip_checksum)?;
+        Cpu::new(cart, None).map(|cpu| Device {
+            cpu,
+            rom_path: None,
+            rewind: None,
+            save_backups: 0,
+            breakpoints: BTreeSet::new(),
+            step_over: None,
+            recorder: None,
+        })
     }
 
-    pub fn new_from_buffer(
-        romdata: Vec<u8>,
-        skip_checksum: bool,
-        save_state: Option<String>,
-    ) -> StrResult<Device> {
+    pub fn new_cgb_from_buffer(romdata: Vec<u8>, skip_checksum: bool) -> Result<Device> {
         let cart = mbc::get_mbc(romdata, skip_checksum)?;
-        Cpu::new(cart, None).map(|cpu| Device { cpu, save_state })
+        Cpu::new_cgb(cart, None).map(|cpu| Device {
+            cpu,
+            rom_path: None,
+            rewind: None,
+            save_backups: 0,
+            breakpoints: BTreeSet::new(),
+            step_over: None,
+            recorder: None,
+        })
     }
 
-    pub fn new_cgb_from_buffer(
-        romdata: Vec<u8>,
-        skip_checksum: bool,
-        save_state: Option<String>,
-    ) -> StrResult<Device> {
-        let cart = mbc::get_mbc(romdata, skip_checksum)?;
-        Cpu::new_cgb(cart, None).map(|cpu| Device { cpu, save_state })
+    /// Path of the save state file for the given slot, next to the ROM file.
+    ///
+    /// For `game.gb`, slot 1 is stored in `game.ss1`.
+    pub fn state_slot_path(&self, slot: u8) -> Result<PathBuf> {
+        self.rom_path
+            .as_ref()
+            .map(|rom| rom.with_extension(format!("ss{slot}")))
+            .ok_or(Error::NoRomPath)
+    }
+
+    /// Read the header of the save state file at `path`
+    pub fn read_state_header(path: &Path) -> Result<SaveStateHeader> {
+        save_state::read_header(path)
+    }
+
+    /// Header describing a save state of the current device
+    fn state_header(&self) -> SaveStateHeader {
+        let header = self.cartridge_header();
+        SaveStateHeader::new(header.title, header.global_checksum, self.cpu.mmu.gbmode)
+    }
+
+    /// Save the emulator state into the given slot.
+    ///
+    /// Returns the path of the written state file.
+    pub fn save_state_to(&mut self, slot: u8) -> Result<PathBuf> {
+        let path = self.state_slot_path(slot)?;
+        self.save_state_to_path(&path)?;
+        Ok(path)
+    }
+
+    /// Load the emulator state from the given slot.
+    pub fn load_state_from(&mut self, slot: u8) -> Result<()> {
+        let path = self.state_slot_path(slot)?;
+        self.load_state_from_path(&path)
+    }
+
+    /// Save the emulator state into the file at `path`
+    pub fn save_state_to_path(&mut self, path: &Path) -> Result<()> {
+        let header = self.state_header();
+        save_state::write(path, &header, &mut self.cpu)
+    }
+
+    /// Load the emulator state from the file at `path`.
+    ///
+    /// The state must have been created with the same ROM and mode as this device.
+    /// Audio output and the serial callback are not part of the state, so the ones
+    /// currently attached to the device are kept.
+    pub fn load_state_from_path(&mut self, path: &Path) -> Result<()> {
+        let cpu = save_state::read(path, &self.state_header())?;
+        self.replace_cpu(cpu);
+
+        Ok(())
+    }
+
+    /// Swap in a restored [`Cpu`], moving over the parts which are not serialized
+    fn replace_cpu(&mut self, mut cpu: Cpu) {
+        // the restored RAM supersedes any change not written yet
+        self.cpu.mmu.mbc.discard_unflushed();
+        cpu.mmu.mbc.set_save_backups(self.save_backups);
+        if self.cpu.mmu.mbc.save_backed_up() {
+            cpu.mmu.mbc.set_save_backed_up();
+        }
+        // the save may have moved since the state was recorded
+        if let Some(path) = self.cpu.mmu.mbc.save_path() {
+            cpu.mmu.mbc.restore_save_path(path);
+        }
+        // rewind snapshots are recorded without the ROM
+        cpu.mmu.mbc.set_rom(self.cpu.mmu.mbc.take_rom());
+        cpu.mmu.gpu.dmg_palette = self.cpu.mmu.gpu.dmg_palette;
+        cpu.mmu.compat_colors = self.cpu.mmu.compat_colors;
+        cpu.mmu.gpu.set_renderer(self.cpu.mmu.gpu.renderer());
+        if let Some(source) = self.cpu.mmu.mbc.take_camera_source() {
+            cpu.mmu.mbc.set_camera_source(source);
+        }
+        if let Some(callback) = self.cpu.mmu.mbc.take_rumble_callback() {
+            cpu.mmu.mbc.set_rumble_callback(callback);
+        }
+        if let Some(clock) = self.cpu.mmu.mbc.take_rtc_clock() {
+            cpu.mmu.mbc.set_rtc_clock(clock);
+        }
+        cpu.mmu.watchpoints = std::mem::take(&mut self.cpu.mmu.watchpoints);
+        cpu.tracer = self.cpu.tracer.take();
+        cpu.mmu.stub_ly = self.cpu.mmu.stub_ly;
+        self.step_over = None;
+        cpu.mmu.sound = self.cpu.mmu.sound.take();
+        if let Some(callback) = self.cpu.mmu.serial.take_callback() {
+            cpu.mmu.serial.set_callback(callback);
+        }
+        self.cpu = cpu;
+        self.sync_audio();
+    }
+
+    /// Start recording snapshots into a rewind buffer, dropping any previous one
+    pub fn enable_rewind(&mut self, config: RewindConfig) {
+        self.rewind = Some(RewindBuffer::new(config, self.cpu.mmu.gpu.frame_counter));
+    }
+
+    /// Stop recording snapshots and free the rewind buffer
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    /// Number of snapshots currently held by the rewind buffer
+    pub fn rewind_len(&self) -> usize {
+        self.rewind.as_ref().map(|r| r.len()).unwrap_or_default()
+    }
+
+    /// Step back by (at least) `frames` frames, rounded to the snapshot interval.
+    ///
+    /// Returns `false` if rewind is disabled or no snapshot was recorded yet.
+    pub fn rewind(&mut self, frames: u32) -> Result<bool> {
+        let Some(rewind) = self.rewind.as_mut() else {
+            return Ok(false);
+        };
+        let count = frames.div_ceil(rewind.interval_frames()) as usize;
+        match rewind.pop(count)? {
+            Some(cpu) => {
+                self.replace_cpu(cpu);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
     }
 
     pub fn do_cycle(&mut self) -> u32 {
-        self.cpu.do_cycle()
+        let ticks = self.cpu.do_cycle();
+        if let Some(rewind) = self.rewind.as_mut()
+            && let Err(e) = rewind.on_cycle(&mut self.cpu)
+        {
+            warn!("Could not record rewind snapshot: {e}");
+        }
+        self.record(ticks);
+        ticks
+    }
+
+    /// Start recording the screen and the sound into an uncompressed AVI file at `path`,
+    /// completing any previous recording.
+    ///
+    /// The sound is only recorded if audio is enabled. Recording stops by itself once the
+    /// file reaches 1 GiB.
+    pub fn start_recording(&mut self, path: &Path) -> Result<()> {
+        self.stop_recording()?;
+        let sample_rate = self.cpu.mmu.sound.as_ref().map(|s| s.samples_rate());
+        let recorder = Recorder::create(path, sample_rate, self.cpu.mmu.gpu.frame_counter)?;
+        if let Some(sound) = self.cpu.mmu.sound.as_mut() {
+            sound.set_recording(true);
+        }
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    /// Stop recording and complete the file; does nothing if not recording
+    pub fn stop_recording(&mut self) -> Result<()> {
+        if let Some(sound) = self.cpu.mmu.sound.as_mut() {
+            sound.set_recording(false);
+        }
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    fn record(&mut self, ticks: u32) {
+        let Some(recorder) = self.recorder.as_mut() else {
+            return;
+        };
+        if !recorder.frame_due(ticks, self.cpu.mmu.gpu.frame_counter) {
+            return;
+        }
+        let samples = self
+            .cpu
+            .mmu
+            .sound
+            .as_mut()
+            .map(|s| s.take_recorded())
+            .unwrap_or_default();
+        let result = recorder.write_frame(&self.cpu.mmu.gpu.data, &samples);
+        if let Err(e) = result {
+            error!("Recording stopped: {e}");
+        } else if recorder.is_full() {
+            warn!("Recording stopped: the file reached its largest size");
+        } else {
+            return;
+        }
+        if let Err(e) = self.stop_recording() {
+            error!("Failed to complete recording: {e}");
+        }
     }
 
     pub fn set_stdout(&mut self, output: bool) {
@@ -119,6 +336,16 @@ impl Device {
         &self.cpu.mmu.gpu.data
     }
 
+    /// Picture of the screen, encoded as PNG
+    pub fn screenshot(&self) -> Vec<u8> {
+        png::encode(SCREEN_W, SCREEN_H, self.get_gpu_data())
+    }
+
+    /// Render a debug view of the video memory
+    pub fn render_vram_view(&self, view: VramView) -> VramImage {
+        self.cpu.mmu.gpu.render_view(view)
+    }
+
     pub fn enable_audio(&mut self, player: Box<dyn sound::AudioPlayer>, is_on: bool) {
         match self.cpu.mmu.gbmode {
             GbMode::Classic => {
@@ -147,11 +374,86 @@ impl Device {
         self.cpu.mmu.keypad.keydown(key);
     }
 
+    /// Mode the device is running in
+    pub fn mode(&self) -> GbMode {
+        self.cpu.mmu.gbmode
+    }
+
+    /// Run the given boot ROM before the game, instead of starting from the post-boot state.
+    ///
+    /// A DMG boot ROM is 256 bytes long, a CGB boot ROM 2304 bytes; which one is expected
+    /// depends on the mode of the device. This must be called before the first cycle.
+    pub fn set_boot_rom(&mut self, data: Vec<u8>) -> Result<()> {
+        self.cpu.set_boot_rom(data)
+    }
+
+    /// Colors the CGB boot ROM would pick for the cartridge
+    pub fn auto_compat_colors(&self) -> DmgPalette {
+        CompatPalette::cartridge_colors(|a| self.cpu.mmu.mbc.readrom(a))
+    }
+
+    /// Color a classic game with the palette of a button combo; see [`Device::set_compat_colors`]
+    pub fn set_compat_palette(&mut self, palette: CompatPalette) {
+        self.set_compat_colors(palette.colors());
+    }
+
+    /// Color a classic game with the given colors.
+    ///
+    /// Only has an effect when the device runs in `ColorAsClassic` mode; games are shown in
+    /// gray scale until colors are set, unless a CGB boot ROM picks them. While a CGB boot ROM
+    /// runs, the colors replace the ones it picks once it hands over to the game, if it
+    /// selected the compatibility mode.
+    pub fn set_compat_colors(&mut self, colors: DmgPalette) {
+        let mmu = &mut self.cpu.mmu;
+        if mmu.boot_rom_running() && mmu.gbmode == GbMode::Color {
+            mmu.compat_colors = Some(colors);
+        } else if mmu.gbmode == GbMode::ColorAsClassic {
+            mmu.gpu.set_compat_colors(&colors);
+        }
+    }
+
+    /// Colors used for the classic shades, unless a compatibility palette colors them
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.cpu.mmu.gpu.dmg_palette = palette;
+    }
+
+    /// Choose how the picture is drawn; see [`Renderer`]
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.cpu.mmu.gpu.set_renderer(renderer);
+    }
+
+    /// Attach the source of the pictures taken by a Pocket Camera cartridge.
+    ///
+    /// Other cartridges ignore it. Without a source, the camera sees a test gradient.
+    pub fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.cpu.mmu.mbc.set_camera_source(source);
+    }
+
+    /// Get notified when the rumble motor of the cartridge is turned on or off.
+    ///
+    /// The callback is called right away with the current state of the motor. Cartridges
+    /// without a motor ignore it.
+    pub fn set_rumble_callback(&mut self, callback: Box<dyn RumbleCallback>) {
+        self.cpu.mmu.mbc.set_rumble_callback(callback);
+    }
+
+    /// Choose where the real time clock of the cartridge gets the time from.
+    ///
+    /// The wall clock is used by default. Cartridges without a clock ignore it.
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.cpu.mmu.mbc.set_rtc_clock(clock);
+    }
+
+    /// Header of the cartridge: title, cartridge type, sizes, checksums...
+    pub fn cartridge_header(&self) -> CartridgeHeader {
+        self.cpu.mmu.mbc.header()
+    }
+
     pub fn romname(&self) -> String {
         self.cpu.mmu.mbc.romname()
     }
 
-    pub fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+    pub fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
         self.cpu.mmu.mbc.loadram(ramdata)
     }
 
@@ -159,14 +461,198 @@ impl Device {
         self.cpu.mmu.mbc.dumpram()
     }
 
+    /// Write the battery save to `path` instead of next to the ROM with the `gbsave` extension.
+    ///
+    /// The save at `path` is loaded if there is one; otherwise the current save is written
+    /// there from now on. Devices created from a buffer have no save file and ignore it.
+    pub fn set_save_path(&mut self, path: &Path) -> Result<()> {
+        self.cpu.mmu.mbc.set_save_path(path)
+    }
+
+    /// Write the battery backed RAM to `path` in the `.sav` format of other emulators: the
+    /// raw RAM, followed by the 48 byte clock footer for cartridges with a MBC3 clock
+    pub fn export_save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.dumpram()).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Replace the battery backed RAM with the save at `path`.
+    ///
+    /// Raw `.sav` files of other emulators, with or without a clock footer, and the saves of
+    /// previous versions are detected from their size. The save file of the device is updated
+    /// at the next flush.
+    pub fn import_save(&mut self, path: &Path) -> Result<()> {
+        let data = std::fs::read(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        self.loadram(&data)
+    }
+
     pub fn ram_is_battery_backed(&self) -> bool {
         self.cpu.mmu.mbc.is_battery_backed()
     }
 
+    /// Write the battery backed RAM to its save file if it changed since the last flush.
+    ///
+    /// Returns whether anything was written. This is also done when the device is dropped.
+    pub fn flush_ram(&mut self) -> Result<bool> {
+        self.cpu.mmu.mbc.flush()
+    }
+
+    /// Keep `count` backups of the previous battery save, rotated the first time it is
+    /// overwritten after the game was loaded
+    pub fn set_save_backups(&mut self, count: usize) {
+        self.save_backups = count;
+        self.cpu.mmu.mbc.set_save_backups(count);
+    }
+
     pub fn check_and_reset_ram_updated(&mut self) -> bool {
         self.cpu.mmu.mbc.check_and_reset_ram_updated()
     }
 
+    /// Trace every instruction run, replacing any previous trace
+    pub fn enable_trace(&mut self, config: &TraceConfig) -> Result<()> {
+        self.cpu.tracer = Some(Tracer::new(config)?);
+        self.cpu.mmu.stub_ly = config.stub_ly;
+        Ok(())
+    }
+
+    /// Stop tracing, writing the end of the trace file
+    pub fn disable_trace(&mut self) -> Result<()> {
+        self.cpu.mmu.stub_ly = false;
+        match self.cpu.tracer.take() {
+            Some(mut tracer) => tracer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Lines held by the ring buffer of the trace, oldest first
+    pub fn trace_lines(&self) -> Vec<String> {
+        self.cpu
+            .tracer
+            .as_ref()
+            .map(Tracer::lines)
+            .unwrap_or_default()
+    }
+
+    /// Registers and execution state of the CPU
+    pub fn cpu_state(&self) -> CpuState {
+        self.cpu.state()
+    }
+
+    /// Stop [`debug_cycle`](Self::debug_cycle) before the instruction at `pc`, in any bank.
+    ///
+    /// Returns whether the breakpoint was not set yet.
+    pub fn add_breakpoint(&mut self, pc: u16) -> bool {
+        self.breakpoints.insert(pc)
+    }
+
+    /// Returns whether the breakpoint was set
+    pub fn remove_breakpoint(&mut self, pc: u16) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Stop [`debug_cycle`](Self::debug_cycle) after an instruction accessing the watched
+    /// memory. Accesses of the DMA transfers are watched too.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.cpu.mmu.watchpoints.add(watchpoint);
+    }
+
+    /// Remove the watchpoint at `index` in [`watchpoints`](Self::watchpoints)
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        self.cpu.mmu.watchpoints.remove(index)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.cpu.mmu.watchpoints.list()
+    }
+
+    /// Run one instruction like [`do_cycle`](Self::do_cycle), and tell whether to stop:
+    /// the instruction hit a watchpoint, completed a call stepped over, or the next one is at
+    /// a breakpoint.
+    ///
+    /// Returns the elapsed ticks along with the reason to stop.
+    pub fn debug_cycle(&mut self) -> (u32, Option<DebugStop>) {
+        let before = self.cpu.state();
+        let ticks = self.do_cycle();
+        let after = self.cpu.state();
+
+        let stop = if after.locked && !before.locked {
+            Some(DebugStop::Locked(before.pc))
+        } else if let Some((address, value, access)) = self.cpu.mmu.watchpoints.take_hit() {
+            Some(DebugStop::Watchpoint(WatchHit {
+                address,
+                value,
+                access,
+                pc: before.pc,
+            }))
+        } else if self
+            .step_over
+            .is_some_and(|(pc, sp)| after.pc == pc && after.sp >= sp)
+        {
+            Some(DebugStop::Step)
+        } else if self.breakpoints.contains(&after.pc) && !(before.halted && after.halted) {
+            Some(DebugStop::Breakpoint(after.pc))
+        } else {
+            None
+        };
+        if stop.is_some() {
+            self.step_over = None;
+        }
+
+        (ticks, stop)
+    }
+
+    /// Run one instruction, or the dispatch of an interrupt
+    pub fn step(&mut self) -> (u32, DebugStop) {
+        let (ticks, stop) = self.debug_cycle();
+        (ticks, stop.unwrap_or(DebugStop::Step))
+    }
+
+    /// Run one instruction, or a whole subroutine if it is a CALL or a RST.
+    ///
+    /// Only the first instruction runs here: keep calling [`debug_cycle`](Self::debug_cycle)
+    /// until it stops with [`DebugStop::Step`] once the subroutine returns, unless a
+    /// breakpoint or watchpoint stops it first.
+    pub fn step_over(&mut self) -> (u32, Option<DebugStop>) {
+        let state = self.cpu.state();
+        let size = match self.peek_byte(state.pc) {
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => 3,
+            // RST
+            opcode if opcode & 0xC7 == 0xC7 => 1,
+            _ => {
+                let (ticks, stop) = self.step();
+                return (ticks, Some(stop));
+            }
+        };
+        self.step_over = Some((state.pc.wrapping_add(size), state.sp));
+        self.debug_cycle()
+    }
+
+    /// Read memory without triggering the watchpoints
+    pub fn peek_byte(&mut self, address: u16) -> u8 {
+        self.cpu.mmu.read(address)
+    }
+
+    /// Decode the instructions starting in `range`, without triggering the watchpoints
+    pub fn disassemble(&mut self, range: RangeInclusive<u16>) -> Vec<Instruction> {
+        disasm::disassemble(range, |address| self.cpu.mmu.read(address))
+    }
+
+    /// Write memory without triggering the watchpoints
+    pub fn poke_byte(&mut self, address: u16, value: u8) {
+        let watchpoints = std::mem::take(&mut self.cpu.mmu.watchpoints);
+        self.cpu.mmu.wb(address, value);
+        self.cpu.mmu.watchpoints = watchpoints;
+    }
+
     pub fn read_byte(&mut self, address: u16) -> u8 {
         self.cpu.read_byte(address)
     }
@@ -180,3 +666,478 @@ impl Device {
         self.cpu.write_wide(address, byte)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debugger::Access;
+    use crate::palette::PalettePreset;
+    use crate::trace::TraceOutput;
+
+    fn test_rom(title: &str) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // JR -2: spin forever at the entry point
+        rom[0x100] = 0x18;
+        rom[0x101] = 0xFE;
+        rom[0x134..0x134 + title.len()].copy_from_slice(title.as_bytes());
+        rom
+    }
+
+    #[test]
+    fn save_and_load_state_slot() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        std::fs::write(&rom_path, test_rom("GAME")).unwrap();
+
+        let mut device = Device::new(&rom_path, true).unwrap();
+        device.write_byte(0xC000, 0x42);
+        let state_path = device.save_state_to(1).unwrap();
+        assert_eq!(state_path, dir.path().join("game.ss1"));
+
+        device.write_byte(0xC000, 0x00);
+        device.load_state_from(1).unwrap();
+        assert_eq!(device.read_byte(0xC000), 0x42);
+    }
+
+    #[test]
+    fn load_missing_state_slot() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        std::fs::write(&rom_path, test_rom("GAME")).unwrap();
+
+        let mut device = Device::new(&rom_path, true).unwrap();
+        assert!(matches!(device.load_state_from(3), Err(Error::Io { .. })));
+    }
+
+    #[test]
+    fn state_header_is_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        std::fs::write(&rom_path, test_rom("GAME")).unwrap();
+
+        let mut device = Device::new(&rom_path, true).unwrap();
+        let state_path = device.save_state_to(0).unwrap();
+        let header = Device::read_state_header(&state_path).unwrap();
+        assert_eq!(header.version, SAVE_STATE_VERSION);
+        assert_eq!(header.title, "GAME");
+        assert_eq!(header.gbmode, GbMode::Classic);
+    }
+
+    #[test]
+    fn reject_state_of_other_rom() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        std::fs::write(&rom_path, test_rom("GAME")).unwrap();
+        let other_rom_path = dir.path().join("other.gb");
+        std::fs::write(&other_rom_path, test_rom("OTHER")).unwrap();
+
+        let state_path = Device::new(&rom_path, true)
+            .unwrap()
+            .save_state_to(0)
+            .unwrap();
+        let mut other = Device::new(&other_rom_path, true).unwrap();
+        assert!(matches!(
+            other.load_state_from_path(&state_path),
+            Err(Error::StateRomMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn state_leaves_out_rom() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        let mut rom = test_rom("GAME");
+        rom.resize(0x100000, 0x55);
+        rom[0x147] = 0x01; // MBC1
+        rom[0x148] = 0x05; // 1 MiB
+        std::fs::write(&rom_path, rom).unwrap();
+
+        let mut device = Device::new(&rom_path, true).unwrap();
+        let state_path = device.save_state_to(1).unwrap();
+        assert!(std::fs::metadata(&state_path).unwrap().len() < 0x40000);
+        assert!(!dir.path().join("game.ss1.tmp").exists());
+
+        let mut device = Device::new(&rom_path, true).unwrap();
+        device.load_state_from(1).unwrap();
+        assert_eq!(device.read_byte(0x0100), 0x18);
+        assert_eq!(device.cartridge_header().title, "GAME");
+    }
+
+    #[test]
+    fn reject_state_of_other_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        std::fs::write(&rom_path, test_rom("GAME")).unwrap();
+
+        let state_path = Device::new(&rom_path, true)
+            .unwrap()
+            .save_state_to(0)
+            .unwrap();
+        let mut device = Device::new_cgb(&rom_path, true).unwrap();
+        assert!(matches!(
+            device.load_state_from_path(&state_path),
+            Err(Error::StateModeMismatch {
+                expected: GbMode::ColorAsClassic,
+                found: GbMode::Classic
+            })
+        ));
+    }
+
+    #[test]
+    fn reject_file_without_magic() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        std::fs::write(&rom_path, test_rom("GAME")).unwrap();
+        std::fs::write(dir.path().join("game.ss0"), b"garbage").unwrap();
+
+        let mut device = Device::new(&rom_path, true).unwrap();
+        assert!(matches!(
+            device.load_state_from(0),
+            Err(Error::NotASaveState)
+        ));
+    }
+
+    #[test]
+    fn rewind_to_previous_snapshot() {
+        fn run_frame(device: &mut Device) {
+            while !device.check_and_reset_gpu_updated() {
+                device.do_cycle();
+            }
+        }
+
+        let mut device = Device::new_from_buffer(test_rom("GAME"), true).unwrap();
+        device.enable_rewind(RewindConfig {
+            interval_frames: 1,
+            capacity: 4,
+        });
+        assert!(!device.rewind(1).unwrap());
+
+        for frame in 0..6 {
+            device.write_byte(0xC000, frame);
+            run_frame(&mut device);
+        }
+        assert_eq!(device.rewind_len(), 4);
+
+        assert!(device.rewind(1).unwrap());
+        assert_eq!(device.read_byte(0xC000), 5);
+        assert!(device.rewind(2).unwrap());
+        assert_eq!(device.read_byte(0xC000), 3);
+        assert_eq!(device.rewind_len(), 1);
+        // rewinding past the start stops at the oldest snapshot
+        assert!(device.rewind(100).unwrap());
+        assert_eq!(device.read_byte(0xC000), 2);
+    }
+
+    #[test]
+    fn boot_rom_is_mapped_until_ff50_write() {
+        let mut device = Device::new_from_buffer(test_rom("GAME"), true).unwrap();
+        assert!(matches!(
+            device.set_boot_rom(vec![0; 0x900]),
+            Err(Error::InvalidBootRomSize {
+                expected: 0x100,
+                actual: 0x900
+            })
+        ));
+
+        // LD A,1; LDH (0x50),A
+        let mut boot_rom = vec![0; 0x100];
+        boot_rom[..4].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        device.set_boot_rom(boot_rom).unwrap();
+        assert_eq!(device.read_byte(0x0000), 0x3E);
+        assert_eq!(device.read_byte(0x0101), 0xFE);
+
+        device.do_cycle();
+        device.do_cycle();
+        assert_eq!(device.read_byte(0x0000), 0x00);
+    }
+
+    #[test]
+    fn cgb_boot_rom_selects_compatibility_mode() {
+        let mut device = Device::new_cgb_from_buffer(test_rom("GAME"), true).unwrap();
+        assert_eq!(device.mode(), GbMode::ColorAsClassic);
+
+        // LD A,4; LDH (0x4C),A; LD A,1; LDH (0x50),A
+        let mut boot_rom = vec![0; 0x900];
+        boot_rom[..8].copy_from_slice(&[0x3E, 0x04, 0xE0, 0x4C, 0x3E, 0x01, 0xE0, 0x50]);
+        boot_rom[0x200] = 0x42;
+        device.set_boot_rom(boot_rom).unwrap();
+        assert_eq!(device.mode(), GbMode::Color);
+        assert_eq!(device.read_byte(0x0200), 0x42);
+
+        for _ in 0..4 {
+            device.do_cycle();
+        }
+        assert_eq!(device.mode(), GbMode::ColorAsClassic);
+        assert!(device.cpu.mmu.gpu.compat_palettes);
+        assert_eq!(device.read_byte(0x0200), 0x00);
+    }
+
+    #[test]
+    fn compat_palette_replaces_boot_rom_colors() {
+        let mut device = Device::new_cgb_from_buffer(test_rom("GAME"), true).unwrap();
+        // LD A,4; LDH (0x4C),A; LD A,1; LDH (0x50),A
+        let mut boot_rom = vec![0; 0x900];
+        boot_rom[..8].copy_from_slice(&[0x3E, 0x04, 0xE0, 0x4C, 0x3E, 0x01, 0xE0, 0x50]);
+        device.set_boot_rom(boot_rom).unwrap();
+        device.set_compat_palette(CompatPalette::DownA);
+        assert!(!device.cpu.mmu.gpu.compat_palettes);
+
+        for _ in 0..4 {
+            device.do_cycle();
+        }
+        assert_eq!(device.mode(), GbMode::ColorAsClassic);
+        // white, yellow, red, black
+        assert_eq!(device.cpu.mmu.gpu.cbgpal[0][1], [0x1F, 0x1F, 0x00]);
+        assert_eq!(device.cpu.mmu.gpu.cbgpal[0][2], [0x1F, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn compat_palette_only_in_color_as_classic_mode() {
+        let mut device = Device::new_from_buffer(test_rom("GAME"), true).unwrap();
+        device.set_compat_palette(CompatPalette::UpA);
+        assert!(!device.cpu.mmu.gpu.compat_palettes);
+
+        let mut device = Device::new_cgb_from_buffer(test_rom("GAME"), true).unwrap();
+        assert_eq!(device.auto_compat_colors(), CompatPalette::DEFAULT.colors());
+        device.set_compat_palette(CompatPalette::UpA);
+        assert!(device.cpu.mmu.gpu.compat_palettes);
+    }
+
+    #[test]
+    fn dmg_palette_is_kept_on_rewind() {
+        let mut device = Device::new_from_buffer(test_rom("GAME"), true).unwrap();
+        device.enable_rewind(RewindConfig {
+            interval_frames: 1,
+            capacity: 4,
+        });
+        let palette = PalettePreset::Dmg.palette();
+        device.set_dmg_palette(palette);
+
+        for _ in 0..2 {
+            while !device.check_and_reset_gpu_updated() {
+                device.do_cycle();
+            }
+        }
+        assert_eq!(device.get_gpu_data()[..3], [0x9B, 0xBC, 0x0F]);
+
+        assert!(device.rewind(1).unwrap());
+        assert_eq!(device.cpu.mmu.gpu.dmg_palette, palette);
+    }
+
+    #[test]
+    fn screenshot_holds_the_screen() {
+        let mut device = Device::new_from_buffer(test_rom("GAME"), true).unwrap();
+        for _ in 0..2 {
+            while !device.check_and_reset_gpu_updated() {
+                device.do_cycle();
+            }
+        }
+
+        let image = png::decode(&device.screenshot()).unwrap();
+        assert_eq!((image.width, image.height), (SCREEN_W, SCREEN_H));
+        assert_eq!(image.data, device.get_gpu_data());
+    }
+
+    #[test]
+    fn recording_holds_a_frame_per_frame() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game.avi");
+        let mut device = Device::new_from_buffer(test_rom("GAME"), true).unwrap();
+        device.start_recording(&path).unwrap();
+        assert!(device.is_recording());
+        for _ in 0..3 {
+            while !device.check_and_reset_gpu_updated() {
+                device.do_cycle();
+            }
+        }
+        device.stop_recording().unwrap();
+        assert!(!device.is_recording());
+
+        let avi = std::fs::read(&path).unwrap();
+        assert_eq!(&avi[..4], b"RIFF");
+        // total frames of the main header
+        assert_eq!(u32::from_le_bytes(avi[48..52].try_into().unwrap()), 3);
+    }
+
+    #[test]
+    fn renderer_is_kept_on_rewind() {
+        let mut device = Device::new_from_buffer(test_rom("GAME"), true).unwrap();
+        device.enable_rewind(RewindConfig {
+            interval_frames: 1,
+            capacity: 4,
+        });
+        device.set_renderer(Renderer::PixelFifo);
+
+        for _ in 0..2 {
+            while !device.check_and_reset_gpu_updated() {
+                device.do_cycle();
+            }
+        }
+
+        assert!(device.rewind(1).unwrap());
+        assert_eq!(device.cpu.mmu.gpu.renderer(), Renderer::PixelFifo);
+    }
+
+    #[test]
+    fn rtc_clock_is_kept_on_load_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        let mut rom = test_rom("GAME");
+        rom[0x147] = 0x10; // MBC3+TIMER+RAM+BATTERY
+        rom[0x149] = 0x02;
+        std::fs::write(&rom_path, rom).unwrap();
+
+        let mut device = Device::new(&rom_path, true).unwrap();
+        device.set_rtc_clock(RtcClock::Emulated);
+        device.save_state_to(1).unwrap();
+        device.load_state_from(1).unwrap();
+        assert!(matches!(
+            device.cpu.mmu.mbc.take_rtc_clock(),
+            Some(RtcClock::Emulated)
+        ));
+    }
+
+    #[test]
+    fn export_and_import_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        let mut rom = test_rom("GAME");
+        rom[0x147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x149] = 0x02;
+        std::fs::write(&rom_path, rom).unwrap();
+
+        let mut device = Device::new(&rom_path, true).unwrap();
+        device.write_byte(0x0000, 0x0A);
+        device.write_byte(0xA000, 0x42);
+        let sav_path = dir.path().join("export.sav");
+        device.export_save(&sav_path).unwrap();
+        assert_eq!(std::fs::read(&sav_path).unwrap().len(), 0x2000);
+
+        device.write_byte(0xA000, 0x00);
+        device.import_save(&sav_path).unwrap();
+        assert_eq!(device.read_byte(0xA000), 0x42);
+        assert!(device.flush_ram().unwrap());
+    }
+
+    #[test]
+    fn save_backups_rotate_once_across_state_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        let mut rom = test_rom("GAME");
+        rom[0x147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x149] = 0x02;
+        std::fs::write(&rom_path, rom).unwrap();
+        std::fs::write(dir.path().join("game.gbsave"), [0x42; 0x2000]).unwrap();
+
+        let mut device = Device::new(&rom_path, true).unwrap();
+        device.set_save_backups(2);
+        device.write_byte(0x0000, 0x0A);
+        device.write_byte(0xA000, 0x01);
+        assert!(device.flush_ram().unwrap());
+        device.save_state_to(1).unwrap();
+        device.load_state_from(1).unwrap();
+        device.write_byte(0xA000, 0x02);
+        assert!(device.flush_ram().unwrap());
+
+        let backup = |i: usize| dir.path().join(format!("game.gbsave.bak{i}"));
+        assert_eq!(std::fs::read(backup(1)).unwrap()[0], 0x42);
+        assert!(!backup(2).exists());
+    }
+
+    #[test]
+    fn save_path_is_kept_on_state_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        let mut rom = test_rom("GAME");
+        rom[0x147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x149] = 0x02;
+        std::fs::write(&rom_path, rom).unwrap();
+
+        let mut device = Device::new(&rom_path, true).unwrap();
+        device.save_state_to(1).unwrap();
+        let save_path = dir.path().join("game.sav");
+        device.set_save_path(&save_path).unwrap();
+        device.load_state_from(1).unwrap();
+        device.write_byte(0x0000, 0x0A);
+        device.write_byte(0xA000, 0x42);
+        assert!(device.flush_ram().unwrap());
+
+        assert_eq!(std::fs::read(&save_path).unwrap()[0], 0x42);
+        assert!(!rom_path.with_extension("gbsave").exists());
+    }
+
+    #[test]
+    fn debugger_stops_and_steps_over() {
+        let mut rom = test_rom("GAME");
+        let code = [
+            0x3E, 0x42, // 0x100: LD A, 0x42
+            0xCD, 0x50, 0x01, // 0x102: CALL 0x0150
+            0xEA, 0x00, 0xC0, // 0x105: LD (0xC000), A
+            0x18, 0xFE, // 0x108: JR -2
+        ];
+        rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+        rom[0x150..0x152].copy_from_slice(&[0x3C, 0xC9]); // INC A; RET
+        let mut device = Device::new_from_buffer(rom, true).unwrap();
+
+        assert_eq!(device.step().1, DebugStop::Step);
+        assert_eq!(device.cpu_state().a, 0x42);
+
+        // the call completes in a single step over
+        let mut stop = device.step_over().1;
+        while stop.is_none() {
+            stop = device.debug_cycle().1;
+        }
+        assert_eq!(stop, Some(DebugStop::Step));
+        let state = device.cpu_state();
+        assert_eq!((state.pc, state.a, state.sp), (0x105, 0x43, 0xFFFE));
+
+        device.add_watchpoint(Watchpoint::write(0xC000));
+        device.add_breakpoint(0x108);
+        let (_, stop) = device.debug_cycle();
+        assert_eq!(
+            stop,
+            Some(DebugStop::Watchpoint(WatchHit {
+                address: 0xC000,
+                value: 0x43,
+                access: Access::Write,
+                pc: 0x105,
+            }))
+        );
+        device.poke_byte(0xC000, 0x00);
+        assert_eq!(device.peek_byte(0xC000), 0x00);
+        assert_eq!(device.debug_cycle().1, Some(DebugStop::Breakpoint(0x108)));
+    }
+
+    #[test]
+    fn trace_follows_instructions() {
+        let mut rom = test_rom("GAME");
+        // LDH A, (LY); JR -4
+        rom[0x100..0x104].copy_from_slice(&[0xF0, 0x44, 0x18, 0xFC]);
+        let mut device = Device::new_from_buffer(rom, true).unwrap();
+        device
+            .enable_trace(&TraceConfig {
+                output: TraceOutput::Ring,
+                limit: 3,
+                stub_ly: true,
+            })
+            .unwrap();
+        for _ in 0..4 {
+            device.do_cycle();
+        }
+
+        let lines = device.trace_lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[2],
+            "A:90 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:18,FC,00,00"
+        );
+        device.disable_trace().unwrap();
+        assert!(device.trace_lines().is_empty());
+    }
+
+    #[test]
+    fn state_slots_need_rom_path() {
+        let mut device = Device::new_from_buffer(test_rom("GAME"), true).unwrap();
+        assert!(matches!(device.save_state_to(0), Err(Error::NoRomPath)));
+    }
+}
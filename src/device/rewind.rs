@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+
+use crate::cpu::Cpu;
+use crate::device::save_state::encode_error;
+use crate::{Error, Result};
+
+/// Compression level used for snapshots; favour speed over size
+const COMPRESSION_LEVEL: u8 = 1;
+
+/// Configuration of the rewind buffer
+#[derive(Debug, Clone, Copy)]
+pub struct RewindConfig {
+    /// A snapshot is recorded every `interval_frames` frames
+    pub interval_frames: u32,
+    /// Maximum number of snapshots kept; the oldest ones are dropped first
+    pub capacity: usize,
+}
+
+/// Ring buffer of compressed [`Cpu`] snapshots.
+///
+/// The snapshots leave out the ROM, which never changes: the restored [`Cpu`] has to get it
+/// back from the live one.
+pub(crate) struct RewindBuffer {
+    config: RewindConfig,
+    snapshots: VecDeque<Vec<u8>>,
+    /// Frame counter of the GPU when the last frame was seen
+    last_frame: u32,
+    /// Frames elapsed since the last snapshot was recorded
+    frames_since_snapshot: u32,
+}
+
+impl RewindBuffer {
+    pub fn new(config: RewindConfig, current_frame: u32) -> Self {
+        Self {
+            config: RewindConfig {
+                interval_frames: config.interval_frames.max(1),
+                capacity: config.capacity,
+            },
+            snapshots: VecDeque::with_capacity(config.capacity),
+            last_frame: current_frame,
+            frames_since_snapshot: 0,
+        }
+    }
+
+    pub fn interval_frames(&self) -> u32 {
+        self.config.interval_frames
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Record a snapshot of `cpu` if enough frames passed since the last one
+    pub fn on_cycle(&mut self, cpu: &mut Cpu) -> Result<()> {
+        let frame = cpu.mmu.gpu.frame_counter;
+        if frame == self.last_frame {
+            return Ok(());
+        }
+        self.frames_since_snapshot += frame.wrapping_sub(self.last_frame);
+        self.last_frame = frame;
+        if self.frames_since_snapshot < self.config.interval_frames {
+            return Ok(());
+        }
+        self.frames_since_snapshot = 0;
+
+        self.push(cpu)
+    }
+
+    /// Compress and store a snapshot of `cpu`, without its ROM
+    fn push(&mut self, cpu: &mut Cpu) -> Result<()> {
+        if self.config.capacity == 0 {
+            return Ok(());
+        }
+
+        let rom = cpu.mmu.mbc.take_rom();
+        let mut encoded = Vec::new();
+        let result = ciborium::into_writer(&*cpu, &mut encoded);
+        cpu.mmu.mbc.set_rom(rom);
+        result.map_err(encode_error)?;
+        let compressed = miniz_oxide::deflate::compress_to_vec(&encoded, COMPRESSION_LEVEL);
+
+        if self.snapshots.len() >= self.config.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(compressed);
+
+        Ok(())
+    }
+
+    /// Drop `count` snapshots and decode the last dropped one, which has no ROM.
+    ///
+    /// The oldest snapshot is never dropped, so rewinding further than the buffer goes
+    /// returns the oldest available state.
+    pub fn pop(&mut self, count: usize) -> Result<Option<Cpu>> {
+        let mut snapshot = None;
+        for _ in 0..count.max(1) {
+            if self.snapshots.len() <= 1 {
+                break;
+            }
+            snapshot = self.snapshots.pop_back();
+        }
+        let snapshot = match snapshot {
+            Some(snapshot) => snapshot,
+            None => match self.snapshots.back() {
+                Some(oldest) => oldest.clone(),
+                None => return Ok(None),
+            },
+        };
+
+        let encoded = miniz_oxide::inflate::decompress_to_vec(&snapshot)
+            .map_err(|e| Error::StateDecode(e.to_string()))?;
+        let cpu: Cpu = ciborium::de::from_reader(encoded.as_slice())
+            .map_err(|e| Error::StateDecode(e.to_string()))?;
+
+        // don't record the restored state again right away
+        self.last_frame = cpu.mmu.gpu.frame_counter;
+        self.frames_since_snapshot = 0;
+
+        Ok(Some(cpu))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RewindBuffer, RewindConfig};
+    use crate::cpu::Cpu;
+    use crate::mbc;
+
+    #[test]
+    fn snapshot_leaves_out_rom() {
+        // 1 MiB of noise which doesn't compress
+        let mut seed = 1u32;
+        let mut rom: Vec<u8> = (0..0x100000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        rom[0x147] = 0x1B;
+        rom[0x148] = 0x05;
+        rom[0x149] = 0x03;
+        let mut cpu = Cpu::new(mbc::get_mbc(rom.clone(), true).unwrap(), None).unwrap();
+
+        let config = RewindConfig {
+            interval_frames: 1,
+            capacity: 2,
+        };
+        let mut rewind = RewindBuffer::new(config, 0);
+        rewind.push(&mut cpu).unwrap();
+        // about the size of the RAM and VRAM, most of which is blank
+        assert!(rewind.snapshots[0].len() < 0x10000);
+        assert_eq!(cpu.mmu.mbc.take_rom(), rom);
+
+        let mut restored = rewind.pop(1).unwrap().unwrap();
+        assert!(restored.mmu.mbc.take_rom().is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::Cpu;
+use crate::gbmode::GbMode;
+use crate::mbc::write_atomic;
+use crate::{Error, Result};
+
+/// Magic bytes every save state file starts with
+pub const SAVE_STATE_MAGIC: [u8; 8] = *b"RBOYSAVE";
+/// Current version of the save state format
+pub const SAVE_STATE_VERSION: u16 = 1;
+
+/// Header written in front of every save state.
+///
+/// It binds the state to the ROM and mode it was created with, so that a state is never
+/// loaded into a different game or by an incompatible build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaveStateHeader {
+    /// Save state format version
+    pub version: u16,
+    /// ROM title from the cartridge header
+    pub title: String,
+    /// Global checksum from the cartridge header (0x14E-0x14F)
+    pub global_checksum: u16,
+    /// Mode the emulator was running in
+    pub gbmode: GbMode,
+    /// Creation time in seconds since the unix epoch
+    pub created_at: u64,
+}
+
+impl SaveStateHeader {
+    /// Create a header for the current format version, timestamped now
+    pub(crate) fn new(title: String, global_checksum: u16, gbmode: GbMode) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            version: SAVE_STATE_VERSION,
+            title,
+            global_checksum,
+            gbmode,
+            created_at,
+        }
+    }
+
+    /// Write the magic and the header to `data`
+    pub(crate) fn write(&self, data: &mut Vec<u8>) -> Result<()> {
+        data.extend_from_slice(&SAVE_STATE_MAGIC);
+        ciborium::into_writer(self, data).map_err(encode_error)
+    }
+
+    /// Read the magic and the header from `reader` of the file at `path`, rejecting unknown
+    /// format versions
+    pub(crate) fn read<R: Read>(mut reader: R, path: &Path) -> Result<Self> {
+        let mut magic = [0; SAVE_STATE_MAGIC.len()];
+        match reader.read_exact(&mut magic) {
+            Ok(()) if magic == SAVE_STATE_MAGIC => {}
+            Ok(()) => return Err(Error::NotASaveState),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(Error::NotASaveState);
+            }
+            Err(source) => {
+                return Err(Error::Io {
+                    path: path.to_path_buf(),
+                    source,
+                });
+            }
+        }
+
+        let header: SaveStateHeader =
+            ciborium::de::from_reader(reader).map_err(|e| decode_error(path, e))?;
+        if header.version != SAVE_STATE_VERSION {
+            return Err(Error::UnsupportedStateVersion {
+                found: header.version,
+                expected: SAVE_STATE_VERSION,
+            });
+        }
+
+        Ok(header)
+    }
+
+    /// Check that a state with this header may be loaded into a device described by `device`
+    pub(crate) fn check_compatible(&self, device: &SaveStateHeader) -> Result<()> {
+        if self.title != device.title || self.global_checksum != device.global_checksum {
+            return Err(Error::StateRomMismatch {
+                expected_title: device.title.clone(),
+                expected_checksum: device.global_checksum,
+                found_title: self.title.clone(),
+                found_checksum: self.global_checksum,
+            });
+        }
+        if self.gbmode != device.gbmode {
+            return Err(Error::StateModeMismatch {
+                expected: device.gbmode,
+                found: self.gbmode,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Open the save state file at `path`
+fn open(path: &Path) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+/// Read the header of the save state file at `path`
+pub(crate) fn read_header(path: &Path) -> Result<SaveStateHeader> {
+    SaveStateHeader::read(open(path)?, path)
+}
+
+/// Write a save state of `cpu` with the given header into the file at `path`.
+///
+/// The previous file is only replaced once the new state is complete. The ROM is left out,
+/// the device loading the state has it already.
+pub(crate) fn write(path: &Path, header: &SaveStateHeader, cpu: &mut Cpu) -> Result<()> {
+    let mut data = Vec::new();
+    header.write(&mut data)?;
+    let rom = cpu.mmu.mbc.take_rom();
+    let result = ciborium::into_writer(&*cpu, &mut data);
+    cpu.mmu.mbc.set_rom(rom);
+    result.map_err(encode_error)?;
+    write_atomic(path, &data, 0).map_err(|source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Read the save state file at `path`, checking it is compatible with the `device` header
+pub(crate) fn read(path: &Path, device: &SaveStateHeader) -> Result<Cpu> {
+    let mut file = open(path)?;
+    SaveStateHeader::read(&mut file, path)?.check_compatible(device)?;
+    ciborium::de::from_reader(file).map_err(|e| decode_error(path, e))
+}
+
+/// Error encoding a state in memory
+pub(crate) fn encode_error(e: ciborium::ser::Error<io::Error>) -> Error {
+    Error::StateEncode(e.to_string())
+}
+
+/// Error decoding the state of the file at `path`
+fn decode_error(path: &Path, e: ciborium::de::Error<io::Error>) -> Error {
+    match e {
+        ciborium::de::Error::Io(source) => Error::Io {
+            path: path.to_path_buf(),
+            source,
+        },
+        e => Error::StateDecode(e.to_string()),
+    }
+}
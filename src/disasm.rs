@@ -0,0 +1,322 @@
+//! Disassembler of the SM83 instruction set, as executed by the [`Cpu`](crate::cpu::Cpu).
+//!
+//! Cycles are machine cycles of 4 clock ticks, as counted by the CPU.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// Opcodes which are not instructions and lock up the CPU
+const ILLEGAL_OPCODES: [u8; 11] = [
+    0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+];
+
+const REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const PAIRS: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const STACK_PAIRS: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [(&str, &str); 8] = [
+    ("ADD", "A, "),
+    ("ADC", "A, "),
+    ("SUB", ""),
+    ("SBC", "A, "),
+    ("AND", ""),
+    ("XOR", ""),
+    ("OR", ""),
+    ("CP", ""),
+];
+const ROTATIONS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const ACCUMULATOR: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+/// Decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// Address of the opcode
+    pub address: u16,
+    /// Opcode and operands, of which the first [`length`](Self::length) are used
+    pub bytes: [u8; 3],
+    pub length: u8,
+    pub mnemonic: &'static str,
+    /// Operands separated by commas, e.g. `A, (HL)`
+    pub operands: String,
+    /// Machine cycles, when the branch is taken for conditional instructions
+    pub cycles: u8,
+    /// Machine cycles of conditional instructions when the branch is not taken
+    pub cycles_not_taken: Option<u8>,
+}
+
+impl Instruction {
+    /// The opcode is not an instruction: the CPU locks up
+    pub fn is_illegal(&self) -> bool {
+        ILLEGAL_OPCODES.contains(&self.bytes[0])
+    }
+
+    /// Address of the next instruction
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.length as u16)
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// `0150: CD 00 40  CALL $4000`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04X}:", self.address)?;
+        for i in 0..3 {
+            match i < self.length as usize {
+                true => write!(f, " {:02X}", self.bytes[i])?,
+                false => write!(f, "   ")?,
+            }
+        }
+        write!(f, "  {}", self.mnemonic)?;
+        if !self.operands.is_empty() {
+            write!(f, " {}", self.operands)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode the instruction at `address`, reading its bytes with `read`
+pub fn decode(address: u16, mut read: impl FnMut(u16) -> u8) -> Instruction {
+    let opcode = read(address);
+    let n = read(address.wrapping_add(1));
+    let nn = u16::from_le_bytes([n, read(address.wrapping_add(2))]);
+
+    let (mnemonic, operands, length, cycles, cycles_not_taken) = if opcode == 0xCB {
+        let (mnemonic, operands, cycles) = decode_cb(n);
+        (mnemonic, operands, 2, cycles, None)
+    } else {
+        decode_base(address, opcode, n, nn)
+    };
+
+    let mut bytes = [opcode, 0, 0];
+    bytes[1..length as usize].copy_from_slice(&nn.to_le_bytes()[..length as usize - 1]);
+    Instruction {
+        address,
+        bytes,
+        length,
+        mnemonic,
+        operands,
+        cycles,
+        cycles_not_taken,
+    }
+}
+
+/// Decode the instructions starting in `range`, reading their bytes with `read`.
+///
+/// The last instruction may extend past the end of the range.
+pub fn disassemble(
+    range: RangeInclusive<u16>,
+    mut read: impl FnMut(u16) -> u8,
+) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut address = *range.start();
+    while range.contains(&address) {
+        let instruction = decode(address, &mut read);
+        let next = instruction.next_address();
+        instructions.push(instruction);
+        if next < address {
+            // wrapped around the address space
+            break;
+        }
+        address = next;
+    }
+    instructions
+}
+
+fn signed(d: u8) -> String {
+    match d as i8 {
+        d if d < 0 => format!("-${:02X}", d.unsigned_abs()),
+        d => format!("+${d:02X}"),
+    }
+}
+
+/// Decode an opcode without prefix, returning the mnemonic, operands, length and cycles when
+/// the branch is taken and not.
+///
+/// The opcodes are split into fields `xxyyyzzz`, with `yyy` split into `ppq`.
+fn decode_base(
+    address: u16,
+    opcode: u8,
+    n: u8,
+    nn: u16,
+) -> (&'static str, String, u8, u8, Option<u8>) {
+    let x = opcode >> 6;
+    let y = ((opcode >> 3) & 7) as usize;
+    let z = (opcode & 7) as usize;
+    let p = y >> 1;
+    let q = y & 1 == 1;
+    let reg_cycles = |r: usize, cycles: u8, hl_cycles: u8| if r == 6 { hl_cycles } else { cycles };
+    let relative = address.wrapping_add(2).wrapping_add(n as i8 as u16);
+
+    let op =
+        |mnemonic, operands: String, length, cycles| (mnemonic, operands, length, cycles, None);
+    match (x, z) {
+        (0, 0) => match y {
+            0 => op("NOP", String::new(), 1, 1),
+            1 => op("LD", format!("(${nn:04X}), SP"), 3, 5),
+            // the CPU doesn't skip the byte following STOP
+            2 => op("STOP", String::new(), 1, 1),
+            3 => op("JR", format!("${relative:04X}"), 2, 3),
+            _ => (
+                "JR",
+                format!("{}, ${relative:04X}", CONDITIONS[y - 4]),
+                2,
+                3,
+                Some(2),
+            ),
+        },
+        (0, 1) if q => op("ADD", format!("HL, {}", PAIRS[p]), 1, 2),
+        (0, 1) => op("LD", format!("{}, ${nn:04X}", PAIRS[p]), 3, 3),
+        (0, 2) => {
+            let address = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            match q {
+                true => op("LD", format!("A, {address}"), 1, 2),
+                false => op("LD", format!("{address}, A"), 1, 2),
+            }
+        }
+        (0, 3) => op(if q { "DEC" } else { "INC" }, PAIRS[p].into(), 1, 2),
+        (0, 4) => op("INC", REGISTERS[y].into(), 1, reg_cycles(y, 1, 3)),
+        (0, 5) => op("DEC", REGISTERS[y].into(), 1, reg_cycles(y, 1, 3)),
+        (0, 6) => op(
+            "LD",
+            format!("{}, ${n:02X}", REGISTERS[y]),
+            2,
+            reg_cycles(y, 2, 3),
+        ),
+        (0, 7) => op(ACCUMULATOR[y], String::new(), 1, 1),
+        (1, 6) if y == 6 => op("HALT", String::new(), 1, 1),
+        (1, _) => op(
+            "LD",
+            format!("{}, {}", REGISTERS[y], REGISTERS[z]),
+            1,
+            if y == 6 || z == 6 { 2 } else { 1 },
+        ),
+        (2, _) => {
+            let (mnemonic, prefix) = ALU[y];
+            op(
+                mnemonic,
+                format!("{prefix}{}", REGISTERS[z]),
+                1,
+                reg_cycles(z, 1, 2),
+            )
+        }
+        (3, 0) => match y {
+            0..=3 => ("RET", CONDITIONS[y].into(), 1, 5, Some(2)),
+            4 => op("LDH", format!("(${:04X}), A", 0xFF00 | n as u16), 2, 3),
+            5 => op("ADD", format!("SP, {}", signed(n)), 2, 4),
+            6 => op("LDH", format!("A, (${:04X})", 0xFF00 | n as u16), 2, 3),
+            _ => op("LD", format!("HL, SP{}", signed(n)), 2, 3),
+        },
+        (3, 1) if !q => op("POP", STACK_PAIRS[p].into(), 1, 3),
+        (3, 1) => match p {
+            0 => op("RET", String::new(), 1, 4),
+            1 => op("RETI", String::new(), 1, 4),
+            2 => op("JP", "HL".into(), 1, 1),
+            _ => op("LD", "SP, HL".into(), 1, 2),
+        },
+        (3, 2) => match y {
+            0..=3 => ("JP", format!("{}, ${nn:04X}", CONDITIONS[y]), 3, 4, Some(3)),
+            4 => op("LD", "($FF00+C), A".into(), 1, 2),
+            5 => op("LD", format!("(${nn:04X}), A"), 3, 4),
+            6 => op("LD", "A, ($FF00+C)".into(), 1, 2),
+            _ => op("LD", format!("A, (${nn:04X})"), 3, 4),
+        },
+        (3, 3) if y == 0 => op("JP", format!("${nn:04X}"), 3, 4),
+        (3, 3) if y == 6 => op("DI", String::new(), 1, 1),
+        (3, 3) if y == 7 => op("EI", String::new(), 1, 1),
+        (3, 4) if y < 4 => (
+            "CALL",
+            format!("{}, ${nn:04X}", CONDITIONS[y]),
+            3,
+            6,
+            Some(3),
+        ),
+        (3, 5) if !q => op("PUSH", STACK_PAIRS[p].into(), 1, 4),
+        (3, 5) if y == 1 => op("CALL", format!("${nn:04X}"), 3, 6),
+        (3, 6) => {
+            let (mnemonic, prefix) = ALU[y];
+            op(mnemonic, format!("{prefix}${n:02X}"), 2, 2)
+        }
+        (3, 7) => op("RST", format!("${:02X}", y * 8), 1, 4),
+        // illegal opcodes, shown as data
+        _ => op("DB", format!("${opcode:02X}"), 1, 1),
+    }
+}
+
+/// Decode an opcode following the 0xCB prefix, returning the mnemonic, operands and cycles
+/// including the prefix
+fn decode_cb(opcode: u8) -> (&'static str, String, u8) {
+    let y = ((opcode >> 3) & 7) as usize;
+    let z = (opcode & 7) as usize;
+    let register = REGISTERS[z];
+    let hl = z == 6;
+    match opcode >> 6 {
+        0 => (ROTATIONS[y], register.into(), if hl { 4 } else { 2 }),
+        1 => ("BIT", format!("{y}, {register}"), if hl { 3 } else { 2 }),
+        2 => ("RES", format!("{y}, {register}"), if hl { 4 } else { 2 }),
+        _ => ("SET", format!("{y}, {register}"), if hl { 4 } else { 2 }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, disassemble};
+
+    fn decode_bytes(address: u16, bytes: &[u8]) -> String {
+        decode(address, |a| {
+            bytes
+                .get(a.wrapping_sub(address) as usize)
+                .copied()
+                .unwrap_or(0)
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn decode_formats_operands() {
+        assert_eq!(
+            decode_bytes(0x0150, &[0xCD, 0x00, 0x40]),
+            "0150: CD 00 40  CALL $4000"
+        );
+        assert_eq!(
+            decode_bytes(0x0100, &[0x18, 0xFE]),
+            "0100: 18 FE     JR $0100"
+        );
+        assert_eq!(
+            decode_bytes(0x0100, &[0x20, 0x05]),
+            "0100: 20 05     JR NZ, $0107"
+        );
+        assert_eq!(decode_bytes(0xC000, &[0x7E]), "C000: 7E        LD A, (HL)");
+        assert_eq!(
+            decode_bytes(0xC000, &[0xE0, 0x40]),
+            "C000: E0 40     LDH ($FF40), A"
+        );
+        assert_eq!(
+            decode_bytes(0xC000, &[0xF8, 0xFE]),
+            "C000: F8 FE     LD HL, SP-$02"
+        );
+        assert_eq!(decode_bytes(0xC000, &[0x9E]), "C000: 9E        SBC A, (HL)");
+        assert_eq!(
+            decode_bytes(0xC000, &[0xCB, 0x7E]),
+            "C000: CB 7E     BIT 7, (HL)"
+        );
+        assert_eq!(
+            decode_bytes(0xC000, &[0xCB, 0x37]),
+            "C000: CB 37     SWAP A"
+        );
+        assert_eq!(decode_bytes(0xC000, &[0xFF]), "C000: FF        RST $38");
+        assert_eq!(decode_bytes(0xC000, &[0xD3]), "C000: D3        DB $D3");
+    }
+
+    #[test]
+    fn disassemble_stops_at_end_of_range() {
+        let code = [0x3E, 0x01, 0x00, 0xC3, 0x50, 0x01];
+        let instructions = disassemble(0x100..=0x103, |a| code[a as usize - 0x100]);
+        let mnemonics: Vec<_> = instructions.iter().map(|i| i.mnemonic).collect();
+        assert_eq!(mnemonics, ["LD", "NOP", "JP"]);
+        assert_eq!(instructions[2].next_address(), 0x106);
+        assert_eq!(instructions[2].cycles, 4);
+
+        let instructions = disassemble(0xFFFF..=0xFFFF, |_| 0xC3);
+        assert_eq!(instructions.len(), 1);
+    }
+}
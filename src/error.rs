@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+use std::{fmt, io};
+
+use crate::gbmode::GbMode;
+
+/// Result type used across the library
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Error type of the library
+#[derive(Debug)]
+pub enum Error {
+    /// The ROM is too small to contain a cartridge header
+    RomTooSmall { size: usize },
+    /// The header checksum at 0x14D doesn't match the header contents
+    InvalidChecksum { expected: u8, actual: u8 },
+    /// The cartridge type at 0x147 is not supported
+    UnsupportedMbc(u8),
+    /// The game only runs on a Game Boy Color, but classic mode was requested
+    CgbOnly,
+    /// The battery RAM data has not the size the cartridge expects
+    InvalidRamSize { expected: usize, actual: usize },
+    /// The boot ROM has not the size expected for the mode of the device
+    InvalidBootRomSize { expected: usize, actual: usize },
+    /// I/O error while accessing the file at `path`
+    Io { path: PathBuf, source: io::Error },
+    /// The device was not created from a ROM file, so there is no place to store state slots
+    NoRomPath,
+    /// The emulator state could not be encoded
+    StateEncode(String),
+    /// The save state could not be decoded
+    StateDecode(String),
+    /// The file does not start with [`SAVE_STATE_MAGIC`](crate::device::SAVE_STATE_MAGIC)
+    NotASaveState,
+    /// The save state was written with an unsupported format version
+    UnsupportedStateVersion { found: u16, expected: u16 },
+    /// The save state was created with a different ROM
+    StateRomMismatch {
+        expected_title: String,
+        expected_checksum: u16,
+        found_title: String,
+        found_checksum: u16,
+    },
+    /// The save state was created in a different mode
+    StateModeMismatch { expected: GbMode, found: GbMode },
+    /// The picture is not a PNG which can be read
+    InvalidPng(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RomTooSmall { size } => write!(f, "ROM is too small ({size} bytes)"),
+            Self::InvalidChecksum { expected, actual } => write!(
+                f,
+                "cartridge checksum is invalid (expected {expected:02X}, got {actual:02X})"
+            ),
+            Self::UnsupportedMbc(kind) => write!(f, "unsupported MBC type {kind:02X}"),
+            Self::CgbOnly => write!(f, "this game does not work in classic mode"),
+            Self::InvalidRamSize { expected, actual } => write!(
+                f,
+                "loaded RAM has incorrect length (expected {expected} bytes, got {actual})"
+            ),
+            Self::InvalidBootRomSize { expected, actual } => write!(
+                f,
+                "boot ROM has incorrect length (expected {expected} bytes, got {actual})"
+            ),
+            Self::Io { path, source } => write!(f, "I/O error on {}: {source}", path.display()),
+            Self::NoRomPath => write!(f, "device has no ROM path to store save states next to"),
+            Self::StateEncode(e) => write!(f, "could not encode save state: {e}"),
+            Self::StateDecode(e) => write!(f, "could not decode save state: {e}"),
+            Self::NotASaveState => write!(f, "file is not a save state"),
+            Self::UnsupportedStateVersion { found, expected } => write!(
+                f,
+                "unsupported save state version {found} (expected {expected})"
+            ),
+            Self::StateRomMismatch {
+                expected_title,
+                expected_checksum,
+                found_title,
+                found_checksum,
+            } => write!(
+                f,
+                "save state belongs to ROM '{found_title}' ({found_checksum:04X}), not '{expected_title}' ({expected_checksum:04X})"
+            ),
+            Self::StateModeMismatch { expected, found } => write!(
+                f,
+                "save state was created in {found:?} mode, but the device runs in {expected:?} mode"
+            ),
+            Self::InvalidPng(reason) => write!(f, "invalid PNG picture: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
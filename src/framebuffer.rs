@@ -61,35 +61,44 @@ impl Framebuffer {
         self.height
     }
 
+    /// Draw a frame of the screen, scaled to the height of the framebuffer and centered
     pub fn write(&self, buf: &[u8]) {
-        let src_w = crate::SCREEN_W as f32;
-        let src_h = crate::SCREEN_H as f32;
+        self.write_image(buf, crate::SCREEN_W, crate::SCREEN_H);
+    }
+
+    /// Draw an RGB24 image, scaled to fit the framebuffer and centered
+    pub fn write_image(&self, buf: &[u8], width: usize, height: usize) {
+        let src_w = width as f32;
+        let src_h = height as f32;
 
+        let dst_w = self.width as f32;
         let dst_h = self.height as f32;
 
-        // Scale factor to fit height
-        let scale = dst_h / src_h;
+        // Scale factor to fit height, unless the image is too wide
+        let scale = (dst_h / src_h).min(dst_w / src_w);
 
         let scaled_w = (src_w * scale).round() as usize;
-        let x_offset = (self.width - scaled_w) / 2;
+        let scaled_h = (src_h * scale).round() as usize;
+        let x_offset = self.width.saturating_sub(scaled_w) / 2;
+        let y_offset = self.height.saturating_sub(scaled_h) / 2;
 
-        for dy in 0..self.height {
+        for dy in 0..scaled_h.min(self.height - y_offset) {
             // map dy to sy in source buffer
             let sy = (dy as f32 / scale).floor() as usize;
-            if sy >= crate::SCREEN_H {
+            if sy >= height {
                 continue;
             }
 
             unsafe {
-                let row = self.ptr.add(dy * self.stride);
+                let row = self.ptr.add((y_offset + dy) * self.stride);
 
-                for dx in 0..scaled_w {
+                for dx in 0..scaled_w.min(self.width - x_offset) {
                     let sx = (dx as f32 / scale).floor() as usize;
-                    if sx >= crate::SCREEN_W {
+                    if sx >= width {
                         continue;
                     }
 
-                    let i = (sy * crate::SCREEN_W + sx) * 3;
+                    let i = (sy * width + sx) * 3;
 
                     let r = buf[i];
                     let g = buf[i + 1];
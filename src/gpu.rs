@@ -1,13 +1,23 @@
+mod fifo;
+mod viewer;
+
 use std::cmp::Ordering;
 
 use serde::{Deserialize, Serialize};
 
+use self::fifo::PixelFifo;
+pub use self::viewer::{VramImage, VramView};
 use crate::gbmode::GbMode;
+use crate::palette::DmgPalette;
 
 const VRAM_SIZE: usize = 0x4000;
 const VOAM_SIZE: usize = 0xA0;
 pub const SCREEN_W: usize = 160;
 pub const SCREEN_H: usize = 144;
+/// Dots of a frame, at the normal speed
+pub const FRAME_DOTS: u32 = 70224;
+/// Dots per second, at the normal speed
+pub const CLOCK_RATE: u32 = 4194304;
 
 #[derive(PartialEq, Copy, Clone, Serialize, Deserialize)]
 enum PrioType {
@@ -16,6 +26,19 @@ enum PrioType {
     Normal,
 }
 
+/// How the picture is drawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Renderer {
+    /// Draw each line at once when it ends, with a fixed timing: the fastest, but changes
+    /// made to the registers while a line is drawn are missed
+    #[default]
+    Scanline,
+    /// Draw the pixels one by one through the pixel FIFO like the hardware, so that changes
+    /// in the middle of a line show up, with the length of mode 3 depending on the scroll,
+    /// the window and the sprites, and with the STAT interrupts blocking each other
+    PixelFifo,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Gpu {
     mode: u8,
@@ -43,16 +66,13 @@ pub struct Gpu {
     palbr: u8,
     pal0r: u8,
     pal1r: u8,
-    palb: [u8; 4],
-    pal0: [u8; 4],
-    pal1: [u8; 4],
-    #[serde(with = "serde_arrays")]
-    vram: [u8; VRAM_SIZE],
+    // Kept on the heap so that moving or deserializing a `Gpu` stays cheap on the stack
+    vram: Vec<u8>,
     #[serde(with = "serde_arrays")]
     voam: [u8; VOAM_SIZE],
     cbgpal_inc: bool,
     cbgpal_ind: u8,
-    cbgpal: [[[u8; 3]; 4]; 8],
+    pub(crate) cbgpal: [[[u8; 3]; 4]; 8],
     csprit_inc: bool,
     csprit_ind: u8,
     csprit: [[[u8; 3]; 4]; 8],
@@ -61,10 +81,29 @@ pub struct Gpu {
     #[serde(with = "serde_arrays")]
     bgprio: [PrioType; SCREEN_W],
     pub updated: bool,
+    /// Number of frames drawn, wrapping around
+    pub frame_counter: u32,
     pub interrupt: u8,
     pub gbmode: GbMode,
+    /// In `ColorAsClassic` mode, color the classic shades with the first color palettes, as
+    /// set up by the CGB boot ROM. Otherwise the classic gray scale is used.
+    #[serde(default)]
+    pub compat_palettes: bool,
+    /// Colors of the classic shades, when they are not colored by the CGB palettes.
+    /// This is a display setting, so it is not part of save states.
+    #[serde(skip)]
+    pub dmg_palette: DmgPalette,
     hblanking: bool,
     first_frame: bool,
+    /// This is an emulation setting, so it is not part of save states.
+    #[serde(skip)]
+    renderer: Renderer,
+    /// Line being drawn by the pixel FIFO
+    #[serde(default)]
+    fifo: PixelFifo,
+    /// Level of the STAT interrupt line, only followed by the pixel FIFO
+    #[serde(default)]
+    stat_line: bool,
 }
 
 impl Gpu {
@@ -95,16 +134,16 @@ impl Gpu {
             palbr: 0,
             pal0r: 0,
             pal1r: 1,
-            palb: [0; 4],
-            pal0: [0; 4],
-            pal1: [0; 4],
-            vram: [0; VRAM_SIZE],
+            vram: vec![0; VRAM_SIZE],
             voam: [0; VOAM_SIZE],
             data: vec![0; SCREEN_W * SCREEN_H * 3],
             bgprio: [PrioType::Normal; SCREEN_W],
             updated: false,
+            frame_counter: 0,
             interrupt: 0,
             gbmode: GbMode::Classic,
+            compat_palettes: false,
+            dmg_palette: DmgPalette::default(),
             cbgpal_inc: false,
             cbgpal_ind: 0,
             cbgpal: [[[0u8; 3]; 4]; 8],
@@ -114,6 +153,9 @@ impl Gpu {
             vrambank: 0,
             hblanking: false,
             first_frame: false,
+            renderer: Renderer::Scanline,
+            fifo: PixelFifo::default(),
+            stat_line: false,
         }
     }
 
@@ -126,6 +168,10 @@ impl Gpu {
             return;
         }
         self.hblanking = false;
+        if self.renderer == Renderer::PixelFifo {
+            self.fifo_cycle(ticks);
+            return;
+        }
 
         let mut ticksleft = ticks;
 
@@ -167,7 +213,23 @@ impl Gpu {
         }
     }
 
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        if renderer == Renderer::Scanline {
+            // drop the line in progress, so that it starts over if the FIFO comes back
+            self.fifo = PixelFifo::default();
+        }
+        self.renderer = renderer;
+    }
+
     fn check_interrupt_lyc(&mut self) {
+        if self.renderer == Renderer::PixelFifo {
+            self.update_stat_line();
+            return;
+        }
         if self.lyc_inte && self.line == self.lyc {
             self.interrupt |= 0x02;
         }
@@ -176,9 +238,11 @@ impl Gpu {
     fn change_mode(&mut self, mode: u8) {
         self.mode = mode;
 
-        if match self.mode {
+        let interrupt = match self.mode {
             0 => {
-                self.renderscan();
+                if self.renderer == Renderer::Scanline {
+                    self.renderscan();
+                }
                 self.hblanking = true;
                 self.m0_inte
             }
@@ -187,6 +251,7 @@ impl Gpu {
                 self.wy_trigger = false;
                 self.interrupt |= 0x01;
                 self.updated = true;
+                self.frame_counter = self.frame_counter.wrapping_add(1);
                 self.first_frame = false;
                 self.m1_inte
             }
@@ -196,10 +261,16 @@ impl Gpu {
                     self.wy_trigger = true;
                     self.wy_pos = -1;
                 }
+                if self.renderer == Renderer::PixelFifo {
+                    self.start_fifo();
+                }
                 false
             }
             _ => false,
-        } {
+        };
+        if self.renderer == Renderer::PixelFifo {
+            self.update_stat_line();
+        } else if interrupt {
             self.interrupt |= 0x02;
         }
     }
@@ -299,6 +370,8 @@ impl Gpu {
                     self.mode = 0;
                     self.wy_trigger = false;
                     self.first_frame = true;
+                    self.fifo = PixelFifo::default();
+                    self.stat_line = false;
                     self.clear_screen();
                 }
                 if !orig_lcd_on && self.lcd_on {
@@ -311,6 +384,9 @@ impl Gpu {
                 self.m2_inte = v & 0x20 == 0x20;
                 self.m1_inte = v & 0x10 == 0x10;
                 self.m0_inte = v & 0x08 == 0x08;
+                if self.renderer == Renderer::PixelFifo {
+                    self.update_stat_line();
+                }
             }
             0xFF42 => self.scy = v,
             0xFF43 => self.scx = v,
@@ -322,15 +398,12 @@ impl Gpu {
             0xFF46 => panic!("0xFF46 should be handled by MMU"),
             0xFF47 => {
                 self.palbr = v;
-                self.update_pal();
             }
             0xFF48 => {
                 self.pal0r = v;
-                self.update_pal();
             }
             0xFF49 => {
                 self.pal1r = v;
-                self.update_pal();
             }
             0xFF4A => self.winy = v,
             0xFF4B => self.winx = v,
@@ -383,62 +456,93 @@ impl Gpu {
     }
 
     fn clear_screen(&mut self) {
-        for v in self.data.iter_mut() {
-            *v = 255;
+        let [r, g, b] = rgb888(self.blank_color());
+        for pixel in self.data.chunks_exact_mut(3) {
+            pixel.copy_from_slice(&[r, g, b]);
         }
         self.updated = true;
     }
 
-    fn update_pal(&mut self) {
-        for i in 0..4 {
-            self.palb[i] = Gpu::get_monochrome_pal_val(self.palbr, i);
-            self.pal0[i] = Gpu::get_monochrome_pal_val(self.pal0r, i);
-            self.pal1[i] = Gpu::get_monochrome_pal_val(self.pal1r, i);
+    /// Color of the screen when nothing is drawn
+    fn blank_color(&self) -> u32 {
+        if self.gbmode == GbMode::Color || self.compat_palettes {
+            0xFFFFFF
+        } else {
+            self.dmg_palette.bg[0]
+        }
+    }
+
+    /// Color the classic shades in `ColorAsClassic` mode with the given colors
+    pub fn set_compat_colors(&mut self, colors: &DmgPalette) {
+        fn rgb555(color: u32) -> [u8; 3] {
+            [
+                ((color >> 19) & 0x1F) as u8,
+                ((color >> 11) & 0x1F) as u8,
+                ((color >> 3) & 0x1F) as u8,
+            ]
+        }
+
+        self.cbgpal[0] = colors.bg.map(rgb555);
+        self.csprit[0] = colors.obj0.map(rgb555);
+        self.csprit[1] = colors.obj1.map(rgb555);
+        self.compat_palettes = true;
+    }
+
+    /// Color of the background, as drawn on the screen
+    fn bg_color(&self, palnr: usize, colnr: usize) -> [u8; 3] {
+        if self.gbmode == GbMode::Color {
+            cgb_rgb888(self.cbgpal[palnr][colnr])
+        } else if self.gbmode == GbMode::ColorAsClassic && self.compat_palettes {
+            cgb_rgb888(self.cbgpal[0][Gpu::get_pal_shade(self.palbr, colnr)])
+        } else {
+            rgb888(self.dmg_palette.bg[Gpu::get_pal_shade(self.palbr, colnr)])
         }
     }
 
-    fn get_monochrome_pal_val(value: u8, index: usize) -> u8 {
-        match (value >> (2 * index)) & 0x03 {
-            0 => 255,
-            1 => 192,
-            2 => 96,
-            _ => 0,
+    /// Color of a sprite, as drawn on the screen; `palnr` is the number of OBP on the classic
+    /// hardware
+    fn obj_color(&self, palnr: usize, colnr: usize) -> [u8; 3] {
+        if self.gbmode == GbMode::Color {
+            return cgb_rgb888(self.csprit[palnr][colnr]);
+        }
+        let register = if palnr == 0 { self.pal0r } else { self.pal1r };
+        let shade = Gpu::get_pal_shade(register, colnr);
+        if self.gbmode == GbMode::ColorAsClassic && self.compat_palettes {
+            cgb_rgb888(self.csprit[palnr][shade])
+        } else if palnr == 0 {
+            rgb888(self.dmg_palette.obj0[shade])
+        } else {
+            rgb888(self.dmg_palette.obj1[shade])
         }
     }
 
+    fn get_pal_shade(value: u8, index: usize) -> usize {
+        ((value >> (2 * index)) & 0x03) as usize
+    }
+
     fn renderscan(&mut self) {
         if self.first_frame {
             // The first frame from when lcd_on is set should not be drawn.
             return;
         }
 
+        let blank = self.blank_color();
         for x in 0..SCREEN_W {
-            self.setcolor(x, 255);
+            self.setcolor(x, blank);
             self.bgprio[x] = PrioType::Normal;
         }
         self.draw_bg();
         self.draw_sprites();
     }
 
-    fn setcolor(&mut self, x: usize, color: u8) {
-        self.data[self.line as usize * SCREEN_W * 3 + x * 3] = color;
-        self.data[self.line as usize * SCREEN_W * 3 + x * 3 + 1] = color;
-        self.data[self.line as usize * SCREEN_W * 3 + x * 3 + 2] = color;
+    fn setcolor(&mut self, x: usize, color: u32) {
+        let baseidx = self.line as usize * SCREEN_W * 3 + x * 3;
+        self.data[baseidx..baseidx + 3].copy_from_slice(&rgb888(color));
     }
 
     fn setrgb(&mut self, x: usize, r: u8, g: u8, b: u8) {
-        // Gameboy Color RGB correction
-        // Taken from the Gambatte emulator
-        // assume r, g and b are between 0 and 1F
         let baseidx = self.line as usize * SCREEN_W * 3 + x * 3;
-
-        let r = r as u32;
-        let g = g as u32;
-        let b = b as u32;
-
-        self.data[baseidx] = ((r * 13 + g * 2 + b) >> 1) as u8;
-        self.data[baseidx + 1] = ((g * 3 + b) << 1) as u8;
-        self.data[baseidx + 2] = ((r * 3 + g * 2 + b * 11) >> 1) as u8;
+        self.data[baseidx..baseidx + 3].copy_from_slice(&cgb_rgb888([r, g, b]));
     }
 
     fn draw_bg(&mut self) {
@@ -536,8 +640,11 @@ impl Gpu {
                 let g = self.cbgpal[palnr][colnr][1];
                 let b = self.cbgpal[palnr][colnr][2];
                 self.setrgb(x, r, g, b);
+            } else if self.gbmode == GbMode::ColorAsClassic && self.compat_palettes {
+                let [r, g, b] = self.cbgpal[0][Gpu::get_pal_shade(self.palbr, colnr)];
+                self.setrgb(x, r, g, b);
             } else {
-                let color = self.palb[colnr];
+                let color = self.dmg_palette.bg[Gpu::get_pal_shade(self.palbr, colnr)];
                 self.setcolor(x, color);
             }
         }
@@ -625,14 +732,25 @@ impl Gpu {
                     let g = self.csprit[c_palnr][colnr][1];
                     let b = self.csprit[c_palnr][colnr][2];
                     self.setrgb((spritex + x) as usize, r, g, b);
+                } else if self.gbmode == GbMode::ColorAsClassic && self.compat_palettes {
+                    if belowbg && self.bgprio[(spritex + x) as usize] != PrioType::Color0 {
+                        continue 'xloop;
+                    }
+                    let (palnr, palr) = if usepal1 {
+                        (1, self.pal1r)
+                    } else {
+                        (0, self.pal0r)
+                    };
+                    let [r, g, b] = self.csprit[palnr][Gpu::get_pal_shade(palr, colnr)];
+                    self.setrgb((spritex + x) as usize, r, g, b);
                 } else {
                     if belowbg && self.bgprio[(spritex + x) as usize] != PrioType::Color0 {
                         continue 'xloop;
                     }
                     let color = if usepal1 {
-                        self.pal1[colnr]
+                        self.dmg_palette.obj1[Gpu::get_pal_shade(self.pal1r, colnr)]
                     } else {
-                        self.pal0[colnr]
+                        self.dmg_palette.obj0[Gpu::get_pal_shade(self.pal0r, colnr)]
                     };
                     self.setcolor((spritex + x) as usize, color);
                 }
@@ -640,11 +758,36 @@ impl Gpu {
         }
     }
 
+    /// Video RAM bank mapped at 0x8000-0x9FFF
+    pub fn vram_bank(&self) -> usize {
+        self.vrambank
+    }
+
     pub fn may_hdma(&self) -> bool {
         self.hblanking
     }
 }
 
+/// Split a `0xRRGGBB` color into its components
+fn rgb888(color: u32) -> [u8; 3] {
+    [(color >> 16) as u8, (color >> 8) as u8, color as u8]
+}
+
+/// Convert a color of the CGB palettes, with components between 0 and 1F, to the colors of
+/// its screen
+pub(crate) fn cgb_rgb888([r, g, b]: [u8; 3]) -> [u8; 3] {
+    // Gameboy Color RGB correction
+    // Taken from the Gambatte emulator
+    let r = r as u32;
+    let g = g as u32;
+    let b = b as u32;
+    [
+        ((r * 13 + g * 2 + b) >> 1) as u8,
+        ((g * 3 + b) << 1) as u8,
+        ((r * 3 + g * 2 + b * 11) >> 1) as u8,
+    ]
+}
+
 // Functions to determine the order of sprites. Input is a tuple x-coord, OAM position
 // These function ensures that sprites with a higher priority are 'larger'
 fn dmg_sprite_order(a: &(i32, i32, u8), b: &(i32, i32, u8)) -> Ordering {
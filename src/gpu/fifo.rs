@@ -0,0 +1,501 @@
+//! Renderer drawing the pixels one by one, like the pixel FIFO of the hardware.
+//!
+//! A background fetcher reads the tiles of the background or the window, 8 pixels at a time,
+//! into the background FIFO, which shifts one pixel out per dot. Sprites of the line pause the
+//! shifting while they are fetched into the sprite FIFO, and the window restarts the fetcher,
+//! so that mode 3 gets longer with them and with the fine scroll. The registers are read when
+//! the hardware reads them: the palettes as each pixel is drawn, the scroll and the tile maps
+//! as each tile is fetched.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Gpu, SCREEN_W, rgb888};
+use crate::gbmode::GbMode;
+
+/// Dots of the first fetch of a line, which the hardware throws away
+const STARTUP_DOTS: u8 = 6;
+/// Dots of a sprite fetch, once the background fetcher has got its tile
+const SPRITE_DOTS: u8 = 6;
+/// Most sprites drawn on a line
+const SPRITES_PER_LINE: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum FetchStep {
+    #[default]
+    Tile,
+    DataLow,
+    DataHigh,
+    /// Waiting for the background FIFO to be empty
+    Push,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Fetcher {
+    step: FetchStep,
+    /// Each step but the push takes 2 dots
+    second_dot: bool,
+    /// Tile fetched, counted from the left of the line or of the window
+    column: u8,
+    tile: u8,
+    /// Attributes of the tile from the second bank, on the color hardware
+    attributes: u8,
+    low: u8,
+    high: u8,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct BgPixel {
+    color: u8,
+    palette: u8,
+    /// Drawn over the sprites, on the color hardware
+    priority: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct ObjPixel {
+    /// 0 is transparent
+    color: u8,
+    /// Color palette, or OBP number on the classic hardware
+    palette: u8,
+    behind_bg: bool,
+    /// Position in the OAM
+    index: u8,
+}
+
+/// Sprite found on the line by the OAM scan
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Sprite {
+    /// Coordinates as in the OAM, offset by 8 and 16
+    x: u8,
+    y: u8,
+    index: u8,
+}
+
+/// State of the line being drawn
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(super) struct PixelFifo {
+    active: bool,
+    /// Next pixel to draw
+    x: u8,
+    /// Dots left before the fetcher starts
+    startup: u8,
+    /// Pixels left to drop at the start of the line, for the fine scroll
+    discard: u8,
+    /// The window is drawn since its left edge
+    window: bool,
+    fetcher: Fetcher,
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+    /// Sprites of the line not fetched yet, in the order they are fetched
+    sprites: Vec<Sprite>,
+    /// Dots spent on the fetch of the first of `sprites`
+    sprite_dots: Option<u8>,
+}
+
+impl Gpu {
+    pub(super) fn fifo_cycle(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.modeclock += 1;
+            if self.modeclock >= 456 {
+                self.modeclock -= 456;
+                self.line = (self.line + 1) % 154;
+                if self.line < 144 {
+                    self.change_mode(2);
+                } else if self.mode != 1 {
+                    self.change_mode(1);
+                }
+            } else if self.line < 144 {
+                if self.mode == 2 && self.modeclock >= 80 {
+                    self.change_mode(3);
+                } else if self.mode == 3 && self.fifo_dot() {
+                    self.change_mode(0);
+                }
+            }
+            self.update_stat_line();
+        }
+    }
+
+    /// Request the STAT interrupt when one of its enabled sources turns on while none was.
+    ///
+    /// The sources share a single line, so a source turning on while another one holds the
+    /// line does not interrupt: with the mode 0 and mode 2 interrupts enabled, only the mode 0
+    /// interrupt is seen.
+    pub(super) fn update_stat_line(&mut self) {
+        let line = (self.lyc_inte && self.line == self.lyc)
+            || match self.mode {
+                0 => self.m0_inte,
+                // the mode 2 interrupt also fires as the vertical blank starts
+                1 => self.m1_inte || (self.m2_inte && self.line == 144 && self.modeclock == 0),
+                2 => self.m2_inte,
+                _ => false,
+            };
+        if line && !self.stat_line {
+            self.interrupt |= 0x02;
+        }
+        self.stat_line = line;
+    }
+
+    /// Scan the OAM for the sprites of the line and start drawing it
+    pub(super) fn start_fifo(&mut self) {
+        let line = self.line as i32;
+        let height = self.sprite_size as i32;
+        let mut sprites: Vec<Sprite> = self
+            .voam
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(_, attributes)| {
+                let top = attributes[0] as i32 - 16;
+                (top..top + height).contains(&line)
+            })
+            .take(SPRITES_PER_LINE)
+            .map(|(index, attributes)| Sprite {
+                x: attributes[1],
+                y: attributes[0],
+                index: index as u8,
+            })
+            .collect();
+        sprites.sort_by_key(|sprite| (sprite.x, sprite.index));
+
+        self.fifo = PixelFifo {
+            active: true,
+            startup: STARTUP_DOTS,
+            discard: self.scx & 0x07,
+            sprites,
+            ..PixelFifo::default()
+        };
+    }
+
+    /// Run the pixel FIFO for a dot of mode 3, returning true once the line is drawn
+    fn fifo_dot(&mut self) -> bool {
+        if !self.fifo.active {
+            // restored from a state saved by the other renderer
+            self.start_fifo();
+        }
+        if self.fifo.startup > 0 {
+            self.fifo.startup -= 1;
+            return false;
+        }
+
+        let x = self.fifo.x;
+        if !self.fifo.window
+            && self.win_on
+            && self.wy_trigger
+            && self.winx <= 166
+            && (x + 7 == self.winx || (x == 0 && self.winx < 7))
+        {
+            self.fifo.window = true;
+            self.wy_pos += 1;
+            self.fifo.bg.clear();
+            self.fifo.fetcher = Fetcher::default();
+            // the window is cut on the left of the screen
+            self.fifo.discard = 7u8.saturating_sub(self.winx);
+        }
+
+        if self.fifo.discard > 0 {
+            self.fetch_dot();
+            if self.fifo.bg.pop_front().is_some() {
+                self.fifo.discard -= 1;
+            }
+            return false;
+        }
+
+        if self.fifo.sprite_dots.is_none()
+            && self.sprite_on
+            && self.fifo.sprites.first().is_some_and(|s| s.x <= x + 8)
+        {
+            self.fifo.sprite_dots = Some(0);
+        }
+        if let Some(dots) = self.fifo.sprite_dots {
+            // the background fetcher finishes the tile it is fetching first
+            let fetching = match self.fifo.fetcher.step {
+                FetchStep::Tile => self.fifo.fetcher.second_dot,
+                FetchStep::DataLow | FetchStep::DataHigh => true,
+                FetchStep::Push => false,
+            };
+            if fetching || self.fifo.bg.is_empty() {
+                self.fetch_dot();
+            } else if dots + 1 < SPRITE_DOTS {
+                self.fifo.sprite_dots = Some(dots + 1);
+            } else {
+                let sprite = self.fifo.sprites.remove(0);
+                self.fetch_sprite(sprite);
+                self.fifo.sprite_dots = None;
+            }
+            return false;
+        }
+
+        self.fetch_dot();
+        let Some(bg) = self.fifo.bg.pop_front() else {
+            return false;
+        };
+        let obj = self.fifo.obj.pop_front();
+        if !self.first_frame {
+            // The first frame from when lcd_on is set should not be drawn.
+            let color = self.fifo_color(bg, obj);
+            let baseidx = (self.line as usize * SCREEN_W + x as usize) * 3;
+            self.data[baseidx..baseidx + 3].copy_from_slice(&color);
+        }
+        self.fifo.x += 1;
+        if self.fifo.x as usize == SCREEN_W {
+            self.fifo.active = false;
+            return true;
+        }
+        false
+    }
+
+    /// Run the background fetcher for a dot
+    fn fetch_dot(&mut self) {
+        let mut fetcher = self.fifo.fetcher;
+        if fetcher.step == FetchStep::Push {
+            if self.fifo.bg.is_empty() {
+                for i in 0..8 {
+                    let bit = if fetcher.attributes & 0x20 != 0 {
+                        i
+                    } else {
+                        7 - i
+                    };
+                    self.fifo.bg.push_back(BgPixel {
+                        color: ((fetcher.low >> bit) & 1) | (((fetcher.high >> bit) & 1) << 1),
+                        palette: fetcher.attributes & 0x07,
+                        priority: fetcher.attributes & 0x80 != 0,
+                    });
+                }
+                fetcher.column = fetcher.column.wrapping_add(1);
+                fetcher.step = FetchStep::Tile;
+            }
+        } else if !fetcher.second_dot {
+            fetcher.second_dot = true;
+        } else {
+            fetcher.second_dot = false;
+            match fetcher.step {
+                FetchStep::Tile => {
+                    let address = self.fetch_map_address(fetcher.column);
+                    fetcher.tile = self.vram[address];
+                    fetcher.attributes = match self.gbmode {
+                        GbMode::Color => self.vram[0x2000 + address],
+                        _ => 0,
+                    };
+                    fetcher.step = FetchStep::DataLow;
+                }
+                FetchStep::DataLow => {
+                    fetcher.low = self.vram[self.fetch_data_address(&fetcher)];
+                    fetcher.step = FetchStep::DataHigh;
+                }
+                FetchStep::DataHigh => {
+                    fetcher.high = self.vram[self.fetch_data_address(&fetcher) + 1];
+                    fetcher.step = FetchStep::Push;
+                }
+                FetchStep::Push => {}
+            }
+        }
+        self.fifo.fetcher = fetcher;
+    }
+
+    /// Offset in the video RAM of the tile number fetched
+    fn fetch_map_address(&self, column: u8) -> usize {
+        let (tilemap, x, y) = if self.fifo.window {
+            (self.win_tilemap, column, self.wy_pos as u8)
+        } else {
+            (
+                self.bg_tilemap,
+                (self.scx >> 3).wrapping_add(column),
+                self.scy.wrapping_add(self.line),
+            )
+        };
+        (tilemap as usize & 0x1FFF) + ((y as usize >> 3) & 31) * 32 + (x as usize & 31)
+    }
+
+    /// Offset in the video RAM of the first byte of the tile row fetched
+    fn fetch_data_address(&self, fetcher: &Fetcher) -> usize {
+        let row = match self.fifo.window {
+            true => self.wy_pos as u8,
+            false => self.scy.wrapping_add(self.line),
+        } as usize
+            & 0x07;
+        let row = if fetcher.attributes & 0x40 != 0 {
+            7 - row
+        } else {
+            row
+        };
+        let tile = match self.tilebase {
+            0x8000 => fetcher.tile as usize,
+            _ => (fetcher.tile as i8 as i16 + 256) as usize,
+        };
+        let bank = if fetcher.attributes & 0x08 != 0 {
+            0x2000
+        } else {
+            0
+        };
+        bank + tile * 16 + row * 2
+    }
+
+    /// Merge the pixels of a sprite into the sprite FIFO
+    fn fetch_sprite(&mut self, sprite: Sprite) {
+        let height = self.sprite_size as u8;
+        let index = sprite.index as usize * 4;
+        let flags = self.voam[index + 3];
+        let row = self.line.wrapping_add(16).wrapping_sub(sprite.y) & (height - 1);
+        let row = if flags & 0x40 != 0 {
+            height - 1 - row
+        } else {
+            row
+        };
+        let tile = match height {
+            16 => self.voam[index + 2] & 0xFE,
+            _ => self.voam[index + 2],
+        };
+        let (palette, bank) = match self.gbmode {
+            GbMode::Color => (flags & 0x07, if flags & 0x08 != 0 { 0x2000 } else { 0 }),
+            _ => ((flags >> 4) & 1, 0),
+        };
+        let address = bank + tile as usize * 16 + row as usize * 2;
+        let (low, high) = (self.vram[address], self.vram[address + 1]);
+
+        // pixels left of the screen, or already drawn when the fetch came late
+        let skip = (self.fifo.x + 8).saturating_sub(sprite.x) as usize;
+        self.fifo.obj.resize(8, ObjPixel::default());
+        for i in skip..8 {
+            let bit = if flags & 0x20 != 0 { i } else { 7 - i };
+            let color = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+            let slot = &mut self.fifo.obj[i - skip];
+            // the first sprite fetched wins on the classic hardware, the first in the OAM on
+            // the color hardware
+            if color != 0
+                && (slot.color == 0 || (self.gbmode == GbMode::Color && sprite.index < slot.index))
+            {
+                *slot = ObjPixel {
+                    color,
+                    palette,
+                    behind_bg: flags & 0x80 != 0,
+                    index: sprite.index,
+                };
+            }
+        }
+    }
+
+    /// Color of a pixel from the background and sprite FIFOs, as drawn on the screen
+    fn fifo_color(&self, bg: BgPixel, obj: Option<ObjPixel>) -> [u8; 3] {
+        // the classic hardware turns the background and the window off with LCDC bit 0
+        let bg_on = self.gbmode == GbMode::Color || self.lcdc0;
+        if let Some(obj) = obj
+            && obj.color != 0
+            && self.sprite_on
+        {
+            let hidden = bg_on
+                && bg.color != 0
+                && match self.gbmode {
+                    GbMode::Color => self.lcdc0 && (bg.priority || obj.behind_bg),
+                    _ => obj.behind_bg,
+                };
+            if !hidden {
+                return self.obj_color(obj.palette as usize, obj.color as usize);
+            }
+        }
+        if bg_on {
+            self.bg_color(bg.palette as usize, bg.color as usize)
+        } else {
+            rgb888(self.blank_color())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gpu::{Gpu, Renderer, SCREEN_W};
+
+    /// GPU with the LCD on, drawing tile 0 everywhere
+    fn fifo_gpu() -> Gpu {
+        let mut gpu = Gpu::new();
+        gpu.set_renderer(Renderer::PixelFifo);
+        // every pixel of tile 0 has color 3
+        gpu.vram[..16].fill(0xFF);
+        gpu.wb(0xFF47, 0xE4);
+        gpu.wb(0xFF40, 0x91);
+        gpu
+    }
+
+    /// Run to the start of mode 3 on the given line
+    fn run_to_mode3(gpu: &mut Gpu, line: u8) {
+        while gpu.line != line || gpu.mode != 3 {
+            gpu.do_cycle(1);
+        }
+    }
+
+    /// Dots spent in mode 3 on the given line
+    fn mode3_length(gpu: &mut Gpu, line: u8) -> u32 {
+        run_to_mode3(gpu, line);
+        let mut dots = 0;
+        while gpu.mode == 3 {
+            gpu.do_cycle(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    fn pixel(gpu: &Gpu, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * SCREEN_W + x) * 3;
+        gpu.data[i..i + 3].try_into().unwrap()
+    }
+
+    #[test]
+    fn mode3_length_depends_on_scroll_and_sprites() {
+        let mut gpu = fifo_gpu();
+        assert_eq!(mode3_length(&mut gpu, 1), 172);
+
+        gpu.wb(0xFF43, 3);
+        assert_eq!(mode3_length(&mut gpu, 2), 175);
+
+        gpu.wb(0xFF43, 0);
+        gpu.wb(0xFF40, 0x93);
+        // sprite on line 3, at the left of the screen
+        gpu.wb(0xFE00, 3 + 16);
+        gpu.wb(0xFE01, 8);
+        assert!(mode3_length(&mut gpu, 3) >= 172 + 6);
+        // sprites left alone while they are off
+        gpu.wb(0xFF40, 0x91);
+        assert_eq!(mode3_length(&mut gpu, 4), 172);
+    }
+
+    #[test]
+    fn palette_change_shows_in_the_middle_of_a_line() {
+        let mut gpu = fifo_gpu();
+        run_to_mode3(&mut gpu, 10);
+        while gpu.fifo.x < 80 {
+            gpu.do_cycle(1);
+        }
+        gpu.wb(0xFF47, 0x00);
+        while gpu.mode == 3 {
+            gpu.do_cycle(1);
+        }
+
+        let shades = gpu.dmg_palette.bg.map(super::rgb888);
+        assert_eq!(pixel(&gpu, 10, 10), shades[3]);
+        assert_eq!(pixel(&gpu, 150, 10), shades[0]);
+    }
+
+    #[test]
+    fn stat_sources_block_each_other() {
+        // mode 0 and mode 2 interrupts are enabled, and they follow each other
+        let stat_interrupts = |renderer| {
+            let mut gpu = Gpu::new();
+            gpu.set_renderer(renderer);
+            gpu.wb(0xFF40, 0x91);
+            gpu.wb(0xFF41, 0x28);
+            run_to_mode3(&mut gpu, 20);
+            gpu.interrupt = 0;
+            let mut interrupts = 0;
+            for _ in 0..456 * 10 {
+                gpu.do_cycle(1);
+                if gpu.interrupt & 0x02 != 0 {
+                    interrupts += 1;
+                }
+                gpu.interrupt = 0;
+            }
+            interrupts
+        };
+        assert_eq!(stat_interrupts(Renderer::PixelFifo), 10);
+        assert_eq!(stat_interrupts(Renderer::Scanline), 20);
+    }
+}
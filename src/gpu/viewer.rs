@@ -0,0 +1,289 @@
+use super::{Gpu, rgb888};
+use crate::gbmode::GbMode;
+
+/// Color between the parts of the views
+const BACKGROUND: [u8; 3] = [0x40, 0x40, 0x40];
+/// Color of the outline of the visible area on the background map
+const VIEWPORT: [u8; 3] = [0xFF, 0x00, 0x00];
+
+/// Tiles held by a bank of video RAM
+const TILES: usize = 384;
+/// Tiles per row in the tile view
+const TILES_PER_ROW: usize = 16;
+/// Space between the banks, the sprites and the palettes
+const GAP: usize = 4;
+/// Size of a color in the palette view
+const SWATCH: usize = 12;
+
+/// Debug view of the video memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VramView {
+    /// Tile data of both banks, in the classic shades without palette
+    Tiles,
+    /// Tile map of the background, with the visible area outlined
+    BackgroundMap,
+    /// Tile map of the window
+    WindowMap,
+    /// The 40 sprites of the OAM, in their order
+    Sprites,
+    /// Background palettes on the left, sprite palettes on the right
+    Palettes,
+}
+
+impl VramView {
+    pub const ALL: [VramView; 5] = [
+        VramView::Tiles,
+        VramView::BackgroundMap,
+        VramView::WindowMap,
+        VramView::Sprites,
+        VramView::Palettes,
+    ];
+
+    pub fn next(self) -> VramView {
+        let i = Self::ALL.iter().position(|v| *v == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    pub fn previous(self) -> VramView {
+        let i = Self::ALL.iter().position(|v| *v == self).unwrap();
+        Self::ALL[(i + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VramView::Tiles => "tiles",
+            VramView::BackgroundMap => "background map",
+            VramView::WindowMap => "window map",
+            VramView::Sprites => "sprites",
+            VramView::Palettes => "palettes",
+        }
+    }
+}
+
+/// Picture of a [`VramView`], in RGB24 like the screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VramImage {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl VramImage {
+    fn new(width: usize, height: usize) -> VramImage {
+        VramImage {
+            width,
+            height,
+            data: BACKGROUND.repeat(width * height),
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let i = (y * self.width + x) * 3;
+            self.data[i..i + 3].copy_from_slice(&color);
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * self.width + x) * 3;
+        self.data[i..i + 3].try_into().unwrap()
+    }
+}
+
+impl Gpu {
+    /// Render a debug view of the video memory
+    pub fn render_view(&self, view: VramView) -> VramImage {
+        match view {
+            VramView::Tiles => self.render_tiles(),
+            VramView::BackgroundMap => self.render_map(self.bg_tilemap, true),
+            VramView::WindowMap => self.render_map(self.win_tilemap, false),
+            VramView::Sprites => self.render_sprites(),
+            VramView::Palettes => self.render_palettes(),
+        }
+    }
+
+    fn banks(&self) -> usize {
+        if self.gbmode == GbMode::Color { 2 } else { 1 }
+    }
+
+    /// Color number of a pixel of one of the 384 tiles of a bank
+    fn tile_pixel(&self, bank: usize, tile: usize, x: usize, y: usize) -> usize {
+        let address = bank * 0x2000 + tile * 16 + y * 2;
+        let bit = 7 - x;
+        (((self.vram[address] >> bit) & 1) | (((self.vram[address + 1] >> bit) & 1) << 1)) as usize
+    }
+
+    fn render_tiles(&self) -> VramImage {
+        let bank_width = TILES_PER_ROW * 8;
+        let banks = self.banks();
+        let mut image = VramImage::new(
+            banks * bank_width + (banks - 1) * GAP,
+            TILES / TILES_PER_ROW * 8,
+        );
+        for bank in 0..banks {
+            for tile in 0..TILES {
+                let left = bank * (bank_width + GAP) + (tile % TILES_PER_ROW) * 8;
+                let top = (tile / TILES_PER_ROW) * 8;
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let colnr = self.tile_pixel(bank, tile, x, y);
+                        image.set(left + x, top + y, rgb888(self.dmg_palette.bg[colnr]));
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    fn render_map(&self, tilemap: u16, viewport: bool) -> VramImage {
+        let mut image = VramImage::new(256, 256);
+        let map = tilemap as usize & 0x1FFF;
+        for i in 0..32 * 32 {
+            let tilenr = self.vram[map + i];
+            let flags = match self.gbmode {
+                GbMode::Color => self.vram[0x2000 + map + i] as usize,
+                _ => 0,
+            };
+            let tile = match self.tilebase {
+                0x8000 => tilenr as usize,
+                _ => (tilenr as i8 as i16 + 256) as usize,
+            };
+            let bank = (flags >> 3) & 1;
+            for y in 0..8 {
+                for x in 0..8 {
+                    let tx = if flags & (1 << 5) != 0 { 7 - x } else { x };
+                    let ty = if flags & (1 << 6) != 0 { 7 - y } else { y };
+                    let colnr = self.tile_pixel(bank, tile, tx, ty);
+                    let color = self.bg_color(flags & 0x07, colnr);
+                    image.set((i % 32) * 8 + x, (i / 32) * 8 + y, color);
+                }
+            }
+        }
+
+        if viewport {
+            let (scx, scy) = (self.scx as usize, self.scy as usize);
+            for x in 0..super::SCREEN_W {
+                image.set((scx + x) % 256, scy, VIEWPORT);
+                image.set((scx + x) % 256, (scy + super::SCREEN_H - 1) % 256, VIEWPORT);
+            }
+            for y in 0..super::SCREEN_H {
+                image.set(scx, (scy + y) % 256, VIEWPORT);
+                image.set((scx + super::SCREEN_W - 1) % 256, (scy + y) % 256, VIEWPORT);
+            }
+        }
+        image
+    }
+
+    fn render_sprites(&self) -> VramImage {
+        // 8 by 5 cells, large enough for 8x16 sprites
+        let (cell_width, cell_height) = (8 + GAP, 16 + GAP);
+        let mut image = VramImage::new(8 * cell_width + GAP, 5 * cell_height + GAP);
+        let height = self.sprite_size as usize;
+        for index in 0..40 {
+            let attributes = &self.voam[index * 4..index * 4 + 4];
+            let tilenum = match height {
+                16 => attributes[2] & 0xFE,
+                _ => attributes[2],
+            } as usize;
+            let flags = attributes[3] as usize;
+            let (palnr, bank) = match self.gbmode {
+                GbMode::Color => (flags & 0x07, (flags >> 3) & 1),
+                _ => ((flags >> 4) & 1, 0),
+            };
+            let left = GAP + (index % 8) * cell_width;
+            let top = GAP + (index / 8) * cell_height;
+            for y in 0..height {
+                for x in 0..8 {
+                    let tx = if flags & (1 << 5) != 0 { 7 - x } else { x };
+                    let ty = if flags & (1 << 6) != 0 {
+                        height - 1 - y
+                    } else {
+                        y
+                    };
+                    let colnr = self.tile_pixel(bank, tilenum + ty / 8, tx, ty % 8);
+                    let color = match colnr {
+                        // transparent
+                        0 => rgb888(self.blank_color()),
+                        _ => self.obj_color(palnr, colnr),
+                    };
+                    image.set(left + x, top + y, color);
+                }
+            }
+        }
+        image
+    }
+
+    fn render_palettes(&self) -> VramImage {
+        let (bg, obj) = match self.gbmode {
+            GbMode::Color => (8, 8),
+            _ => (1, 2),
+        };
+        let column = 4 * SWATCH;
+        let mut image = VramImage::new(2 * column + 3 * GAP, 8 * (SWATCH + GAP) + GAP);
+        for (left, count, sprites) in [(GAP, bg, false), (2 * GAP + column, obj, true)] {
+            for palnr in 0..count {
+                for colnr in 0..4 {
+                    let color = match sprites {
+                        false => self.bg_color(palnr, colnr),
+                        true => self.obj_color(palnr, colnr),
+                    };
+                    let top = GAP + palnr * (SWATCH + GAP);
+                    for y in 0..SWATCH {
+                        for x in 0..SWATCH {
+                            image.set(left + colnr * SWATCH + x, top + y, color);
+                        }
+                    }
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{VramImage, VramView};
+    use crate::gbmode::GbMode;
+    use crate::gpu::Gpu;
+
+    #[test]
+    fn tiles_view_shows_both_banks() {
+        let mut gpu = Gpu::new();
+        gpu.gbmode = GbMode::Color;
+        // first row of tile 1 of bank 1: colors 3, 2, 1, 0, ...
+        gpu.vram[0x2000 + 16] = 0b1010_0000;
+        gpu.vram[0x2000 + 17] = 0b1100_0000;
+
+        let image = gpu.render_view(VramView::Tiles);
+        assert_eq!((image.width, image.height), (260, 192));
+        let shades = gpu.dmg_palette.bg.map(super::rgb888);
+        let left = 128 + 4 + 8;
+        assert_eq!(image.get(left, 0), shades[3]);
+        assert_eq!(image.get(left + 1, 0), shades[2]);
+        assert_eq!(image.get(left + 2, 0), shades[1]);
+        assert_eq!(image.get(left + 3, 0), shades[0]);
+    }
+
+    #[test]
+    fn sprites_view_follows_flags() {
+        let mut gpu = Gpu::new();
+        gpu.pal1r = 0b1110_0100;
+        // tile 2: a single pixel of color 1 at the top left
+        gpu.vram[32] = 0x80;
+        // sprite 9, flipped horizontally, using OBP1
+        gpu.voam[36..40].copy_from_slice(&[16, 8, 2, 0x30]);
+
+        let image: VramImage = gpu.render_view(VramView::Sprites);
+        let (left, top) = (4 + 12, 4 + 20);
+        let color = super::rgb888(gpu.dmg_palette.obj1[1]);
+        assert_eq!(image.get(left + 7, top), color);
+        assert_ne!(image.get(left, top), color);
+    }
+
+    #[test]
+    fn views_cycle() {
+        assert_eq!(VramView::Palettes.next(), VramView::Tiles);
+        assert_eq!(VramView::Tiles.previous(), VramView::Palettes);
+        assert_eq!(VramView::Tiles.next(), VramView::BackgroundMap);
+    }
+}
@@ -0,0 +1,250 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Logo every cartridge holds at 0x104-0x133, checked by the boot ROM
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// Size of the header, including everything before it
+pub(crate) const HEADER_END: usize = 0x150;
+
+/// Old licensee code telling that the new licensee code is used instead
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+/// Game Boy Color support declared at 0x143
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CgbSupport {
+    /// Made for the classic Game Boy
+    None,
+    /// Enhanced for the Game Boy Color, but also runs on the classic Game Boy
+    Compatible,
+    /// Only runs on the Game Boy Color
+    Only,
+}
+
+/// Region the cartridge was sold in, declared at 0x14A
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Region {
+    Japan,
+    Overseas,
+}
+
+/// Publisher of the game
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Licensee {
+    /// Code at 0x14B, used by older cartridges
+    Old(u8),
+    /// Two character code at 0x144-0x145, used when the old code is 0x33
+    New(String),
+}
+
+impl fmt::Display for Licensee {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Old(code) => write!(f, "{code:02X}"),
+            Self::New(code) => write!(f, "{code}"),
+        }
+    }
+}
+
+/// Cartridge header, found at 0x100-0x14F of every ROM
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CartridgeHeader {
+    /// Code run after the boot ROM, usually a jump to the start of the game (0x100-0x103)
+    pub entry_point: [u8; 4],
+    /// The logo at 0x104-0x133 is the one checked by the boot ROM
+    pub logo_valid: bool,
+    /// Title of the game, up to the first NUL: 16 characters, or 11 on color cartridges
+    pub title: String,
+    /// Four letter code of the game on color cartridges (0x13F-0x142)
+    pub manufacturer_code: Option<String>,
+    /// Game Boy Color support (0x143)
+    pub cgb: CgbSupport,
+    /// Super Game Boy functions are supported (0x146)
+    pub sgb: bool,
+    /// Cartridge type, telling the memory bank controller and the hardware on the cartridge
+    /// (0x147)
+    pub cartridge_type: u8,
+    /// Size of the ROM in bytes, unless the code at 0x148 is unknown
+    pub rom_size: Option<usize>,
+    /// Size of the cartridge RAM in bytes, unless the code at 0x149 is unknown
+    pub ram_size: Option<usize>,
+    /// Destination code (0x14A)
+    pub region: Region,
+    pub licensee: Licensee,
+    /// Version of the game (0x14C)
+    pub version: u8,
+    /// Checksum of 0x134-0x14C, checked by the boot ROM (0x14D)
+    pub header_checksum: u8,
+    /// Sum of all the other bytes of the ROM, big endian; not checked by the hardware
+    /// (0x14E-0x14F)
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    /// Parse the header of `rom`, which must at least hold the first 0x150 bytes of the ROM
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader> {
+        if rom.len() < HEADER_END {
+            return Err(Error::RomTooSmall { size: rom.len() });
+        }
+        Ok(Self::read(rom))
+    }
+
+    /// Parse a header from the 0x150 first bytes of `rom`
+    pub(crate) fn read(rom: &[u8]) -> CartridgeHeader {
+        let cgb = match rom[0x143] {
+            0xC0 => CgbSupport::Only,
+            v if v & 0x80 != 0 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+        let title_size = if cgb == CgbSupport::None { 16 } else { 11 };
+        let title = rom[0x134..0x134 + title_size]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect();
+        let manufacturer_code = &rom[0x13F..0x143];
+        let manufacturer_code = (cgb != CgbSupport::None
+            && manufacturer_code
+                .iter()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()))
+        .then(|| String::from_utf8_lossy(manufacturer_code).into_owned());
+
+        let rom_size = match rom[0x148] {
+            code @ 0x00..=0x08 => Some(0x8000 << code),
+            0x52 => Some(72 * 0x4000),
+            0x53 => Some(80 * 0x4000),
+            0x54 => Some(96 * 0x4000),
+            _ => None,
+        };
+        let ram_size = match rom[0x149] {
+            0x00 => Some(0),
+            0x01 => Some(0x800),
+            0x02 => Some(0x2000),
+            0x03 => Some(0x8000),
+            0x04 => Some(0x20000),
+            0x05 => Some(0x10000),
+            _ => None,
+        };
+        let licensee = match rom[0x14B] {
+            USE_NEW_LICENSEE => Licensee::New(String::from_utf8_lossy(&rom[0x144..0x146]).into()),
+            code => Licensee::Old(code),
+        };
+
+        CartridgeHeader {
+            entry_point: rom[0x100..0x104].try_into().unwrap(),
+            logo_valid: rom[0x104..0x134] == NINTENDO_LOGO,
+            title,
+            manufacturer_code,
+            cgb,
+            sgb: rom[0x146] == 0x03,
+            cartridge_type: rom[0x147],
+            rom_size,
+            ram_size,
+            region: if rom[0x14A] == 0x00 {
+                Region::Japan
+            } else {
+                Region::Overseas
+            },
+            licensee,
+            version: rom[0x14C],
+            header_checksum: rom[0x14D],
+            global_checksum: u16::from_be_bytes([rom[0x14E], rom[0x14F]]),
+        }
+    }
+
+    /// Name of the cartridge type, e.g. `MBC1+RAM+BATTERY`
+    pub fn cartridge_type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0B => "MMM01",
+            0x0C => "MMM01+RAM",
+            0x0D => "MMM01+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFD => "BANDAI TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _ => "unknown",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CartridgeHeader, CgbSupport, Licensee, NINTENDO_LOGO, Region};
+
+    #[test]
+    fn parse_color_header() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x134..0x13F].copy_from_slice(b"POKEMON_SLV");
+        rom[0x13F..0x143].copy_from_slice(b"AAXE");
+        rom[0x143] = 0x80;
+        rom[0x144..0x146].copy_from_slice(b"01");
+        rom[0x146] = 0x03;
+        rom[0x147] = 0x10;
+        rom[0x148] = 0x06;
+        rom[0x149] = 0x03;
+        rom[0x14A] = 0x01;
+        rom[0x14B] = 0x33;
+        rom[0x14C] = 0x02;
+        rom[0x14E..0x150].copy_from_slice(&[0x12, 0x34]);
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.logo_valid);
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb, CgbSupport::Compatible);
+        assert!(header.sgb);
+        assert_eq!(header.cartridge_type_name(), "MBC3+TIMER+RAM+BATTERY");
+        assert_eq!(header.rom_size, Some(0x200000));
+        assert_eq!(header.ram_size, Some(0x8000));
+        assert_eq!(header.region, Region::Overseas);
+        assert_eq!(header.licensee, Licensee::New("01".to_string()));
+        assert_eq!(header.version, 2);
+        assert_eq!(header.global_checksum, 0x1234);
+    }
+
+    #[test]
+    fn parse_classic_header() {
+        let mut rom = vec![0; 0x150];
+        rom[0x134..0x144].copy_from_slice(b"SIXTEEN LETTERS!");
+        rom[0x14B] = 0x01;
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.logo_valid);
+        assert_eq!(header.title, "SIXTEEN LETTERS!");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert_eq!(header.region, Region::Japan);
+        assert_eq!(header.licensee, Licensee::Old(0x01));
+        assert!(CartridgeHeader::parse(&rom[..0x14F]).is_err());
+    }
+}
@@ -0,0 +1,316 @@
+//! Runner of test ROMs without screen, sound or keys, telling whether they passed.
+//!
+//! The test suites report their results in different ways, each followed by a [`Check`]:
+//!
+//! ```no_run
+//! use rboy::headless::{Check, Headless, Outcome};
+//!
+//! let rom = std::fs::read("roms/instr_timing.gb").unwrap();
+//! let mut runner = Headless::new(rom, false).unwrap();
+//! assert_eq!(runner.run(&Check::Serial, 600).unwrap(), Outcome::Passed);
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::device::Device;
+use crate::gbmode::GbMode;
+use crate::gpu::{FRAME_DOTS, SCREEN_H, SCREEN_W, cgb_rgb888};
+use crate::palette::DmgPalette;
+use crate::png::{self, Image};
+use crate::serial::SerialCallback;
+use crate::{Error, Result};
+
+/// `LD B,B`, used by the test ROMs as a breakpoint for the emulators
+const LD_B_B: u8 = 0x40;
+/// Registers B, C, D, E, H and L of a passed Mooneye test
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+/// Registers B, C, D, E, H and L of a failed Mooneye test
+const MOONEYE_FAILED: [u8; 6] = [0x42; 6];
+/// Shades of the reference pictures of the classic hardware
+const REFERENCE_SHADES: [u32; 4] = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000];
+
+/// How a test ROM reports its result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Check {
+    /// The result is printed on the serial port, ending with "Passed" or "Failed", like the
+    /// tests of Blargg
+    Serial,
+    /// `LD B,B` is executed with the Fibonacci numbers 3, 5, 8, 13, 21 and 34 in B, C, D, E,
+    /// H and L on success, or 0x42 in all of them on failure, like the tests of Mooneye
+    Fibonacci,
+    /// Once `LD B,B` is executed, the screen matches the reference PNG picture, like
+    /// dmg-acid2 and cgb-acid2.
+    ///
+    /// The reference of the classic hardware is in the shades FFFFFF, AAAAAA, 555555 and
+    /// 000000. The reference of the color hardware has the colors without the correction
+    /// of the screen, which is applied before comparing.
+    Screen(Vec<u8>),
+}
+
+/// Result of a test ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// The ROM reported a failure, described by the message
+    Failed(String),
+    /// The ROM did not report a result in time
+    TimedOut,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passed => write!(f, "passed"),
+            Self::Failed(message) => write!(f, "failed: {message}"),
+            Self::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+struct SerialCapture(Arc<Mutex<Vec<u8>>>);
+
+impl SerialCallback for SerialCapture {
+    fn call(&mut self, value: u8) -> Option<u8> {
+        self.0.lock().unwrap().push(value);
+        None
+    }
+}
+
+/// Device running a test ROM, capturing what it prints on the serial port
+pub struct Headless {
+    device: Device,
+    serial: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Headless {
+    /// Run the ROM on the classic hardware, or on the color hardware if `color` is set
+    pub fn new(rom: Vec<u8>, color: bool) -> Result<Headless> {
+        let mut device = match color {
+            true => Device::new_cgb_from_buffer(rom, true)?,
+            false => Device::new_from_buffer(rom, true)?,
+        };
+        device.set_dmg_palette(DmgPalette::uniform(REFERENCE_SHADES));
+        let serial = Arc::new(Mutex::new(Vec::new()));
+        device.set_serial_callback(Box::new(SerialCapture(serial.clone())));
+        Ok(Headless { device, serial })
+    }
+
+    /// Device running the ROM, to change its settings before running it
+    pub fn device(&mut self) -> &mut Device {
+        &mut self.device
+    }
+
+    /// Text printed on the serial port so far
+    pub fn serial_output(&self) -> String {
+        String::from_utf8_lossy(&self.serial.lock().unwrap()).into_owned()
+    }
+
+    /// Run the ROM for at most `max_frames` frames of emulated time, until it reports its
+    /// result.
+    ///
+    /// Fails if the reference picture of a [`Check::Screen`] cannot be read.
+    pub fn run(&mut self, check: &Check, max_frames: u32) -> Result<Outcome> {
+        let reference = match check {
+            Check::Screen(reference) => Some(self.screen_reference(reference)?),
+            _ => None,
+        };
+
+        let limit = max_frames as u64 * FRAME_DOTS as u64;
+        let mut dots = 0;
+        while dots < limit {
+            if *check != Check::Serial && self.at_breakpoint() {
+                return Ok(match &reference {
+                    Some(reference) => self.compare_screen(reference),
+                    None => self.fibonacci_outcome(),
+                });
+            }
+            let frame = dots / FRAME_DOTS as u64;
+            dots += self.device.do_cycle() as u64;
+            if *check == Check::Serial
+                && dots / FRAME_DOTS as u64 != frame
+                && let Some(outcome) = self.serial_outcome()
+            {
+                return Ok(outcome);
+            }
+        }
+        Ok(match check {
+            Check::Serial => self.serial_outcome().unwrap_or(Outcome::TimedOut),
+            _ => Outcome::TimedOut,
+        })
+    }
+
+    /// The next instruction is `LD B,B`
+    fn at_breakpoint(&mut self) -> bool {
+        let state = self.device.cpu_state();
+        !state.halted && self.device.peek_byte(state.pc) == LD_B_B
+    }
+
+    fn serial_outcome(&self) -> Option<Outcome> {
+        let output = self.serial_output();
+        if output.contains("Passed") {
+            Some(Outcome::Passed)
+        } else if let Some(at) = output.find("Failed")
+            && output[at..].contains('\n')
+        {
+            Some(Outcome::Failed(output.trim().to_string()))
+        } else {
+            None
+        }
+    }
+
+    fn fibonacci_outcome(&self) -> Outcome {
+        let state = self.device.cpu_state();
+        let registers = [state.b, state.c, state.d, state.e, state.h, state.l];
+        if registers == FIBONACCI {
+            Outcome::Passed
+        } else if registers == MOONEYE_FAILED {
+            Outcome::Failed("the ROM reported a failure".to_string())
+        } else {
+            Outcome::Failed(format!(
+                "unexpected registers B={:02X} C={:02X} D={:02X} E={:02X} H={:02X} L={:02X}",
+                state.b, state.c, state.d, state.e, state.h, state.l
+            ))
+        }
+    }
+
+    /// Decode the reference picture, in the colors of the screen
+    fn screen_reference(&self, png: &[u8]) -> Result<Image> {
+        let mut image = png::decode(png)?;
+        if (image.width, image.height) != (SCREEN_W, SCREEN_H) {
+            return Err(Error::InvalidPng(
+                "the picture is not the size of the screen",
+            ));
+        }
+        if self.device.mode() == GbMode::Color {
+            for pixel in image.data.chunks_exact_mut(3) {
+                let color = cgb_rgb888([pixel[0] >> 3, pixel[1] >> 3, pixel[2] >> 3]);
+                pixel.copy_from_slice(&color);
+            }
+        }
+        Ok(image)
+    }
+
+    fn compare_screen(&mut self, reference: &Image) -> Outcome {
+        // let the frame being drawn complete
+        self.device.check_and_reset_gpu_updated();
+        let mut dots = 0;
+        while !self.device.check_and_reset_gpu_updated() && dots < 2 * FRAME_DOTS as u64 {
+            dots += self.device.do_cycle() as u64;
+        }
+
+        let screen = self.device.get_gpu_data();
+        let mut differences = screen
+            .chunks_exact(3)
+            .zip(reference.data.chunks_exact(3))
+            .enumerate()
+            .filter(|(_, (actual, expected))| actual != expected);
+        let Some((first, (actual, expected))) = differences.next() else {
+            return Outcome::Passed;
+        };
+        let hex = |pixel: &[u8]| format!("{:02X}{:02X}{:02X}", pixel[0], pixel[1], pixel[2]);
+        Outcome::Failed(format!(
+            "{} pixels differ from the reference, the first at ({}, {}): expected {}, got {}",
+            differences.count() + 1,
+            first % SCREEN_W,
+            first / SCREEN_W,
+            hex(expected),
+            hex(actual)
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Check, Headless, Outcome};
+    use crate::gpu::{SCREEN_H, SCREEN_W};
+    use crate::png;
+
+    /// ROM running `code` from the entry point
+    fn rom(code: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + code.len()].copy_from_slice(code);
+        rom
+    }
+
+    /// `LD B,B` with the given registers, then an endless loop
+    fn breakpoint_rom([b, c, d, e, h, l]: [u8; 6]) -> Vec<u8> {
+        rom(&[
+            0x06, b, 0x0E, c, 0x16, d, 0x1E, e, 0x26, h, 0x2E, l, 0x40, 0x18, 0xFE,
+        ])
+    }
+
+    /// ROM printing `text` on the serial port
+    fn serial_rom(text: &str) -> Vec<u8> {
+        let mut code = vec![
+            0x21, 0x50, 0x01, // LD HL,$0150
+            0x2A, // LD A,(HL+)
+            0xB7, // OR A
+            0x28, 0x08, // JR Z,+8
+            0xE0, 0x01, // LDH ($01),A
+            0x3E, 0x81, // LD A,$81
+            0xE0, 0x02, // LDH ($02),A
+            0x18, 0xF4, // JR -12
+            0x18, 0xFE, // JR -2
+        ];
+        code.resize(0x50, 0);
+        code.extend_from_slice(text.as_bytes());
+        code.push(0);
+        rom(&code)
+    }
+
+    /// Screen in a single color
+    fn screen_png(gray: u8) -> Vec<u8> {
+        png::encode(SCREEN_W, SCREEN_H, &vec![gray; SCREEN_W * SCREEN_H * 3])
+    }
+
+    #[test]
+    fn fibonacci_registers_pass() {
+        let mut runner = Headless::new(breakpoint_rom([3, 5, 8, 13, 21, 34]), false).unwrap();
+        assert_eq!(runner.run(&Check::Fibonacci, 10).unwrap(), Outcome::Passed);
+
+        let mut runner = Headless::new(breakpoint_rom([0x42; 6]), false).unwrap();
+        assert!(matches!(
+            runner.run(&Check::Fibonacci, 10).unwrap(),
+            Outcome::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn serial_output_is_checked() {
+        let mut runner = Headless::new(serial_rom("test\nPassed\n"), false).unwrap();
+        assert_eq!(runner.run(&Check::Serial, 10).unwrap(), Outcome::Passed);
+
+        let mut runner = Headless::new(serial_rom("test\nFailed #2\n"), false).unwrap();
+        assert_eq!(
+            runner.run(&Check::Serial, 10).unwrap(),
+            Outcome::Failed("test\nFailed #2".to_string())
+        );
+
+        let mut runner = Headless::new(serial_rom("test\n"), false).unwrap();
+        assert_eq!(runner.run(&Check::Serial, 10).unwrap(), Outcome::TimedOut);
+    }
+
+    #[test]
+    fn screen_is_compared_to_reference() {
+        // nothing is drawn with the tiles left empty
+        let rom = breakpoint_rom([0; 6]);
+        let mut runner = Headless::new(rom.clone(), false).unwrap();
+        let white = Check::Screen(screen_png(0xFF));
+        assert_eq!(runner.run(&white, 10).unwrap(), Outcome::Passed);
+
+        let mut runner = Headless::new(rom.clone(), false).unwrap();
+        let black = Check::Screen(screen_png(0x00));
+        assert_eq!(
+            runner.run(&black, 10).unwrap(),
+            Outcome::Failed(
+                "23040 pixels differ from the reference, the first at (0, 0): expected \
+                 000000, got FFFFFF"
+                    .to_string()
+            )
+        );
+
+        let mut runner = Headless::new(rom, false).unwrap();
+        assert!(runner.run(&Check::Screen(b"garbage".to_vec()), 10).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+
+use rboy::KeypadKey;
+use rboy::input::KeyEvent;
+
+/// Event emitted when a button combo changes state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComboEvent<A> {
+    /// All the keys of the combo are now held
+    Activated(A),
+    /// At least one key of a previously active combo has been released
+    Deactivated(A),
+}
+
+/// A button combo bound to an action
+struct Combo<A> {
+    keys: Vec<KeypadKey>,
+    action: A,
+    active: bool,
+}
+
+/// Tracks the keys currently held and detects when button combos become active or inactive
+pub struct Hotkeys<A> {
+    pressed: Vec<KeypadKey>,
+    combos: Vec<Combo<A>>,
+}
+
+impl<A> Hotkeys<A>
+where
+    A: Copy + fmt::Debug,
+{
+    pub fn new() -> Self {
+        Self {
+            pressed: Vec::new(),
+            combos: Vec::new(),
+        }
+    }
+
+    /// Bind `action` to the combo made of `keys`.
+    ///
+    /// Empty combos are ignored, and so are combos overlapping one bound before: holding the
+    /// keys of one would also trigger the other.
+    pub fn bind(&mut self, keys: Vec<KeypadKey>, action: A) {
+        if keys.is_empty() {
+            warn!("Ignoring empty button combo");
+            return;
+        }
+        let within = |a: &[KeypadKey], b: &[KeypadKey]| a.iter().all(|k| b.contains(k));
+        if let Some(other) = self
+            .combos
+            .iter()
+            .find(|c| within(&c.keys, &keys) || within(&keys, &c.keys))
+        {
+            warn!(
+                "Ignoring button combo {keys:?} of {action:?}, it overlaps the combo {:?} of {:?}",
+                other.keys, other.action
+            );
+            return;
+        }
+        self.combos.push(Combo {
+            keys,
+            action,
+            active: false,
+        });
+    }
+
+    /// Handle a key event and return the combos which changed state
+    pub fn handle_event(&mut self, event: KeyEvent, key: KeypadKey) -> Vec<ComboEvent<A>> {
+        match event {
+            KeyEvent::Down if !self.pressed.contains(&key) => self.pressed.push(key),
+            KeyEvent::Down => {}
+            KeyEvent::Up => self.pressed.retain(|k| *k != key),
+        }
+
+        let mut events = Vec::new();
+        for combo in &mut self.combos {
+            let held = combo.keys.iter().all(|k| self.pressed.contains(k));
+            if held && !combo.active {
+                events.push(ComboEvent::Activated(combo.action));
+            } else if !held && combo.active {
+                events.push(ComboEvent::Deactivated(combo.action));
+            }
+            combo.active = held;
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_should_activate_and_deactivate_combo() {
+        let mut hotkeys = Hotkeys::new();
+        hotkeys.bind(vec![KeypadKey::Select, KeypadKey::Left], 1);
+
+        assert!(
+            hotkeys
+                .handle_event(KeyEvent::Down, KeypadKey::Select)
+                .is_empty()
+        );
+        assert_eq!(
+            hotkeys.handle_event(KeyEvent::Down, KeypadKey::Left),
+            vec![ComboEvent::Activated(1)]
+        );
+        // repeated key down doesn't activate again
+        assert!(
+            hotkeys
+                .handle_event(KeyEvent::Down, KeypadKey::Left)
+                .is_empty()
+        );
+        assert_eq!(
+            hotkeys.handle_event(KeyEvent::Up, KeypadKey::Select),
+            vec![ComboEvent::Deactivated(1)]
+        );
+        assert!(
+            hotkeys
+                .handle_event(KeyEvent::Up, KeypadKey::Left)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_should_ignore_overlapping_combo() {
+        let mut hotkeys = Hotkeys::new();
+        hotkeys.bind(vec![KeypadKey::Select, KeypadKey::Right], 1);
+        hotkeys.bind(vec![KeypadKey::Right, KeypadKey::Select], 2);
+        hotkeys.bind(vec![KeypadKey::Select, KeypadKey::Right, KeypadKey::A], 3);
+        hotkeys.bind(vec![KeypadKey::Select, KeypadKey::A], 4);
+
+        hotkeys.handle_event(KeyEvent::Down, KeypadKey::Select);
+        assert_eq!(
+            hotkeys.handle_event(KeyEvent::Down, KeypadKey::Right),
+            vec![ComboEvent::Activated(1)]
+        );
+        assert_eq!(
+            hotkeys.handle_event(KeyEvent::Down, KeypadKey::A),
+            vec![ComboEvent::Activated(4)]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 pub mod config;
 pub mod gpio;
+pub mod rumble;
 pub mod state;
 
 use std::sync::Arc;
@@ -7,9 +8,11 @@ use std::sync::atomic::AtomicBool;
 use std::sync::mpsc::Sender;
 use std::time::Duration;
 
-pub use self::config::{InputListenerConfig, KeyConfig, PowerSwitch, RepeatConfig};
-use self::gpio::{Gpio, GpioValue};
-use self::state::{KeyState, OutEvent};
+pub use self::config::{
+    InputListenerConfig, KeyConfig, LongPressConfig, PowerAction, PowerSwitch, RepeatConfig,
+};
+use self::gpio::Gpio;
+use self::state::{KeyState, OutEvent, PowerSwitchState};
 use crate::KeypadKey;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -29,8 +32,9 @@ where
 {
     exit: Arc<AtomicBool>,
     event_sender: Sender<Event>,
+    power_sender: Sender<PowerAction>,
     keys: Vec<KeyState<GPIO>>,
-    power_switches: Vec<PowerSwitch<GPIO>>,
+    power_switches: Vec<PowerSwitchState<GPIO>>,
     poll_interval: Duration,
 }
 
@@ -38,13 +42,24 @@ impl<G> InputListener<G>
 where
     G: Gpio,
 {
-    /// Create a new input listener with the given configuration
-    pub fn new(config: InputListenerConfig<G>, event_sender: Sender<Event>) -> Self {
+    /// Create a new input listener with the given configuration.
+    ///
+    /// Key events are sent to `event_sender`, power switch actions to `power_sender`.
+    pub fn new(
+        config: InputListenerConfig<G>,
+        event_sender: Sender<Event>,
+        power_sender: Sender<PowerAction>,
+    ) -> Self {
         InputListener {
             exit: config.exit,
             event_sender,
+            power_sender,
             keys: config.keys.into_iter().map(KeyState::from).collect(),
-            power_switches: config.power_switches,
+            power_switches: config
+                .power_switches
+                .into_iter()
+                .map(PowerSwitchState::from)
+                .collect(),
             poll_interval: config.poll_interval,
         }
     }
@@ -56,7 +71,7 @@ where
                 Self::handle_key_poll(key, &mut self.event_sender);
             }
             for switch in &mut self.power_switches {
-                Self::handle_power_switch_poll(switch, &self.exit);
+                Self::handle_power_switch_poll(switch, &self.power_sender);
             }
             trace!("tick");
             std::thread::sleep(self.poll_interval);
@@ -93,8 +108,11 @@ where
         }
     }
 
-    /// Handle polling of a single power switch
-    fn handle_power_switch_poll(switch: &mut PowerSwitch<G>, exit: &Arc<AtomicBool>) {
+    /// Handle polling of a single power switch.
+    ///
+    /// Carrying out the action is left to the application, so that it can e.g. stop the
+    /// emulator and write the saves before shutting down.
+    fn handle_power_switch_poll(switch: &mut PowerSwitchState<G>, sender: &Sender<PowerAction>) {
         let value = match switch.gpio.read() {
             Ok(v) => v,
             Err(e) => {
@@ -102,15 +120,10 @@ where
                 return;
             }
         };
-        if value == GpioValue::Enabled {
-            warn!("Power switch activated, shutting down system");
-            #[cfg(target_os = "linux")]
-            {
-                use std::process::Command;
-                if let Err(e) = Command::new("shutdown").arg("-h").arg("now").spawn() {
-                    error!("Failed to execute shutdown command: {}", e);
-                }
-                exit.store(true, std::sync::atomic::Ordering::SeqCst);
+        if let Some(action) = switch.handle_gpio_value(value) {
+            warn!("Power switch activated: {action:?}");
+            if let Err(e) = sender.send(action) {
+                error!("Failed to send power switch action: {}", e);
             }
         }
     }
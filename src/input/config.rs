@@ -21,12 +21,38 @@ pub struct RepeatConfig {
     pub rate: Duration,
 }
 
+/// Action requested by a power switch; carrying it out is up to the application
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PowerAction {
+    /// Stop the emulator, write the saves and shut down the system
+    Shutdown,
+    /// Exit the application
+    Exit,
+    /// Return to the game menu
+    Menu,
+    /// Pause or resume the emulation
+    Suspend,
+    /// Run a custom command with its arguments
+    Command(Vec<String>),
+}
+
+/// Configuration for the long press of a power switch
+pub struct LongPressConfig {
+    /// How long the switch must be held
+    pub duration: Duration,
+    pub action: PowerAction,
+}
+
 /// Configuration for an individual power switch
 pub struct PowerSwitch<GPIO>
 where
     GPIO: Gpio,
 {
     pub gpio: GPIO,
+    pub debounce: Duration,
+    /// Action on press, or on release before the long press duration if a long press is set
+    pub action: PowerAction,
+    pub long_press: Option<LongPressConfig>,
 }
 
 /// Configuration for the input listener
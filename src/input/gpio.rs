@@ -3,11 +3,11 @@ mod linux;
 mod mock;
 mod raspberry;
 
-pub use self::linux::LinuxGpio;
+pub use self::linux::{LinuxGpio, LinuxGpioOutput};
 #[cfg(test)]
 #[allow(unused)]
 pub use self::mock::MockGpio;
-pub use self::raspberry::RaspberryGpio;
+pub use self::raspberry::{RaspberryGpio, RaspberryGpioOutput};
 
 /// GPIO value representation.
 ///
@@ -29,3 +29,9 @@ pub trait Gpio {
     /// Read the current GPIO value
     fn read(&mut self) -> anyhow::Result<GpioValue>;
 }
+
+/// Output counterpart of [`Gpio`], driving a pin
+pub trait GpioOutput {
+    /// Set the GPIO value
+    fn write(&mut self, value: GpioValue) -> anyhow::Result<()>;
+}
@@ -2,7 +2,7 @@ use std::path::Path;
 
 use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
 
-use super::{Gpio, GpioValue};
+use super::{Gpio, GpioOutput, GpioValue};
 
 pub struct LinuxGpio {
     handle: LineHandle,
@@ -46,3 +46,40 @@ impl Gpio for LinuxGpio {
         }
     }
 }
+
+/// GPIO output through the Linux character device interface
+pub struct LinuxGpioOutput {
+    handle: LineHandle,
+}
+
+impl LinuxGpioOutput {
+    /// Create a new [`LinuxGpioOutput`] instance for the specified GPIO pin, initially disabled
+    pub fn try_new(device: &Path, gpio: u8, active_low: bool) -> anyhow::Result<Self> {
+        debug!("Opening chip at {:?}", device);
+        let mut chip = Chip::new(device)
+            .map_err(|e| anyhow::anyhow!("Failed to open GPIO chip {:?}: {}", device, e))?;
+        let line = chip.get_line(gpio as u32)?;
+
+        let mut flags = LineRequestFlags::OUTPUT;
+        if active_low {
+            flags |= LineRequestFlags::ACTIVE_LOW;
+            debug!("Setting line ACTIVE_LOW for GPIO {gpio}");
+        }
+
+        debug!("Requesting output line handle for GPIO {gpio}");
+        let handle = line.request(flags, GpioValue::Disabled as u8, "gpio2key")?;
+
+        Ok(LinuxGpioOutput { handle })
+    }
+}
+
+impl GpioOutput for LinuxGpioOutput {
+    fn write(&mut self, value: GpioValue) -> anyhow::Result<()> {
+        trace!(
+            "Write GPIO {gpio} value: {value:?}",
+            gpio = self.handle.line().offset()
+        );
+        self.handle.set_value(value as u8)?;
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Gpio, GpioValue};
+use super::{Gpio, GpioOutput, GpioValue};
 
 /// Mock GPIO implementation for testing
 pub struct MockGpio {
@@ -26,6 +26,13 @@ impl Gpio for MockGpio {
     }
 }
 
+impl GpioOutput for MockGpio {
+    fn write(&mut self, value: GpioValue) -> anyhow::Result<()> {
+        self.value = (value == GpioValue::Enabled) != self.active_low;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
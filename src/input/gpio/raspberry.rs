@@ -1,6 +1,6 @@
-use rppal::gpio::{Gpio as RrppalGpio, InputPin};
+use rppal::gpio::{Gpio as RrppalGpio, InputPin, Level, OutputPin};
 
-use super::{Gpio, GpioValue};
+use super::{Gpio, GpioOutput, GpioValue};
 
 pub struct RaspberryGpio {
     active_low: bool,
@@ -34,3 +34,35 @@ impl Gpio for RaspberryGpio {
         }
     }
 }
+
+pub struct RaspberryGpioOutput {
+    active_low: bool,
+    pin: OutputPin,
+}
+
+impl RaspberryGpioOutput {
+    /// Create a new [`RaspberryGpioOutput`] instance for the specified GPIO pin, initially disabled
+    pub fn try_new(gpio: u8, active_low: bool) -> anyhow::Result<Self> {
+        let pin = RrppalGpio::new()
+            .map_err(|e| anyhow::anyhow!("Failed to access GPIO: {}", e))?
+            .get(gpio)
+            .map_err(|e| anyhow::anyhow!("Failed to get GPIO pin {}: {}", gpio, e))?
+            .into_output();
+
+        let mut output = RaspberryGpioOutput { active_low, pin };
+        output.write(GpioValue::Disabled)?;
+        Ok(output)
+    }
+}
+
+impl GpioOutput for RaspberryGpioOutput {
+    fn write(&mut self, value: GpioValue) -> anyhow::Result<()> {
+        trace!("Write GPIO {gpio} value: {value:?}", gpio = self.pin.pin());
+        let level = match (value, self.active_low) {
+            (GpioValue::Enabled, false) | (GpioValue::Disabled, true) => Level::High,
+            (GpioValue::Disabled, false) | (GpioValue::Enabled, true) => Level::Low,
+        };
+        self.pin.write(level);
+        Ok(())
+    }
+}
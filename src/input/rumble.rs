@@ -0,0 +1,75 @@
+use super::gpio::{GpioOutput, GpioValue};
+use crate::RumbleCallback;
+
+/// Drives GPIO outputs, e.g. the transistor of a vibration motor, from the rumble motor of the
+/// cartridge.
+///
+/// The outputs are disabled when dropped, so that the motor stops with the emulator.
+pub struct GpioRumble<G>
+where
+    G: GpioOutput,
+{
+    outputs: Vec<G>,
+}
+
+impl<G> GpioRumble<G>
+where
+    G: GpioOutput,
+{
+    pub fn new(outputs: Vec<G>) -> Self {
+        Self { outputs }
+    }
+
+    fn write(&mut self, value: GpioValue) {
+        for output in &mut self.outputs {
+            if let Err(e) = output.write(value) {
+                error!("Failed to write rumble GPIO: {e}");
+            }
+        }
+    }
+}
+
+impl<G> RumbleCallback for GpioRumble<G>
+where
+    G: GpioOutput + Send,
+{
+    fn call(&mut self, on: bool) {
+        debug!("Rumble: {on}");
+        self.write(if on {
+            GpioValue::Enabled
+        } else {
+            GpioValue::Disabled
+        });
+    }
+}
+
+impl<G> Drop for GpioRumble<G>
+where
+    G: GpioOutput,
+{
+    fn drop(&mut self) {
+        self.write(GpioValue::Disabled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::gpio::{Gpio, MockGpio};
+
+    #[test]
+    fn test_should_drive_outputs() {
+        let mut rumble = GpioRumble::new(vec![
+            MockGpio::new(false, false),
+            MockGpio::new(false, true),
+        ]);
+        rumble.call(true);
+        for output in &mut rumble.outputs {
+            assert_eq!(output.read().unwrap(), GpioValue::Enabled);
+        }
+        rumble.call(false);
+        for output in &mut rumble.outputs {
+            assert_eq!(output.read().unwrap(), GpioValue::Disabled);
+        }
+    }
+}
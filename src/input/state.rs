@@ -1,6 +1,6 @@
 use std::time::{Duration, Instant};
 
-use crate::input::config::{KeyConfig, RepeatConfig};
+use crate::input::config::{KeyConfig, PowerAction, PowerSwitch, RepeatConfig};
 use crate::input::gpio::{Gpio, GpioValue};
 
 /// Configuration for an individual key binding
@@ -54,6 +54,69 @@ where
     }
 }
 
+/// State of a power switch, telling short presses from long presses
+pub struct PowerSwitchState<GPIO>
+where
+    GPIO: Gpio,
+{
+    pub gpio: GPIO,
+    pub debounce: Duration,
+    pub action: PowerAction,
+    /// Long press action; the long press is detected as the first repeat of the switch
+    pub long_press: Option<(RepeatConfig, PowerAction)>,
+    pub state: State,
+}
+
+impl<G> PowerSwitchState<G>
+where
+    G: Gpio,
+{
+    /// Handle a value read from the gpio and update the state accordingly.
+    ///
+    /// Returns the action to carry out, if any.
+    pub fn handle_gpio_value(&mut self, value: GpioValue) -> Option<PowerAction> {
+        let repeat = self.long_press.as_ref().map(|(repeat, _)| repeat);
+        let (new_state, out_event) = self.state.handle_gpio_value(value, repeat, self.debounce);
+        let was_repeating = matches!(self.state, State::Repeating { .. });
+        self.state = new_state;
+
+        match (out_event, &self.long_press) {
+            // without long press, act right away
+            (OutEvent::Press, None) => Some(self.action.clone()),
+            // the first repeat is the long press; the state doesn't repeat again
+            (OutEvent::Repeat, Some((_, long_action))) if !was_repeating => {
+                Some(long_action.clone())
+            }
+            // released before the long press fired
+            (OutEvent::Release, Some(_)) if !was_repeating => Some(self.action.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl<GPIO> From<PowerSwitch<GPIO>> for PowerSwitchState<GPIO>
+where
+    GPIO: Gpio,
+{
+    fn from(config: PowerSwitch<GPIO>) -> Self {
+        PowerSwitchState {
+            gpio: config.gpio,
+            debounce: config.debounce,
+            action: config.action,
+            long_press: config.long_press.map(|long_press| {
+                (
+                    RepeatConfig {
+                        delay: long_press.duration,
+                        rate: Duration::MAX,
+                    },
+                    long_press.action,
+                )
+            }),
+            state: State::Unknown,
+        }
+    }
+}
+
 /// Output event generated by a key state change
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OutEvent {
@@ -195,6 +258,8 @@ impl State {
 mod tests {
 
     use super::*;
+    use crate::input::config::LongPressConfig;
+    use crate::input::gpio::MockGpio;
 
     #[test]
     fn test_should_handle_gpio_enabled_press() {
@@ -351,6 +416,59 @@ mod tests {
         assert_eq!(out_event, OutEvent::None);
     }
 
+    fn power_switch(long_press: Option<Duration>) -> PowerSwitchState<MockGpio> {
+        PowerSwitchState::from(PowerSwitch {
+            gpio: MockGpio::new(false, false),
+            debounce: Duration::from_millis(10),
+            action: PowerAction::Menu,
+            long_press: long_press.map(|duration| LongPressConfig {
+                duration,
+                action: PowerAction::Shutdown,
+            }),
+        })
+    }
+
+    #[test]
+    fn test_should_trigger_power_action_on_press() {
+        let mut switch = power_switch(None);
+        switch.state = State::Released {
+            since: Instant::now() - Duration::from_millis(200),
+        };
+        assert_eq!(
+            switch.handle_gpio_value(GpioValue::Enabled),
+            Some(PowerAction::Menu)
+        );
+        assert_eq!(switch.handle_gpio_value(GpioValue::Enabled), None);
+    }
+
+    #[test]
+    fn test_should_trigger_short_press_on_release() {
+        let mut switch = power_switch(Some(Duration::from_secs(2)));
+        switch.state = State::Pressed {
+            since: Instant::now() - Duration::from_millis(200),
+        };
+        assert_eq!(switch.handle_gpio_value(GpioValue::Enabled), None);
+        assert_eq!(
+            switch.handle_gpio_value(GpioValue::Disabled),
+            Some(PowerAction::Menu)
+        );
+    }
+
+    #[test]
+    fn test_should_trigger_long_press_once() {
+        let mut switch = power_switch(Some(Duration::from_secs(2)));
+        switch.state = State::Pressed {
+            since: Instant::now() - Duration::from_secs(3),
+        };
+        assert_eq!(
+            switch.handle_gpio_value(GpioValue::Enabled),
+            Some(PowerAction::Shutdown)
+        );
+        assert_eq!(switch.handle_gpio_value(GpioValue::Enabled), None);
+        // no short press when released after a long press
+        assert_eq!(switch.handle_gpio_value(GpioValue::Disabled), None);
+    }
+
     #[test]
     fn test_should_tell_whether_is_debounced() {
         let now = Instant::now();
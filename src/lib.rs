@@ -4,25 +4,42 @@
 #[macro_use]
 extern crate log;
 
-pub use crate::gpu::{SCREEN_H, SCREEN_W};
+pub use crate::compat_palette::CompatPalette;
+pub use crate::error::{Error, Result};
+pub use crate::gbmode::GbMode;
+pub use crate::gpu::{CLOCK_RATE, FRAME_DOTS, Renderer, SCREEN_H, SCREEN_W, VramImage, VramView};
+pub use crate::header::{CartridgeHeader, CgbSupport, Licensee, Region};
 pub use crate::keypad::KeypadKey;
+pub use crate::mbc::{
+    CAMERA_HEIGHT, CAMERA_WIDTH, CameraSource, RtcClock, RtcSource, RumbleCallback,
+};
+pub use crate::palette::{DmgPalette, PalettePreset};
 pub use crate::serial::SerialCallback;
 pub use crate::sound::AudioPlayer;
+pub use crate::trace::{TraceConfig, TraceOutput};
 
+pub mod debugger;
 pub mod device;
+pub mod disasm;
+mod error;
 
+mod compat_palette;
 mod cpu;
 pub mod framebuffer;
 mod gbmode;
 mod gpu;
+mod header;
+pub mod headless;
 pub mod input;
 mod keypad;
 mod mbc;
 mod mmu;
+mod palette;
+mod png;
 mod printer;
+mod recorder;
 mod register;
 mod serial;
 mod sound;
 mod timer;
-
-pub type StrResult<T> = Result<T, &'static str>;
+mod trace;
@@ -3,7 +3,12 @@ extern crate log;
 
 mod app_config;
 mod args;
+mod capture;
+mod debug_server;
+mod hotkey;
 mod menu;
+mod palette_selector;
+mod shutdown;
 
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
@@ -12,21 +17,79 @@ use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError, TrySendE
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{FromSample, Sample};
-use rboy::device::Device;
+use rboy::device::{Device, RewindConfig};
 use rboy::framebuffer::{Framebuffer, FramebufferConfig};
-use rboy::input::gpio::RaspberryGpio;
-use rboy::input::{InputListener, InputListenerConfig, KeyConfig, KeyEvent, PowerSwitch};
-
-use self::app_config::AppConfig;
+use rboy::input::gpio::{RaspberryGpio, RaspberryGpioOutput};
+use rboy::input::rumble::GpioRumble;
+use rboy::input::{
+    InputListener, InputListenerConfig, KeyConfig, KeyEvent, LongPressConfig, PowerAction,
+    PowerSwitch,
+};
+use rboy::{TraceConfig, TraceOutput, VramImage, VramView};
+
+use self::app_config::{AppConfig, CompatPaletteConfig, Speed};
+use self::debug_server::DebugServer;
+use self::hotkey::{ComboEvent, Hotkeys};
+use self::palette_selector::PaletteSelector;
 
 enum GBEvent {
     KeyUp(rboy::KeypadKey),
     KeyDown(rboy::KeypadKey),
+    /// Start or stop playing the game backwards
+    Rewind(bool),
+    /// Change the emulation speed
+    SetSpeed(Speed),
+    /// Pause or resume the emulation
+    Suspend(bool),
+    /// Change the colors of classic games
+    SetPalette(rboy::DmgPalette),
+    /// Write the saves, optionally into a save state slot, and stop the emulator
+    PowerOff {
+        autosave_slot: Option<u8>,
+    },
+    /// Show a debug view of the video memory instead of the screen
+    SetVramView(Option<VramView>),
+    /// Save a picture of the screen
+    Screenshot(PathBuf),
+    /// Stop the current video recording, or start one into the file
+    ToggleRecording(PathBuf),
+}
+
+/// Picture sent to the framebuffer by the CPU thread
+enum Frame {
+    /// The screen of the game
+    Screen(Vec<u8>),
+    /// A debug view of the video memory
+    Vram(VramImage),
 }
 
+/// Actions bound to button combos while the emulator is running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Hotkey {
+    Rewind,
+    NextSpeed,
+    NextPalette,
+    VramViewer,
+    Screenshot,
+    Record,
+}
+
+/// Keys released for the game when the VRAM viewer opens, as it takes over the keys
+const VIEWER_RELEASED_KEYS: [rboy::KeypadKey; 8] = [
+    rboy::KeypadKey::Right,
+    rboy::KeypadKey::Left,
+    rboy::KeypadKey::Up,
+    rboy::KeypadKey::Down,
+    rboy::KeypadKey::A,
+    rboy::KeypadKey::B,
+    rboy::KeypadKey::Select,
+    rboy::KeypadKey::Start,
+];
+
 /// The Application state.
 #[derive(Debug, Clone)]
 enum AppState {
@@ -61,6 +124,14 @@ fn main() -> anyhow::Result<()> {
     })?);
     info!("Framebuffer opened.");
 
+    let mut debugger = args.debugger.as_ref().map(DebugServer::start).transpose()?;
+
+    let trace = args.trace.as_ref().map(|path| TraceConfig {
+        output: TraceOutput::File(path.clone()),
+        limit: args.trace_limit,
+        stub_ly: args.trace_stub_ly,
+    });
+
     // init state
     let mut app_state = match &args.rom_path {
         Some(rom_path) => AppState::Emulator {
@@ -82,15 +153,25 @@ fn main() -> anyhow::Result<()> {
         })
         .expect("Error setting Ctrl-C handler");
     }
+    // set by the power switches
+    let power_off = Arc::new(AtomicBool::new(false));
 
     // loop through state machine
 
     loop {
         app_state = match app_state {
-            AppState::Emulator { config, rom_file } => {
-                run_emulator(&rom_file, config, framebuffer.clone(), exit.clone())?
+            AppState::Emulator { config, rom_file } => run_emulator(
+                &rom_file,
+                config,
+                framebuffer.clone(),
+                exit.clone(),
+                power_off.clone(),
+                &mut debugger,
+                trace.as_ref(),
+            )?,
+            AppState::Menu { config } => {
+                run_menu(config, framebuffer.clone(), exit.clone(), power_off.clone())?
             }
-            AppState::Menu { config } => run_menu(config, framebuffer.clone(), exit.clone())?,
             AppState::Exit => break,
         };
         debug!("New AppState: {app_state:?}",);
@@ -99,6 +180,11 @@ fn main() -> anyhow::Result<()> {
     // zero fb
     framebuffer.zero();
 
+    if power_off.load(std::sync::atomic::Ordering::SeqCst) {
+        warn!("Power switch activated, shutting down system");
+        shutdown::run_command(&config.shutdown)?;
+    }
+
     Ok(())
 }
 
@@ -106,16 +192,30 @@ fn run_menu(
     config: Rc<AppConfig>,
     framebuffer: Rc<Framebuffer>,
     exit: Arc<AtomicBool>,
+    power_off: Arc<AtomicBool>,
 ) -> anyhow::Result<AppState> {
     // run input listener
     let (keyboard_event_sender, keyboard_event_receiver) = mpsc::channel();
+    let (power_event_sender, power_event_receiver) = mpsc::channel();
 
     let input_listener_exit = Arc::new(AtomicBool::new(false));
-    let input_listener_thread =
-        run_input_listener(&config, input_listener_exit.clone(), keyboard_event_sender);
+    let input_listener_thread = run_input_listener(
+        &config,
+        input_listener_exit.clone(),
+        keyboard_event_sender,
+        power_event_sender,
+    );
 
     // run menu
-    let res = menu::AppMenu::new(config, framebuffer, exit, keyboard_event_receiver)?.run();
+    let res = menu::AppMenu::new(
+        config,
+        framebuffer,
+        exit,
+        power_off,
+        keyboard_event_receiver,
+        power_event_receiver,
+    )?
+    .run();
     // stop input listener
     input_listener_exit.store(true, std::sync::atomic::Ordering::SeqCst);
     let _ = input_listener_thread.join();
@@ -130,18 +230,120 @@ fn run_emulator(
     config: Rc<AppConfig>,
     framebuffer: Rc<Framebuffer>,
     exit: Arc<AtomicBool>,
+    power_off: Arc<AtomicBool>,
+    debugger: &mut Option<DebugServer>,
+    trace: Option<&TraceConfig>,
 ) -> anyhow::Result<AppState> {
     info!("Starting emulator with ROM: {}", rom_file.display());
     // zero framebuffer
     framebuffer.zero();
     debug!("Framebuffer zeroed.");
 
-    let cpu = construct_cpu(rom_file, false, false, None);
+    let cpu = construct_cpu(rom_file, false, false);
 
     let Some(mut cpu) = cpu else {
         return Err(anyhow::anyhow!("Could not construct CPU"));
     };
     debug!("CPU constructed");
+    let header = cpu.cartridge_header();
+    info!(
+        "Cartridge: {} ({}, version {}, licensee {})",
+        header.title,
+        header.cartridge_type_name(),
+        header.version,
+        header.licensee
+    );
+
+    // known before a CGB boot ROM switches the device to color mode
+    let compat_mode = cpu.mode() == rboy::GbMode::ColorAsClassic;
+    let mut boot_rom_loaded = false;
+    if let Some(path) = config.boot_rom.path(cpu.mode()) {
+        match std::fs::read(path) {
+            Ok(data) => match cpu.set_boot_rom(data) {
+                Ok(()) => {
+                    boot_rom_loaded = true;
+                    debug!("Boot ROM {} loaded", path.display());
+                }
+                Err(e) => error!("Invalid boot ROM {}: {e}", path.display()),
+            },
+            Err(e) => error!("Failed to read boot ROM {}: {e}", path.display()),
+        }
+    }
+    if compat_mode {
+        match config.compat_palette {
+            // the CGB boot ROM picks the palette itself
+            CompatPaletteConfig::Auto if boot_rom_loaded => {}
+            CompatPaletteConfig::Gray if boot_rom_loaded => {
+                warn!("Compatibility palette \"gray\" ignored, the CGB boot ROM colors the game")
+            }
+            setting => {
+                if let Some(colors) = setting.colors(cpu.auto_compat_colors()) {
+                    // applied once the boot ROM hands over to the game
+                    cpu.set_compat_colors(colors);
+                    debug!("Compatibility palette {setting} selected");
+                }
+            }
+        }
+    }
+
+    let mut hotkeys = Hotkeys::new();
+    if let Some(rewind) = &config.rewind {
+        cpu.enable_rewind(RewindConfig {
+            interval_frames: rewind.interval_frames,
+            capacity: rewind.capacity,
+        });
+        hotkeys.bind(rewind.combo(), Hotkey::Rewind);
+        debug!("Rewind enabled");
+    }
+    cpu.set_save_backups(config.battery_save.backups);
+    let save_path = rom_file.with_extension(&config.battery_save.extension);
+    if let Err(e) = cpu.set_save_path(&save_path) {
+        error!("Failed to load battery save {}: {e}", save_path.display());
+    }
+    cpu.set_rtc_clock(config.rtc_clock.clock());
+    cpu.set_renderer(config.renderer.renderer());
+    if let Some(trace) = trace {
+        match cpu.enable_trace(trace) {
+            Ok(()) => info!("Tracing instructions: {:?}", trace.output),
+            Err(e) => error!("Failed to start the trace: {e}"),
+        }
+    }
+    let flush_interval = config.battery_save.flush_interval();
+    let mut speed = config.speed.default;
+    if !config.speed.combo.is_empty() {
+        hotkeys.bind(config.speed.combo(), Hotkey::NextSpeed);
+    }
+    if !config.rumble.is_empty() {
+        let outputs = config
+            .rumble
+            .iter()
+            .filter_map(|rumble| {
+                match RaspberryGpioOutput::try_new(rumble.gpio, rumble.active_low) {
+                    Ok(output) => Some(output),
+                    Err(e) => {
+                        error!("Failed to open rumble GPIO {}: {e}", rumble.gpio);
+                        None
+                    }
+                }
+            })
+            .collect();
+        cpu.set_rumble_callback(Box::new(GpioRumble::new(outputs)));
+    }
+    let mut palette = PaletteSelector::new(&config.palette, rom_file);
+    cpu.set_dmg_palette(palette.palette());
+    debug!("Palette {} selected", palette.name());
+    if !config.palette.combo.is_empty() {
+        hotkeys.bind(config.palette.combo(), Hotkey::NextPalette);
+    }
+    if !config.vram_viewer.combo.is_empty() {
+        hotkeys.bind(config.vram_viewer.combo(), Hotkey::VramViewer);
+    }
+    if !config.screenshot.combo.is_empty() {
+        hotkeys.bind(config.screenshot.combo(), Hotkey::Screenshot);
+    }
+    if !config.recording.combo.is_empty() {
+        hotkeys.bind(config.recording.combo(), Hotkey::Record);
+    }
 
     let cpal_audio_stream;
 
@@ -161,22 +363,76 @@ fn run_emulator(
     let (video_sender, video_receiver) = mpsc::sync_channel(1);
 
     debug!("Starting CPU thread");
-    let cpu_thread = thread::spawn(move || run_cpu(cpu, video_sender, gb_event_receiver));
+    let debug_server = debugger.take();
+    let cpu_thread = thread::spawn(move || {
+        run_cpu(
+            cpu,
+            speed,
+            flush_interval,
+            video_sender,
+            gb_event_receiver,
+            debug_server,
+        )
+    });
     debug!("CPU thread started");
 
     // run input listener
     let (keyboard_event_sender, keyboard_event_receiver) = mpsc::channel();
-    let input_listener_thread = run_input_listener(&config, exit.clone(), keyboard_event_sender);
+    let (power_event_sender, power_event_receiver) = mpsc::channel();
+    let input_listener_exit = Arc::new(AtomicBool::new(false));
+    let input_listener_thread = run_input_listener(
+        &config,
+        input_listener_exit.clone(),
+        keyboard_event_sender,
+        power_event_sender,
+    );
     debug!("Input listener started");
 
+    let mut suspended = false;
+    let mut vram_view: Option<VramView> = None;
     loop {
         if exit.load(std::sync::atomic::Ordering::SeqCst) {
             info!("Exit requested, stopping emulator...");
             break;
         }
 
+        if let Ok(action) = power_event_receiver.try_recv() {
+            match action {
+                PowerAction::Shutdown => {
+                    power_off.store(true, std::sync::atomic::Ordering::SeqCst);
+                    exit.store(true, std::sync::atomic::Ordering::SeqCst);
+                }
+                PowerAction::Exit => exit.store(true, std::sync::atomic::Ordering::SeqCst),
+                PowerAction::Menu => {
+                    info!("Returning to menu...");
+                    break;
+                }
+                PowerAction::Suspend => {
+                    suspended = !suspended;
+                    info!("Emulation suspended: {suspended}");
+                    let _ = gb_event_sender.send(GBEvent::Suspend(suspended));
+                }
+                PowerAction::Command(command) => shutdown::spawn_command(&command),
+            }
+        }
+
         if let Ok((event, key)) = keyboard_event_receiver.try_recv() {
             match event {
+                // the keys page through the views instead of playing
+                KeyEvent::Down if let Some(view) = vram_view => {
+                    let next = match key {
+                        rboy::KeypadKey::Left => view.previous(),
+                        rboy::KeypadKey::Right => view.next(),
+                        _ => view,
+                    };
+                    if next != view {
+                        info!("VRAM viewer: {}", next.name());
+                        vram_view = Some(next);
+                        framebuffer.zero();
+                        let _ = gb_event_sender.send(GBEvent::SetVramView(vram_view));
+                    }
+                }
+                KeyEvent::Up if vram_view.is_some() => {}
                 KeyEvent::Down => {
                     debug!("Key Down: {:?}", key);
                     let _ = gb_event_sender.send(GBEvent::KeyDown(key));
@@ -186,13 +442,87 @@ fn run_emulator(
                     let _ = gb_event_sender.send(GBEvent::KeyUp(key));
                 }
             }
+
+            for combo in hotkeys.handle_event(event, key) {
+                debug!("Hotkey: {combo:?}");
+                let _ = match combo {
+                    ComboEvent::Activated(Hotkey::Rewind) => {
+                        gb_event_sender.send(GBEvent::Rewind(true))
+                    }
+                    ComboEvent::Deactivated(Hotkey::Rewind) => {
+                        gb_event_sender.send(GBEvent::Rewind(false))
+                    }
+                    ComboEvent::Activated(Hotkey::NextSpeed) => {
+                        speed = config.speed.next_step(speed);
+                        info!("Emulation speed: {speed}");
+                        gb_event_sender.send(GBEvent::SetSpeed(speed))
+                    }
+                    ComboEvent::Activated(Hotkey::NextPalette) => {
+                        let colors = palette.next();
+                        info!("Palette: {}", palette.name());
+                        gb_event_sender.send(GBEvent::SetPalette(colors))
+                    }
+                    ComboEvent::Activated(Hotkey::VramViewer) => {
+                        vram_view = match vram_view {
+                            Some(_) => None,
+                            None => {
+                                // release the keys of the combo held by the game
+                                for key in VIEWER_RELEASED_KEYS {
+                                    let _ = gb_event_sender.send(GBEvent::KeyUp(key));
+                                }
+                                Some(VramView::Tiles)
+                            }
+                        };
+                        info!(
+                            "VRAM viewer: {}",
+                            vram_view.map(VramView::name).unwrap_or("closed")
+                        );
+                        framebuffer.zero();
+                        gb_event_sender.send(GBEvent::SetVramView(vram_view))
+                    }
+                    ComboEvent::Activated(Hotkey::Screenshot) => {
+                        let directory = config.screenshot.directory(&config.roms_directory);
+                        match capture::next_path(&directory, rom_file, "png") {
+                            Ok(path) => gb_event_sender.send(GBEvent::Screenshot(path)),
+                            Err(e) => {
+                                error!("{e}");
+                                Ok(())
+                            }
+                        }
+                    }
+                    ComboEvent::Activated(Hotkey::Record) => {
+                        // the CPU thread knows whether a recording is running; the path is
+                        // only used to start one
+                        let directory = config.recording.directory(&config.roms_directory);
+                        match capture::next_path(&directory, rom_file, "avi") {
+                            Ok(path) => gb_event_sender.send(GBEvent::ToggleRecording(path)),
+                            Err(e) => {
+                                error!("{e}");
+                                Ok(())
+                            }
+                        }
+                    }
+                    ComboEvent::Deactivated(
+                        Hotkey::NextSpeed
+                        | Hotkey::NextPalette
+                        | Hotkey::VramViewer
+                        | Hotkey::Screenshot
+                        | Hotkey::Record,
+                    ) => Ok(()),
+                };
+            }
         }
 
         match video_receiver.try_recv() {
-            Ok(data) => {
+            Ok(Frame::Screen(data)) if vram_view.is_none() => {
                 trace!("Received video frame, updating framebuffer");
                 framebuffer.write(&data);
             }
+            Ok(Frame::Vram(image)) if vram_view.is_some() => {
+                framebuffer.write_image(&image.data, image.width, image.height);
+            }
+            // sent before the viewer was toggled
+            Ok(_) => {}
             Err(TryRecvError::Empty) => {
                 thread::sleep(std::time::Duration::from_millis(10));
             }
@@ -201,12 +531,25 @@ fn run_emulator(
     }
 
     debug!("Stopping input listener...");
+    input_listener_exit.store(true, std::sync::atomic::Ordering::SeqCst);
     let _ = input_listener_thread.join();
     debug!("Input listener stopped.");
 
+    if power_off.load(std::sync::atomic::Ordering::SeqCst) {
+        // let the CPU thread write the saves before the system goes down
+        let _ = gb_event_sender.send(GBEvent::PowerOff {
+            autosave_slot: config.shutdown.autosave_slot,
+        });
+    }
+
     drop(cpal_audio_stream);
-    drop(video_receiver); // Stop CPU thread by disconnecting
-    let _ = cpu_thread.join();
+    // Stop CPU thread by disconnecting, also when it doesn't send frames, e.g. while suspended
+    drop(video_receiver);
+    drop(gb_event_sender);
+    if let Ok(debug_server) = cpu_thread.join() {
+        *debugger = debug_server;
+    }
+    debug!("CPU thread stopped.");
 
     // zero framebuffer
     framebuffer.zero();
@@ -219,15 +562,10 @@ fn run_emulator(
     }
 }
 
-fn construct_cpu(
-    rom_file: &Path,
-    classic_mode: bool,
-    skip_checksum: bool,
-    reload_mode: Option<String>,
-) -> Option<Box<Device>> {
+fn construct_cpu(rom_file: &Path, classic_mode: bool, skip_checksum: bool) -> Option<Box<Device>> {
     let opt_c = match classic_mode {
-        true => Device::new(rom_file, skip_checksum, reload_mode),
-        false => Device::new_cgb(rom_file, skip_checksum, reload_mode),
+        true => Device::new(rom_file, skip_checksum),
+        false => Device::new_cgb(rom_file, skip_checksum),
     };
     let c = match opt_c {
         Ok(cpu) => cpu,
@@ -240,37 +578,157 @@ fn construct_cpu(
     Some(Box::new(c))
 }
 
-fn run_cpu(mut cpu: Box<Device>, sender: SyncSender<Vec<u8>>, receiver: Receiver<GBEvent>) {
+fn run_cpu(
+    mut cpu: Box<Device>,
+    mut speed: Speed,
+    flush_interval: Duration,
+    sender: SyncSender<Frame>,
+    receiver: Receiver<GBEvent>,
+    debugger: Option<DebugServer>,
+) -> Option<DebugServer> {
     let periodic = timer_periodic(16);
 
-    let waitticks = (4194304f64 / 1000.0 * 16.0).round() as u32;
+    let period_ticks = (rboy::CLOCK_RATE as f64 / 1000.0 * 16.0).round() as u32;
+    let mut waitticks = speed.tick_budget(period_ticks);
     let mut ticks = 0;
+    let mut rewinding = false;
+    let mut suspended = false;
+    let mut debug_paused = debugger.is_some();
+    let mut vram_view = None;
+    // time of the first RAM change not written to the save file yet
+    let mut ram_changed_at = None;
 
     'outer: loop {
-        while ticks < waitticks {
-            ticks += cpu.do_cycle();
+        if rewinding {
+            // play back one snapshot per period instead of running the game
+            match cpu.rewind(1) {
+                Ok(true) => {
+                    let frame = video_frame(&cpu, vram_view);
+                    if let Err(TrySendError::Disconnected(..)) = sender.try_send(frame) {
+                        break 'outer;
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    error!("Failed to rewind: {e}");
+                    rewinding = false;
+                }
+            }
+        }
+
+        while !rewinding && !suspended && !debug_paused && ticks < waitticks {
+            ticks += match &debugger {
+                Some(debugger) => debugger.cycle(&mut cpu, &mut debug_paused),
+                None => cpu.do_cycle(),
+            };
             if cpu.check_and_reset_gpu_updated() {
-                let data = cpu.get_gpu_data().to_vec();
-                if let Err(TrySendError::Disconnected(..)) = sender.try_send(data) {
+                let frame = video_frame(&cpu, vram_view);
+                if let Err(TrySendError::Disconnected(..)) = sender.try_send(frame) {
                     break 'outer;
                 }
             }
         }
 
-        ticks -= waitticks;
+        ticks = ticks.saturating_sub(waitticks);
+
+        if cpu.check_and_reset_ram_updated() {
+            ram_changed_at.get_or_insert_with(Instant::now);
+        }
+        if ram_changed_at.is_some_and(|t: Instant| t.elapsed() >= flush_interval) {
+            ram_changed_at = None;
+            if let Err(e) = cpu.flush_ram() {
+                error!("Failed to write battery save: {e}");
+            }
+        }
+
+        if let Some(debugger) = &debugger {
+            debugger.poll(&mut cpu, &mut debug_paused);
+        }
 
         'recv: loop {
             match receiver.try_recv() {
                 Ok(event) => match event {
                     GBEvent::KeyUp(key) => cpu.keyup(key),
                     GBEvent::KeyDown(key) => cpu.keydown(key),
+                    GBEvent::Rewind(enabled) => rewinding = enabled,
+                    GBEvent::Suspend(enabled) => {
+                        suspended = enabled;
+                        cpu.sync_audio();
+                    }
+                    GBEvent::SetPalette(palette) => cpu.set_dmg_palette(palette),
+                    GBEvent::Screenshot(path) => match std::fs::write(&path, cpu.screenshot()) {
+                        Ok(()) => info!("Screenshot saved to {}", path.display()),
+                        Err(e) => error!("Failed to save screenshot {}: {e}", path.display()),
+                    },
+                    GBEvent::ToggleRecording(path) => {
+                        if cpu.is_recording() {
+                            match cpu.stop_recording() {
+                                Ok(()) => info!("Recording stopped"),
+                                Err(e) => error!("Failed to complete recording: {e}"),
+                            }
+                        } else {
+                            match cpu.start_recording(&path) {
+                                Ok(()) => info!("Recording to {}", path.display()),
+                                Err(e) => error!("Failed to start recording: {e}"),
+                            }
+                        }
+                    }
+                    GBEvent::SetVramView(view) => {
+                        vram_view = view;
+                        // show the view while the emulation is paused too
+                        let _ = sender.try_send(video_frame(&cpu, vram_view));
+                    }
+                    GBEvent::SetSpeed(new_speed) => {
+                        speed = new_speed;
+                        waitticks = speed.tick_budget(period_ticks);
+                        // drop the audio buffered at the previous speed
+                        cpu.sync_audio();
+                    }
+                    GBEvent::PowerOff { autosave_slot } => {
+                        power_off(&mut cpu, autosave_slot);
+                        break 'outer;
+                    }
                 },
                 Err(TryRecvError::Empty) => break 'recv,
                 Err(TryRecvError::Disconnected) => break 'outer,
             }
         }
 
-        let _ = periodic.recv();
+        // don't spin while nothing runs, even at uncapped speed
+        if speed.is_capped() || suspended || debug_paused {
+            let _ = periodic.recv();
+        }
+    }
+
+    // the frames may stop being received with a power off still queued, sent just before
+    while let Ok(event) = receiver.try_recv() {
+        if let GBEvent::PowerOff { autosave_slot } = event {
+            power_off(&mut cpu, autosave_slot);
+        }
+    }
+    if let Err(e) = cpu.stop_recording() {
+        error!("Failed to complete recording: {e}");
+    }
+    debugger
+}
+
+/// Write the saves, optionally into a save state slot, before the system goes down
+fn power_off(cpu: &mut Device, autosave_slot: Option<u8>) {
+    if let Some(slot) = autosave_slot {
+        match cpu.save_state_to(slot) {
+            Ok(path) => info!("State saved to {}", path.display()),
+            Err(e) => error!("Failed to save state: {e}"),
+        }
+    }
+    if let Err(e) = cpu.flush_ram() {
+        error!("Failed to write battery save: {e}");
+    }
+}
+
+fn video_frame(cpu: &Device, vram_view: Option<VramView>) -> Frame {
+    match vram_view {
+        Some(view) => Frame::Vram(cpu.render_vram_view(view)),
+        None => Frame::Screen(cpu.get_gpu_data().to_vec()),
     }
 }
 
@@ -471,6 +929,7 @@ fn run_input_listener(
     config: &AppConfig,
     exit: Arc<AtomicBool>,
     event_sender: Sender<rboy::input::Event>,
+    power_sender: Sender<PowerAction>,
 ) -> JoinHandle<()> {
     let poll_interval = config.poll_interval();
     let power_switches = config
@@ -478,6 +937,12 @@ fn run_input_listener(
         .iter()
         .map(|ps| PowerSwitch {
             gpio: gpio(ps.gpio, ps.active_low.unwrap_or(config.default_active_low)),
+            debounce: ps.debounce().unwrap_or(config.default_debounce()),
+            action: ps.action.action(),
+            long_press: ps.long_press.as_ref().map(|long_press| LongPressConfig {
+                duration: long_press.duration(),
+                action: long_press.action.action(),
+            }),
         })
         .collect();
     let keys = config
@@ -508,7 +973,7 @@ fn run_input_listener(
         keys,
         poll_interval,
     };
-    thread::spawn(move || InputListener::new(config, event_sender).run())
+    thread::spawn(move || InputListener::new(config, event_sender, power_sender).run())
 }
 
 fn gpio(pin: u8, active_low: bool) -> RaspberryGpio {
@@ -547,6 +1012,81 @@ fn log_config(config: &AppConfig) {
             "    Active Low: {}",
             ps.active_low.unwrap_or(config.default_active_low)
         );
+        if let Some(debounce) = ps.debounce() {
+            info!("    Debounce (ms): {}", debounce.as_millis());
+        }
+        info!("    Action: {:?}", ps.action);
+        if let Some(long_press) = &ps.long_press {
+            info!(
+                "    Long Press ({} ms): {:?}",
+                long_press.duration().as_millis(),
+                long_press.action
+            );
+        }
+    }
+    info!("  Rumble:");
+    for rumble in &config.rumble {
+        info!("    GPIO {}", rumble.gpio);
+        info!("    Active Low: {}", rumble.active_low);
+    }
+    info!("  Speed:");
+    info!("    Default: {}", config.speed.default);
+    info!("    Combo: {:?}", config.speed.combo());
+    for step in &config.speed.steps {
+        info!("    Step: {step}");
+    }
+    info!("  Boot ROMs:");
+    if let Some(dmg) = &config.boot_rom.dmg {
+        info!("    DMG: {}", dmg.display());
+    }
+    if let Some(cgb) = &config.boot_rom.cgb {
+        info!("    CGB: {}", cgb.display());
+    }
+    info!("  Compatibility Palette: {}", config.compat_palette);
+    info!("  RTC Clock: {:?}", config.rtc_clock);
+    info!("  Renderer: {:?}", config.renderer);
+    info!("  Palette:");
+    info!("    Default: {}", config.palette.default);
+    info!("    Combo: {:?}", config.palette.combo());
+    for custom in &config.palette.custom {
+        info!("    Custom: {}", custom.name);
+    }
+    info!("  Screenshot:");
+    info!("    Combo: {:?}", config.screenshot.combo());
+    info!(
+        "    Directory: {}",
+        config
+            .screenshot
+            .directory(&config.roms_directory)
+            .display()
+    );
+    info!("  Recording:");
+    info!("    Combo: {:?}", config.recording.combo());
+    info!(
+        "    Directory: {}",
+        config.recording.directory(&config.roms_directory).display()
+    );
+    info!("  Shutdown:");
+    info!("    Command: {:?}", config.shutdown.command);
+    info!(
+        "    Timeout (ms): {}",
+        config.shutdown.timeout().as_millis()
+    );
+    if let Some(slot) = config.shutdown.autosave_slot {
+        info!("    Autosave Slot: {slot}");
+    }
+    info!("  Battery Save:");
+    info!(
+        "    Flush Interval (ms): {}",
+        config.battery_save.flush_interval().as_millis()
+    );
+    info!("    Backups: {}", config.battery_save.backups);
+    info!("    Extension: {}", config.battery_save.extension);
+    if let Some(rewind) = &config.rewind {
+        info!("  Rewind:");
+        info!("    Combo: {:?}", rewind.combo());
+        info!("    Interval (frames): {}", rewind.interval_frames);
+        info!("    Capacity: {}", rewind.capacity);
     }
 }
 
@@ -557,3 +1097,111 @@ fn init_app_log(level: args::LogLevel) -> anyhow::Result<()> {
         .try_init()
         .map_err(|e| anyhow::anyhow!("Failed to initialize logger: {}", e))
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_should_save_state_on_power_off_with_frames_disconnected() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom_file = dir.path().join("game.gb");
+        let mut rom = vec![0; 0x8000];
+        // JR -2: spin forever at the entry point
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+        std::fs::write(&rom_file, rom).unwrap();
+        let cpu = Box::new(Device::new(&rom_file, true).unwrap());
+
+        let (gb_event_sender, gb_event_receiver) = mpsc::channel();
+        let (video_sender, video_receiver) = mpsc::sync_channel(1);
+        let cpu_thread = thread::spawn(move || {
+            run_cpu(
+                cpu,
+                // a frame is sent in every period, before the events are read
+                Speed::Multiplier(2.0),
+                Duration::from_secs(5),
+                video_sender,
+                gb_event_receiver,
+                None,
+            )
+        });
+        // the CPU thread is in its loop, waiting for the next period after sending a frame
+        video_receiver.recv().unwrap();
+        video_receiver.recv().unwrap();
+
+        // as run_emulator stops
+        gb_event_sender
+            .send(GBEvent::PowerOff {
+                autosave_slot: Some(1),
+            })
+            .unwrap();
+        drop(video_receiver);
+        cpu_thread.join().unwrap();
+
+        assert!(rom_file.with_extension("ss1").exists());
+    }
+
+    /// Wait for the CPU thread to stop, failing instead of hanging when it doesn't
+    fn join_cpu_thread(cpu_thread: thread::JoinHandle<Option<DebugServer>>) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !cpu_thread.is_finished() {
+            assert!(Instant::now() < deadline, "the CPU thread did not stop");
+            thread::sleep(Duration::from_millis(10));
+        }
+        cpu_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_should_stop_cpu_thread_while_suspended() {
+        let cpu = Box::new(Device::new_from_buffer(vec![0; 0x8000], true).unwrap());
+
+        let (gb_event_sender, gb_event_receiver) = mpsc::channel();
+        let (video_sender, video_receiver) = mpsc::sync_channel(1);
+        let cpu_thread = thread::spawn(move || {
+            run_cpu(
+                cpu,
+                Speed::Uncapped,
+                Duration::from_secs(5),
+                video_sender,
+                gb_event_receiver,
+                None,
+            )
+        });
+        gb_event_sender.send(GBEvent::Suspend(true)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        // as run_emulator stops
+        drop(video_receiver);
+        drop(gb_event_sender);
+        join_cpu_thread(cpu_thread);
+    }
+
+    #[test]
+    fn test_should_stop_cpu_thread_while_debugger_paused() {
+        let dir = tempfile::tempdir().unwrap();
+        let endpoint = debug_server::DebugEndpoint::Socket(dir.path().join("debug.sock"));
+        let debugger = DebugServer::start(&endpoint).unwrap();
+        let cpu = Box::new(Device::new_from_buffer(vec![0; 0x8000], true).unwrap());
+
+        let (gb_event_sender, gb_event_receiver) = mpsc::channel();
+        let (video_sender, video_receiver) = mpsc::sync_channel(1);
+        let cpu_thread = thread::spawn(move || {
+            run_cpu(
+                cpu,
+                Speed::Uncapped,
+                Duration::from_secs(5),
+                video_sender,
+                gb_event_receiver,
+                // the emulation starts paused, waiting for a continue
+                Some(debugger),
+            )
+        });
+        thread::sleep(Duration::from_millis(50));
+
+        // as run_emulator stops
+        drop(video_receiver);
+        drop(gb_event_sender);
+        join_cpu_thread(cpu_thread);
+    }
+}
@@ -0,0 +1,275 @@
+use serde::{Deserialize, Serialize};
+
+use crate::header::CartridgeHeader;
+use crate::mbc::{Mbc, ram_banks, rom_banks};
+use crate::{Error, Result};
+
+/// Width of the pictures taken by the Pocket Camera
+pub const CAMERA_WIDTH: usize = 128;
+/// Height of the pictures taken by the Pocket Camera
+pub const CAMERA_HEIGHT: usize = 112;
+
+/// Number of registers of the camera sensor
+const REGISTERS: usize = 0x36;
+/// First register of the 4x4 dithering matrix, made of 3 thresholds per pixel
+const MATRIX: usize = 0x06;
+/// Offset in RAM bank 0 where the captured picture is written, as 2bpp tiles
+const PICTURE: usize = 0x0100;
+/// Size of the captured picture in RAM
+const PICTURE_SIZE: usize = CAMERA_WIDTH * CAMERA_HEIGHT / 4;
+
+/// Source of the pictures taken by the Pocket Camera, e.g. a webcam
+pub trait CameraSource: Send {
+    /// Take a picture of [`CAMERA_WIDTH`] x [`CAMERA_HEIGHT`] pixels, as brightness values from
+    /// 0 (black) to 255 (white), row by row
+    fn capture(&mut self) -> Vec<u8>;
+}
+
+/// Pocket Camera (Game Boy Camera).
+///
+/// Writing 0x10 to 0x4000-0x5FFF maps the registers of the camera sensor at 0xA000 instead of
+/// the RAM. Starting a capture takes a picture from the attached [`CameraSource`], or a test
+/// gradient when there is none, and writes it to RAM bank 0 using the exposure and dithering
+/// matrix set in the registers. The capture completes immediately; edge enhancement and gain
+/// are not emulated.
+#[derive(Serialize, Deserialize)]
+pub struct PocketCamera {
+    rom: Vec<u8>,
+    /// Header of the cartridge, read once from the ROM whatever gets mapped later
+    header: CartridgeHeader,
+    ram: Vec<u8>,
+    ram_on: bool,
+    ram_updated: bool,
+    rombank: usize,
+    rambank: usize,
+    rombanks: usize,
+    rambanks: usize,
+    camera_mapped: bool,
+    registers: Vec<u8>,
+    #[serde(skip)]
+    source: Option<Box<dyn CameraSource>>,
+}
+
+impl PocketCamera {
+    pub fn new(data: Vec<u8>) -> Result<PocketCamera> {
+        let rombanks = rom_banks(data[0x148]);
+        let rambanks = ram_banks(data[0x149]);
+
+        let res = PocketCamera {
+            header: CartridgeHeader::read(&data),
+            rom: data,
+            ram: vec![0; rambanks * 0x2000],
+            ram_on: false,
+            ram_updated: false,
+            rombank: 1,
+            rambank: 0,
+            rombanks,
+            rambanks,
+            camera_mapped: false,
+            registers: vec![0; REGISTERS],
+            source: None,
+        };
+
+        Ok(res)
+    }
+
+    fn ram_address(&self, a: u16) -> Option<usize> {
+        (self.rambank < self.rambanks).then(|| (self.rambank * 0x2000) | ((a as usize) & 0x1FFF))
+    }
+
+    /// Take a picture and write it to the RAM
+    fn capture(&mut self) {
+        if self.ram.len() < PICTURE + PICTURE_SIZE {
+            warn!("Ignoring camera capture, the cartridge RAM is too small for the picture");
+            return;
+        }
+        let picture = match self.source.as_mut() {
+            Some(source) => source.capture(),
+            None => (0..CAMERA_WIDTH * CAMERA_HEIGHT)
+                .map(|i| ((i % CAMERA_WIDTH) * 2) as u8)
+                .collect(),
+        };
+        if picture.len() != CAMERA_WIDTH * CAMERA_HEIGHT {
+            warn!("Ignoring camera picture of {} pixels", picture.len());
+            return;
+        }
+
+        let exposure = u16::from_be_bytes([self.registers[2], self.registers[3]]) as u32;
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let value = (picture[y * CAMERA_WIDTH + x] as u32 * exposure / 0x0300).min(255);
+                let matrix = MATRIX + ((y & 3) * 4 + (x & 3)) * 3;
+                let thresholds = &self.registers[matrix..matrix + 3];
+                let shade = thresholds.iter().filter(|&&t| value < t as u32).count() as u8;
+
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let address = PICTURE + tile * 16 + (y % 8) * 2;
+                let bit = 0x80 >> (x % 8);
+                for (plane, mask) in [(0, 0x01), (1, 0x02)] {
+                    if shade & mask != 0 {
+                        self.ram[address + plane] |= bit;
+                    } else {
+                        self.ram[address + plane] &= !bit;
+                    }
+                }
+            }
+        }
+        self.ram_updated = true;
+    }
+}
+
+#[typetag::serde]
+impl Mbc for PocketCamera {
+    fn readrom(&self, a: u16) -> u8 {
+        let bank = if a < 0x4000 { 0 } else { self.rombank };
+        let idx = (bank * 0x4000) | ((a as usize) & 0x3FFF);
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if self.camera_mapped {
+            // only the capture register can be read back
+            return match a & 0x7F {
+                0 => self.registers[0] & 0x07,
+                _ => 0x00,
+            };
+        }
+        // reading the RAM doesn't need it to be enabled
+        self.ram_address(a).map(|i| self.ram[i]).unwrap_or(0xFF)
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000..=0x1FFF => self.ram_on = v & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rombank = ((v as usize) & 0x3F) % self.rombanks.max(1),
+            0x4000..=0x5FFF => {
+                self.camera_mapped = v & 0x10 != 0;
+                self.rambank = (v as usize) & 0x0F;
+            }
+            0x6000..=0x7FFF => {}
+            _ => panic!("Could not write to {:04X} (Pocket Camera)", a),
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if self.camera_mapped {
+            let register = (a & 0x7F) as usize;
+            if register < REGISTERS {
+                self.registers[register] = v;
+            }
+            if register == 0 && v & 0x01 != 0 {
+                self.capture();
+                self.registers[0] &= !0x01;
+            }
+            return;
+        }
+        if !self.ram_on {
+            return;
+        }
+        if let Some(i) = self.ram_address(a) {
+            self.ram[i] = v;
+            self.ram_updated = true;
+        }
+    }
+
+    fn header(&self) -> CartridgeHeader {
+        self.header.clone()
+    }
+
+    fn take_rom(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.rom)
+    }
+
+    fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err(Error::InvalidRamSize {
+                expected: self.ram.len(),
+                actual: ramdata.len(),
+            });
+        }
+
+        self.ram = ramdata.to_vec();
+
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        std::mem::take(&mut self.ram_updated)
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rombank
+    }
+
+    fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.source = Some(source);
+    }
+
+    fn take_camera_source(&mut self) -> Option<Box<dyn CameraSource>> {
+        self.source.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CAMERA_HEIGHT, CAMERA_WIDTH, CameraSource, PICTURE, PocketCamera};
+    use crate::mbc::Mbc;
+
+    struct Black;
+
+    impl CameraSource for Black {
+        fn capture(&mut self) -> Vec<u8> {
+            vec![0; CAMERA_WIDTH * CAMERA_HEIGHT]
+        }
+    }
+
+    #[test]
+    fn capture_writes_dithered_picture() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0xFC;
+        rom[0x149] = 0x04;
+        let mut mbc = PocketCamera::new(rom).unwrap();
+        mbc.set_camera_source(Box::new(Black));
+
+        mbc.writerom(0x4000, 0x10);
+        // exposure 0x0300, thresholds 0x40, 0x80, 0xC0 for every pixel
+        mbc.writeram(0xA002, 0x03);
+        for i in 0..16 {
+            for (j, t) in [0x40, 0x80, 0xC0].into_iter().enumerate() {
+                mbc.writeram(0xA006 + i * 3 + j as u16, t);
+            }
+        }
+        mbc.writeram(0xA000, 0x01);
+        assert_eq!(mbc.readram(0xA000), 0x00);
+        assert!(mbc.check_and_reset_ram_updated());
+
+        mbc.writerom(0x4000, 0x00);
+        // black pixels use shade 3: both bit planes set
+        assert_eq!(mbc.readram(0xA000 + PICTURE as u16), 0xFF);
+        assert_eq!(mbc.readram(0xA000 + PICTURE as u16 + 1), 0xFF);
+        assert!(mbc.take_camera_source().is_some());
+    }
+
+    #[test]
+    fn capture_without_ram_is_ignored() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0xFC;
+        let mut mbc = PocketCamera::new(rom).unwrap();
+
+        mbc.writerom(0x4000, 0x10);
+        mbc.writeram(0xA000, 0x01);
+        assert_eq!(mbc.readram(0xA000), 0x00);
+    }
+}
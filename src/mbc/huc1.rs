@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+
+use crate::header::CartridgeHeader;
+use crate::mbc::{Mbc, ram_banks, rom_banks};
+use crate::{Error, Result};
+
+/// Hudson HuC1: MBC1-like banking, with an infrared port mapped instead of the RAM on demand
+#[derive(Serialize, Deserialize)]
+pub struct HuC1 {
+    rom: Vec<u8>,
+    /// Header of the cartridge, read once from the ROM whatever gets mapped later
+    header: CartridgeHeader,
+    ram: Vec<u8>,
+    ir_mode: bool,
+    ram_updated: bool,
+    rombank: usize,
+    rambank: usize,
+    rombanks: usize,
+    rambanks: usize,
+}
+
+impl HuC1 {
+    pub fn new(data: Vec<u8>) -> Result<HuC1> {
+        let rombanks = rom_banks(data[0x148]);
+        let rambanks = ram_banks(data[0x149]);
+
+        let res = HuC1 {
+            header: CartridgeHeader::read(&data),
+            rom: data,
+            ram: vec![0; rambanks * 0x2000],
+            ir_mode: false,
+            ram_updated: false,
+            rombank: 1,
+            rambank: 0,
+            rombanks,
+            rambanks,
+        };
+
+        Ok(res)
+    }
+
+    fn ram_address(&self, a: u16) -> Option<usize> {
+        (self.rambank < self.rambanks).then(|| (self.rambank * 0x2000) | ((a as usize) & 0x1FFF))
+    }
+}
+
+#[typetag::serde]
+impl Mbc for HuC1 {
+    fn readrom(&self, a: u16) -> u8 {
+        let bank = if a < 0x4000 { 0 } else { self.rombank };
+        let idx = (bank * 0x4000) | ((a as usize) & 0x3FFF);
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if self.ir_mode {
+            // no light received
+            return 0xC0;
+        }
+        self.ram_address(a).map(|i| self.ram[i]).unwrap_or(0xFF)
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000..=0x1FFF => self.ir_mode = v & 0x0F == 0x0E,
+            0x2000..=0x3FFF => {
+                self.rombank = match (v as usize) & 0x3F {
+                    0 => 1,
+                    n => n,
+                } % self.rombanks.max(1)
+            }
+            0x4000..=0x5FFF => self.rambank = (v as usize) & 0x03,
+            0x6000..=0x7FFF => {}
+            _ => panic!("Could not write to {:04X} (HuC1)", a),
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if self.ir_mode {
+            // the infrared LED is not emulated
+            return;
+        }
+        if let Some(i) = self.ram_address(a) {
+            self.ram[i] = v;
+            self.ram_updated = true;
+        }
+    }
+
+    fn header(&self) -> CartridgeHeader {
+        self.header.clone()
+    }
+
+    fn take_rom(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.rom)
+    }
+
+    fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err(Error::InvalidRamSize {
+                expected: self.ram.len(),
+                actual: ramdata.len(),
+            });
+        }
+
+        self.ram = ramdata.to_vec();
+
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        std::mem::take(&mut self.ram_updated)
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rombank
+    }
+}
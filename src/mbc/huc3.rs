@@ -0,0 +1,276 @@
+use std::time;
+
+use serde::{Deserialize, Serialize};
+
+use crate::header::CartridgeHeader;
+use crate::mbc::{Mbc, ram_banks, rom_banks};
+use crate::{Error, Result};
+
+/// Size of the RTC memory, in nibbles
+const RTC_MEMORY_SIZE: usize = 0x100;
+
+/// Hudson HuC3: ROM and RAM banking, an infrared port and a real time clock counting minutes
+/// and days.
+///
+/// The clock is accessed with commands written to 0xA000 while in RTC mode. Each command is
+/// a nibble followed by its argument nibble, and works on a small nibble memory: the clock is
+/// copied to and from its first 6 nibbles (minute of the day then day, both 12 bits).
+#[derive(Serialize, Deserialize)]
+pub struct HuC3 {
+    rom: Vec<u8>,
+    /// Header of the cartridge, read once from the ROM whatever gets mapped later
+    header: CartridgeHeader,
+    ram: Vec<u8>,
+    rombank: usize,
+    rambank: usize,
+    rombanks: usize,
+    rambanks: usize,
+    /// Register 0x0000-0x1FFF, selecting what is mapped at 0xA000-0xBFFF
+    mode: u8,
+    ram_updated: bool,
+    /// Unix time at which the clock was zero
+    rtc_zero: u64,
+    rtc_memory: Vec<u8>,
+    rtc_index: u8,
+    rtc_response: u8,
+}
+
+impl HuC3 {
+    pub fn new(data: Vec<u8>) -> Result<HuC3> {
+        let rombanks = rom_banks(data[0x148]);
+        let rambanks = ram_banks(data[0x149]);
+
+        let res = HuC3 {
+            header: CartridgeHeader::read(&data),
+            rom: data,
+            ram: vec![0; rambanks * 0x2000],
+            rombank: 1,
+            rambank: 0,
+            rombanks,
+            rambanks,
+            mode: 0,
+            ram_updated: false,
+            rtc_zero: now(),
+            rtc_memory: vec![0; RTC_MEMORY_SIZE],
+            rtc_index: 0,
+            rtc_response: 0,
+        };
+
+        Ok(res)
+    }
+
+    fn ram_address(&self, a: u16) -> Option<usize> {
+        (self.rambank < self.rambanks).then(|| (self.rambank * 0x2000) | ((a as usize) & 0x1FFF))
+    }
+
+    /// Copy the clock to the first nibbles of the RTC memory
+    fn latch_clock(&mut self) {
+        let elapsed = now().saturating_sub(self.rtc_zero);
+        let minutes = (elapsed / 60) % (24 * 60);
+        let days = (elapsed / (24 * 3600)) & 0xFFF;
+        for i in 0..3 {
+            self.rtc_memory[i] = ((minutes >> (4 * i)) & 0xF) as u8;
+            self.rtc_memory[3 + i] = ((days >> (4 * i)) & 0xF) as u8;
+        }
+    }
+
+    /// Set the clock from the first nibbles of the RTC memory
+    fn set_clock(&mut self) {
+        let value = |start: usize| {
+            (0..3).fold(0u64, |v, i| {
+                v | ((self.rtc_memory[start + i] as u64) << (4 * i))
+            })
+        };
+        let seconds = value(0) * 60 + value(3) * 24 * 3600;
+        self.rtc_zero = now().saturating_sub(seconds);
+        self.ram_updated = true;
+    }
+
+    fn rtc_command(&mut self, v: u8) {
+        let arg = v & 0x0F;
+        let index = self.rtc_index as usize;
+        match v >> 4 {
+            // read and advance
+            0x1 => {
+                self.rtc_response = 0x10 | self.rtc_memory[index];
+                self.rtc_index = self.rtc_index.wrapping_add(1);
+            }
+            // write, and advance for 0x3
+            0x2 | 0x3 => {
+                self.rtc_memory[index] = arg;
+                self.ram_updated = true;
+                if v >> 4 == 0x3 {
+                    self.rtc_index = self.rtc_index.wrapping_add(1);
+                }
+            }
+            0x4 => self.rtc_index = (self.rtc_index & 0xF0) | arg,
+            0x5 => self.rtc_index = (self.rtc_index & 0x0F) | (arg << 4),
+            0x6 => match arg {
+                0x0 => self.latch_clock(),
+                0x1 => self.set_clock(),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|t| t.as_secs())
+        .unwrap_or_default()
+}
+
+#[typetag::serde]
+impl Mbc for HuC3 {
+    fn readrom(&self, a: u16) -> u8 {
+        let bank = if a < 0x4000 { 0 } else { self.rombank };
+        let idx = (bank * 0x4000) | ((a as usize) & 0x3FFF);
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        match self.mode {
+            0x0 | 0xA => self.ram_address(a).map(|i| self.ram[i]).unwrap_or(0xFF),
+            0xC => self.rtc_response,
+            // the clock is always ready for the next command
+            0xD => 0x01,
+            // no light received
+            0xE => 0xC0,
+            _ => 0xFF,
+        }
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000..=0x1FFF => self.mode = v & 0x0F,
+            0x2000..=0x3FFF => self.rombank = ((v as usize) & 0x7F) % self.rombanks.max(1),
+            0x4000..=0x5FFF => self.rambank = (v as usize) & 0x03,
+            0x6000..=0x7FFF => {}
+            _ => panic!("Could not write to {:04X} (HuC3)", a),
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        match self.mode {
+            0xA => {
+                if let Some(i) = self.ram_address(a) {
+                    self.ram[i] = v;
+                    self.ram_updated = true;
+                }
+            }
+            0xB => self.rtc_command(v),
+            _ => {}
+        }
+    }
+
+    fn header(&self) -> CartridgeHeader {
+        self.header.clone()
+    }
+
+    fn take_rom(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.rom)
+    }
+
+    fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        // the raw RAM, as saved by other emulators
+        if ramdata.len() == self.ram.len() {
+            self.ram = ramdata.to_vec();
+            return Ok(());
+        }
+
+        let expected = 8 + RTC_MEMORY_SIZE + self.ram.len();
+        if ramdata.len() != expected {
+            return Err(Error::InvalidRamSize {
+                expected,
+                actual: ramdata.len(),
+            });
+        }
+
+        let (rtc_zero, rest) = ramdata.split_at(8);
+        let (rtc_memory, ram) = rest.split_at(RTC_MEMORY_SIZE);
+        self.rtc_zero = u64::from_be_bytes(rtc_zero.try_into().unwrap());
+        self.rtc_memory = rtc_memory.to_vec();
+        self.ram = ram.to_vec();
+
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        let mut data = self.rtc_zero.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.rtc_memory);
+        data.extend_from_slice(&self.ram);
+        data
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        std::mem::take(&mut self.ram_updated)
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rombank
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HuC3, now};
+    use crate::mbc::Mbc;
+
+    fn huc3() -> HuC3 {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0xFE;
+        rom[0x149] = 0x02;
+        HuC3::new(rom).unwrap()
+    }
+
+    fn command(mbc: &mut HuC3, v: u8) -> u8 {
+        mbc.writerom(0x0000, 0x0B);
+        mbc.writeram(0xA000, v);
+        mbc.writerom(0x0000, 0x0C);
+        mbc.readram(0xA000)
+    }
+
+    #[test]
+    fn clock_is_set_and_read_through_commands() {
+        let mut mbc = huc3();
+        // 0x123 minutes, day 0x045
+        command(&mut mbc, 0x40);
+        for nibble in [0x3, 0x2, 0x1, 0x5, 0x4, 0x0] {
+            command(&mut mbc, 0x30 | nibble);
+        }
+        command(&mut mbc, 0x61);
+        let expected = 0x123 * 60 + 0x45 * 24 * 3600;
+        assert!((expected..=expected + 1).contains(&(now() - mbc.rtc_zero)));
+
+        mbc.rtc_memory[..6].fill(0);
+        command(&mut mbc, 0x60);
+        command(&mut mbc, 0x40);
+        let nibbles: Vec<u8> = (0..6).map(|_| command(&mut mbc, 0x10) & 0x0F).collect();
+        assert_eq!(nibbles, vec![0x3, 0x2, 0x1, 0x5, 0x4, 0x0]);
+    }
+
+    #[test]
+    fn ram_is_read_only_in_mode_0() {
+        let mut mbc = huc3();
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writeram(0xA000, 0x42);
+        mbc.writerom(0x0000, 0x00);
+        mbc.writeram(0xA000, 0x24);
+        assert_eq!(mbc.readram(0xA000), 0x42);
+
+        let ram = mbc.dumpram();
+        let mut other = huc3();
+        other.loadram(&ram).unwrap();
+        assert_eq!(other.readram(0xA000), 0x42);
+    }
+}
@@ -1,16 +1,22 @@
 use serde::{Deserialize, Serialize};
 
-use crate::StrResult;
+use crate::Result;
+use crate::header::CartridgeHeader;
 use crate::mbc::Mbc;
 
 #[derive(Serialize, Deserialize)]
 pub struct MBC0 {
     rom: Vec<u8>,
+    /// Header of the cartridge, read once from the ROM whatever gets mapped later
+    header: CartridgeHeader,
 }
 
 impl MBC0 {
-    pub fn new(data: Vec<u8>) -> StrResult<MBC0> {
-        Ok(MBC0 { rom: data })
+    pub fn new(data: Vec<u8>) -> Result<MBC0> {
+        Ok(MBC0 {
+            header: CartridgeHeader::read(&data),
+            rom: data,
+        })
     }
 }
 
@@ -25,10 +31,22 @@ impl Mbc for MBC0 {
     fn writerom(&mut self, _a: u16, _v: u8) {}
     fn writeram(&mut self, _a: u16, _v: u8) {}
 
+    fn header(&self) -> CartridgeHeader {
+        self.header.clone()
+    }
+
+    fn take_rom(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.rom)
+    }
+
+    fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
     fn is_battery_backed(&self) -> bool {
         false
     }
-    fn loadram(&mut self, _ramdata: &[u8]) -> StrResult<()> {
+    fn loadram(&mut self, _ramdata: &[u8]) -> Result<()> {
         Ok(())
     }
     fn dumpram(&self) -> Vec<u8> {
@@ -1,24 +1,35 @@
 use serde::{Deserialize, Serialize};
 
-use crate::StrResult;
+use crate::header::CartridgeHeader;
 use crate::mbc::{Mbc, ram_banks, rom_banks};
+use crate::{Error, Result};
+
+/// Size of the multicart ROMs, which are wired as 4 games of 256 KiB
+const MULTICART_ROM_BANKS: usize = 0x40;
 
 #[derive(Serialize, Deserialize)]
 pub struct MBC1 {
     rom: Vec<u8>,
+    /// Header of the cartridge, read once from the ROM whatever gets mapped later
+    header: CartridgeHeader,
     ram: Vec<u8>,
     ram_on: bool,
     ram_updated: bool,
     banking_mode: u8,
+    /// BANK1 register (0x2000-0x3FFF): lower bits of the ROM bank, never 0
     rombank: usize,
+    /// BANK2 register (0x4000-0x5FFF): upper bits of the ROM bank, or the RAM bank
     rambank: usize,
     has_battery: bool,
     rombanks: usize,
     rambanks: usize,
+    /// MBC1M wiring: bit 4 of BANK1 is not connected, and BANK2 selects one of the games
+    #[serde(default)]
+    multicart: bool,
 }
 
 impl MBC1 {
-    pub fn new(data: Vec<u8>) -> StrResult<MBC1> {
+    pub fn new(data: Vec<u8>) -> Result<MBC1> {
         let (has_battery, rambanks) = match data[0x147] {
             0x02 => (false, ram_banks(data[0x149])),
             0x03 => (true, ram_banks(data[0x149])),
@@ -26,8 +37,13 @@ impl MBC1 {
         };
         let rombanks = rom_banks(data[0x148]);
         let ramsize = rambanks * 0x2000;
+        let multicart = is_multicart(&data, rombanks);
+        if multicart {
+            info!("MBC1 multicart detected");
+        }
 
         let res = MBC1 {
+            header: CartridgeHeader::read(&data),
             rom: data,
             ram: std::iter::repeat_n(0u8, ramsize).collect(),
             ram_on: false,
@@ -38,37 +54,76 @@ impl MBC1 {
             has_battery,
             rombanks,
             rambanks,
+            multicart,
         };
 
         Ok(res)
     }
+
+    /// Number of bits of BANK1 used for the ROM bank
+    fn bank1_bits(&self) -> u32 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    /// ROM bank mapped at 0x0000-0x3FFF: BANK2 is applied there in mode 1
+    fn rombank_low(&self) -> usize {
+        let bank = if self.banking_mode == 1 {
+            self.rambank << self.bank1_bits()
+        } else {
+            0
+        };
+        bank % self.rombanks.max(1)
+    }
+
+    /// ROM bank mapped at 0x4000-0x7FFF
+    fn rombank_high(&self) -> usize {
+        let bank1 = self.rombank & ((1 << self.bank1_bits()) - 1);
+        let bank = (self.rambank << self.bank1_bits()) | bank1;
+        bank % self.rombanks.max(1)
+    }
+
+    /// Address in `ram` of an access to 0xA000-0xBFFF; BANK2 selects the RAM bank in mode 1
+    fn ram_address(&self, a: u16) -> Option<usize> {
+        if !self.ram_on || self.ram.is_empty() {
+            return None;
+        }
+        let rambank = if self.banking_mode == 1 {
+            self.rambank % self.rambanks
+        } else {
+            0
+        };
+        Some(((rambank * 0x2000) | ((a & 0x1FFF) as usize)) % self.ram.len())
+    }
+}
+
+/// Detect a MBC1M multicart: a 1 MiB ROM with the header of another game at bank 0x10
+fn is_multicart(data: &[u8], rombanks: usize) -> bool {
+    const LOGO: std::ops::Range<usize> = 0x104..0x134;
+
+    if rombanks != MULTICART_ROM_BANKS {
+        return false;
+    }
+    let game_start = 0x10 * 0x4000;
+    data.get(game_start + LOGO.start..game_start + LOGO.end)
+        .is_some_and(|logo| logo == &data[LOGO])
 }
 
 #[typetag::serde]
 impl Mbc for MBC1 {
     fn readrom(&self, a: u16) -> u8 {
         let bank = if a < 0x4000 {
-            if self.banking_mode == 0 {
-                0
-            } else {
-                self.rombank & 0xE0
-            }
+            self.rombank_low()
         } else {
-            self.rombank
+            self.rombank_high()
         };
         let idx = (bank * 0x4000) | ((a as usize) & 0x3FFF);
         *self.rom.get(idx).unwrap_or(&0xFF)
     }
     fn readram(&self, a: u16) -> u8 {
-        if !self.ram_on {
-            return 0xFF;
+        match self.ram_address(a) {
+            Some(address) => self.ram[address],
+            None => 0xFF,
         }
-        let rambank = if self.banking_mode == 1 {
-            self.rambank
-        } else {
-            0
-        };
-        self.ram[(rambank * 0x2000) | ((a & 0x1FFF) as usize)]
     }
 
     fn writerom(&mut self, a: u16, v: u8) {
@@ -77,20 +132,14 @@ impl Mbc for MBC1 {
                 self.ram_on = v & 0xF == 0xA;
             }
             0x2000..=0x3FFF => {
-                let lower_bits = match (v as usize) & 0x1F {
+                // the zero check applies to all 5 bits, even when bit 4 is not connected
+                self.rombank = match (v as usize) & 0x1F {
                     0 => 1,
                     n => n,
                 };
-                self.rombank = ((self.rombank & 0x60) | lower_bits) % self.rombanks;
             }
             0x4000..=0x5FFF => {
-                if self.rombanks > 0x20 {
-                    let upper_bits = (v as usize & 0x03) % (self.rombanks >> 5);
-                    self.rombank = self.rombank & 0x1F | (upper_bits << 5)
-                }
-                if self.rambanks > 1 {
-                    self.rambank = (v as usize) & 0x03;
-                }
+                self.rambank = (v as usize) & 0x03;
             }
             0x6000..=0x7FFF => {
                 self.banking_mode = v & 0x01;
@@ -100,28 +149,34 @@ impl Mbc for MBC1 {
     }
 
     fn writeram(&mut self, a: u16, v: u8) {
-        if !self.ram_on {
-            return;
-        }
-        let rambank = if self.banking_mode == 1 {
-            self.rambank
-        } else {
-            0
-        };
-        let address = (rambank * 0x2000) | ((a & 0x1FFF) as usize);
-        if address < self.ram.len() {
+        if let Some(address) = self.ram_address(a) {
             self.ram[address] = v;
             self.ram_updated = true;
         }
     }
 
+    fn header(&self) -> CartridgeHeader {
+        self.header.clone()
+    }
+
+    fn take_rom(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.rom)
+    }
+
+    fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
     fn is_battery_backed(&self) -> bool {
         self.has_battery
     }
 
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
         if ramdata.len() != self.ram.len() {
-            return Err("Loaded RAM has incorrect length");
+            return Err(Error::InvalidRamSize {
+                expected: self.ram.len(),
+                actual: ramdata.len(),
+            });
         }
 
         self.ram = ramdata.to_vec();
@@ -138,4 +193,95 @@ impl Mbc for MBC1 {
         self.ram_updated = false;
         result
     }
+
+    fn rom_bank(&self) -> usize {
+        self.rombank_high()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC1;
+    use crate::mbc::Mbc;
+
+    /// ROM of `banks` banks, each filled with its number, with a cartridge header in `headers`
+    fn rom(banks: usize, headers: &[usize]) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..banks)
+            .flat_map(|bank| std::iter::repeat_n(bank as u8, 0x4000))
+            .collect();
+        for &bank in headers {
+            let start = bank * 0x4000;
+            rom[start + 0x104..start + 0x134].copy_from_slice(&[0xCE; 0x30]);
+        }
+        rom[0x147] = 0x03;
+        rom[0x148] = (banks / 2).trailing_zeros() as u8;
+        rom[0x149] = 0x03;
+        rom
+    }
+
+    #[test]
+    fn mode1_maps_upper_bits_in_low_area() {
+        let mut mbc = MBC1::new(rom(0x40, &[0])).unwrap();
+        assert!(!mbc.multicart);
+        mbc.writerom(0x2000, 0x00);
+        assert_eq!(mbc.readrom(0x4000), 0x01);
+        mbc.writerom(0x4000, 0x01);
+        assert_eq!(mbc.readrom(0x4000), 0x21);
+        assert_eq!(mbc.readrom(0x0000), 0x00);
+
+        mbc.writerom(0x6000, 0x01);
+        assert_eq!(mbc.readrom(0x0000), 0x20);
+        // BANK2 wraps around the ROM size
+        mbc.writerom(0x4000, 0x03);
+        assert_eq!(mbc.readrom(0x0000), 0x20);
+        assert_eq!(mbc.readrom(0x4000), 0x21);
+    }
+
+    #[test]
+    fn header_ignores_mapped_banks() {
+        let mut rom = rom(0x40, &[0]);
+        rom[0x134..0x138].copy_from_slice(b"GAME");
+        let mut mbc = MBC1::new(rom).unwrap();
+        let header = mbc.header();
+
+        mbc.writerom(0x4000, 0x01);
+        mbc.writerom(0x6000, 0x01);
+        assert_eq!(mbc.readrom(0x0134), 0x20);
+        assert_eq!(mbc.header(), header);
+        assert_eq!(mbc.romname(), "GAME");
+    }
+
+    #[test]
+    fn mode1_selects_ram_bank() {
+        let mut mbc = MBC1::new(rom(4, &[0])).unwrap();
+        assert_eq!(mbc.readram(0xA000), 0xFF);
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x02);
+        mbc.writeram(0xA000, 0x42);
+        mbc.writerom(0x6000, 0x01);
+        assert_eq!(mbc.readram(0xA000), 0x00);
+        mbc.writerom(0x6000, 0x00);
+        assert_eq!(mbc.readram(0xA000), 0x42);
+        // only the lower nibble enables the RAM
+        mbc.writerom(0x0000, 0x1A);
+        assert_eq!(mbc.readram(0xA000), 0x42);
+    }
+
+    #[test]
+    fn multicart_ignores_bank1_bit4() {
+        let mut mbc = MBC1::new(rom(0x40, &[0, 0x10, 0x20, 0x30])).unwrap();
+        assert!(mbc.multicart);
+
+        mbc.writerom(0x2000, 0x12);
+        assert_eq!(mbc.readrom(0x4000), 0x02);
+        // 0x10 is not zero, so it is not turned into 1
+        mbc.writerom(0x2000, 0x10);
+        assert_eq!(mbc.readrom(0x4000), 0x00);
+
+        mbc.writerom(0x4000, 0x02);
+        mbc.writerom(0x2000, 0x03);
+        assert_eq!(mbc.readrom(0x4000), 0x23);
+        mbc.writerom(0x6000, 0x01);
+        assert_eq!(mbc.readrom(0x0000), 0x20);
+    }
 }
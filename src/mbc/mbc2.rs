@@ -1,11 +1,14 @@
 use serde::{Deserialize, Serialize};
 
-use crate::StrResult;
+use crate::header::CartridgeHeader;
 use crate::mbc::{Mbc, rom_banks};
+use crate::{Error, Result};
 
 #[derive(Serialize, Deserialize)]
 pub struct MBC2 {
     rom: Vec<u8>,
+    /// Header of the cartridge, read once from the ROM whatever gets mapped later
+    header: CartridgeHeader,
     ram: Vec<u8>,
     ram_on: bool,
     ram_updated: bool,
@@ -15,11 +18,12 @@ pub struct MBC2 {
 }
 
 impl MBC2 {
-    pub fn new(data: Vec<u8>) -> StrResult<MBC2> {
+    pub fn new(data: Vec<u8>) -> Result<MBC2> {
         let has_battery = matches!(data[0x147], 0x06);
         let rombanks = rom_banks(data[0x148]);
 
         let res = MBC2 {
+            header: CartridgeHeader::read(&data),
             rom: data,
             ram: vec![0; 512],
             ram_on: false,
@@ -68,13 +72,28 @@ impl Mbc for MBC2 {
         self.ram_updated = true;
     }
 
+    fn header(&self) -> CartridgeHeader {
+        self.header.clone()
+    }
+
+    fn take_rom(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.rom)
+    }
+
+    fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
     fn is_battery_backed(&self) -> bool {
         self.has_battery
     }
 
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
         if ramdata.len() != self.ram.len() {
-            return Err("Loaded RAM has incorrect length");
+            return Err(Error::InvalidRamSize {
+                expected: self.ram.len(),
+                actual: ramdata.len(),
+            });
         }
 
         self.ram = ramdata.to_vec();
@@ -91,4 +110,8 @@ impl Mbc for MBC2 {
         self.ram_updated = false;
         result
     }
+
+    fn rom_bank(&self) -> usize {
+        self.rombank
+    }
 }
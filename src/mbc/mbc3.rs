@@ -1,15 +1,21 @@
 use std::convert::TryInto;
-use std::io::prelude::*;
-use std::time;
 
 use serde::{Deserialize, Serialize};
 
-use crate::StrResult;
+use crate::header::CartridgeHeader;
+use crate::mbc::rtc::{FOOTER_SIZE, Rtc, RtcClock, SHORT_FOOTER_SIZE};
 use crate::mbc::{Mbc, ram_banks};
+use crate::{Error, Result};
 
+/// MBC3, with the optional real time clock.
+///
+/// The battery save holds the RAM followed by the clock in the 48 byte footer used by most
+/// emulators.
 #[derive(Serialize, Deserialize)]
 pub struct MBC3 {
     rom: Vec<u8>,
+    /// Header of the cartridge, read once from the ROM whatever gets mapped later
+    header: CartridgeHeader,
     ram: Vec<u8>,
     rombank: usize,
     rambank: usize,
@@ -18,13 +24,11 @@ pub struct MBC3 {
     ram_on: bool,
     ram_updated: bool,
     has_battery: bool,
-    rtc_ram: [u8; 5],
-    rtc_ram_latch: [u8; 5],
-    rtc_zero: Option<u64>,
+    rtc: Option<Rtc>,
 }
 
 impl MBC3 {
-    pub fn new(data: Vec<u8>) -> StrResult<MBC3> {
+    pub fn new(data: Vec<u8>) -> Result<MBC3> {
         let subtype = data[0x147];
         let has_battery = matches!(subtype, 0x0F | 0x10 | 0x13);
         let rambanks = match subtype {
@@ -33,11 +37,12 @@ impl MBC3 {
         };
         let ramsize = rambanks * 0x2000;
         let rtc = match subtype {
-            0x0F | 0x10 => Some(0),
+            0x0F | 0x10 => Some(Rtc::new()),
             _ => None,
         };
 
         let res = MBC3 {
+            header: CartridgeHeader::read(&data),
             rom: data,
             ram: std::iter::repeat_n(0u8, ramsize).collect(),
             rombank: 1,
@@ -47,68 +52,11 @@ impl MBC3 {
             ram_on: false,
             ram_updated: false,
             has_battery,
-            rtc_ram: [0u8; 5],
-            rtc_ram_latch: [0u8; 5],
-            rtc_zero: rtc,
+            rtc,
         };
 
         Ok(res)
     }
-
-    fn latch_rtc_reg(&mut self) {
-        self.calc_rtc_reg();
-        self.rtc_ram_latch.clone_from_slice(&self.rtc_ram);
-    }
-
-    fn calc_rtc_reg(&mut self) {
-        // Do not modify regs when halted
-        if self.rtc_ram[4] & 0x40 == 0x40 {
-            return;
-        }
-
-        let tzero = match self.rtc_zero {
-            Some(t) => time::UNIX_EPOCH + time::Duration::from_secs(t),
-            None => return,
-        };
-
-        if self.compute_difftime() == self.rtc_zero {
-            // No time has passed. Do not alter registers
-            return;
-        }
-
-        let difftime = match time::SystemTime::now().duration_since(tzero) {
-            Ok(n) => n.as_secs(),
-            _ => 0,
-        };
-        self.rtc_ram[0] = (difftime % 60) as u8;
-        self.rtc_ram[1] = ((difftime / 60) % 60) as u8;
-        self.rtc_ram[2] = ((difftime / 3600) % 24) as u8;
-        let days = difftime / (3600 * 24);
-        self.rtc_ram[3] = days as u8;
-        self.rtc_ram[4] = (self.rtc_ram[4] & 0xFE) | (((days >> 8) & 0x01) as u8);
-        if days >= 512 {
-            self.rtc_ram[4] |= 0x80;
-            self.calc_rtc_zero();
-        }
-    }
-
-    fn compute_difftime(&self) -> Option<u64> {
-        self.rtc_zero?;
-        let mut difftime = match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
-            Ok(t) => t.as_secs(),
-            Err(_) => panic!("System clock is set to a time before the unix epoch (1970-01-01)"),
-        };
-        difftime -= self.rtc_ram[0] as u64;
-        difftime -= (self.rtc_ram[1] as u64) * 60;
-        difftime -= (self.rtc_ram[2] as u64) * 3600;
-        let days = ((self.rtc_ram[4] as u64 & 0x1) << 8) | (self.rtc_ram[3] as u64);
-        difftime -= days * 3600 * 24;
-        Some(difftime)
-    }
-
-    fn calc_rtc_zero(&mut self) {
-        self.rtc_zero = self.compute_difftime();
-    }
 }
 
 #[typetag::serde]
@@ -127,8 +75,10 @@ impl Mbc for MBC3 {
         }
         if !self.selectrtc && self.rambank < self.rambanks {
             self.ram[(self.rambank * 0x2000) | ((a as usize) & 0x1FFF)]
-        } else if self.selectrtc && self.rambank < 5 {
-            self.rtc_ram_latch[self.rambank]
+        } else if self.selectrtc
+            && let Some(rtc) = &self.rtc
+        {
+            rtc.read(self.rambank)
         } else {
             0xFF
         }
@@ -146,7 +96,11 @@ impl Mbc for MBC3 {
                 self.selectrtc = v & 0x8 == 0x8;
                 self.rambank = (v & 0x7) as usize;
             }
-            0x6000..=0x7FFF => self.latch_rtc_reg(),
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.latch();
+                }
+            }
             _ => panic!("Could not write to {:04X} (MBC3)", a),
         }
     }
@@ -157,53 +111,67 @@ impl Mbc for MBC3 {
         if !self.selectrtc && self.rambank < self.rambanks {
             self.ram[(self.rambank * 0x2000) | ((a as usize) & 0x1FFF)] = v;
             self.ram_updated = true;
-        } else if self.selectrtc && self.rambank < 5 {
-            self.calc_rtc_reg();
-            let vmask = match self.rambank {
-                0 | 1 => 0x3F,
-                2 => 0x1F,
-                4 => 0xC1,
-                _ => 0xFF,
-            };
-            self.rtc_ram[self.rambank] = v & vmask;
-            self.calc_rtc_zero();
+        } else if self.selectrtc
+            && let Some(rtc) = &mut self.rtc
+        {
+            rtc.write(self.rambank, v);
             self.ram_updated = true;
         }
     }
 
+    fn header(&self) -> CartridgeHeader {
+        self.header.clone()
+    }
+
+    fn take_rom(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.rom)
+    }
+
+    fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
     fn is_battery_backed(&self) -> bool {
         self.has_battery
     }
 
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
-        if ramdata.len() != 8 + self.ram.len() {
-            return Err("Loaded ram is too small");
-        }
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        let ramsize = self.ram.len();
+        let (ram, footer) = match ramdata.len().checked_sub(ramsize) {
+            Some(0) => (ramdata, None),
+            Some(FOOTER_SIZE | SHORT_FOOTER_SIZE) => {
+                let (ram, footer) = ramdata.split_at(ramsize);
+                (ram, Some(footer))
+            }
+            // older versions saved the time at which the clock was zero before the RAM
+            Some(8) => {
+                let (zero, ram) = ramdata.split_at(8);
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.load_zero(u64::from_be_bytes(zero.try_into().unwrap()));
+                }
+                (ram, None)
+            }
+            _ => {
+                return Err(Error::InvalidRamSize {
+                    expected: ramsize + if self.rtc.is_some() { FOOTER_SIZE } else { 0 },
+                    actual: ramdata.len(),
+                });
+            }
+        };
 
-        let (int_bytes, rest) = ramdata.split_at(8);
-        let rtc = u64::from_be_bytes(int_bytes.try_into().unwrap());
-        if self.rtc_zero.is_some() {
-            self.rtc_zero = Some(rtc);
+        if let (Some(rtc), Some(footer)) = (&mut self.rtc, footer) {
+            rtc.load_footer(footer);
         }
-        self.ram = rest.to_vec();
+        self.ram = ram.to_vec();
         Ok(())
     }
 
     fn dumpram(&self) -> Vec<u8> {
-        let rtc = self.rtc_zero.unwrap_or_default();
-
-        let mut file = vec![];
-
-        let mut ok = true;
-        if ok {
-            let rtc_bytes = rtc.to_be_bytes();
-            ok = file.write_all(&rtc_bytes).is_ok();
-        };
-        if ok {
-            let _ = file.write_all(&self.ram);
-        };
-
-        file
+        let mut data = self.ram.to_vec();
+        if let Some(rtc) = &self.rtc {
+            data.extend_from_slice(&rtc.footer());
+        }
+        data
     }
 
     fn check_and_reset_ram_updated(&mut self) -> bool {
@@ -211,4 +179,98 @@ impl Mbc for MBC3 {
         self.ram_updated = false;
         result
     }
+
+    fn rom_bank(&self) -> usize {
+        self.rombank
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        if let Some(rtc) = &mut self.rtc
+            && rtc.do_cycle(ticks)
+        {
+            self.ram_updated = true;
+        }
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_clock(clock);
+        }
+    }
+
+    fn take_rtc_clock(&mut self) -> Option<RtcClock> {
+        self.rtc.as_mut().and_then(Rtc::take_clock)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC3;
+    use crate::mbc::Mbc;
+    use crate::mbc::rtc::RtcClock;
+
+    fn mbc3() -> MBC3 {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x10; // MBC3+TIMER+RAM+BATTERY
+        rom[0x149] = 0x02;
+        MBC3::new(rom).unwrap()
+    }
+
+    fn read_rtc(mbc: &mut MBC3, register: u8) -> u8 {
+        mbc.writerom(0x4000, 0x08 | register);
+        mbc.readram(0xA000)
+    }
+
+    #[test]
+    fn save_holds_ram_and_clock() {
+        let mut mbc = mbc3();
+        mbc.set_rtc_clock(RtcClock::Fixed(1_000_000));
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writeram(0xA000, 0x42);
+        mbc.writerom(0x4000, 0x09);
+        mbc.writeram(0xA000, 12);
+
+        let save = mbc.dumpram();
+        assert_eq!(save.len(), 0x2000 + 48);
+
+        // two minutes later
+        let mut other = mbc3();
+        other.set_rtc_clock(RtcClock::Fixed(1_000_120));
+        other.loadram(&save).unwrap();
+        other.writerom(0x0000, 0x0A);
+        other.writerom(0x6000, 0x00);
+        other.writerom(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut other, 1), 14);
+        other.writerom(0x4000, 0x00);
+        assert_eq!(other.readram(0xA000), 0x42);
+    }
+
+    #[test]
+    fn loads_previous_save_format() {
+        let mut mbc = mbc3();
+        let zero = 1_000_000u64;
+        mbc.set_rtc_clock(RtcClock::Fixed(zero + 3 * 3600));
+        let mut save = zero.to_be_bytes().to_vec();
+        save.extend_from_slice(&[0x24; 0x2000]);
+        mbc.loadram(&save).unwrap();
+
+        mbc.writerom(0x0000, 0x0A);
+        assert_eq!(read_rtc(&mut mbc, 2), 3);
+        mbc.writerom(0x4000, 0x00);
+        assert_eq!(mbc.readram(0xA000), 0x24);
+        assert!(mbc.loadram(&[0; 0x2000 + 3]).is_err());
+    }
+
+    #[test]
+    fn emulated_clock_runs_with_cycles() {
+        let mut mbc = mbc3();
+        mbc.set_rtc_clock(RtcClock::Emulated);
+        mbc.do_cycle(4_194_304 * 5);
+        assert!(mbc.check_and_reset_ram_updated());
+
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc, 0), 5);
+        assert!(matches!(mbc.take_rtc_clock(), Some(RtcClock::Emulated)));
+    }
 }
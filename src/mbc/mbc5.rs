@@ -1,11 +1,20 @@
 use serde::{Deserialize, Serialize};
 
-use crate::StrResult;
+use crate::header::CartridgeHeader;
 use crate::mbc::{Mbc, ram_banks, rom_banks};
+use crate::{Error, Result};
+
+/// Receives the state of the rumble motor of a cartridge
+pub trait RumbleCallback: Send {
+    /// Called when the motor is turned on or off
+    fn call(&mut self, on: bool);
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct MBC5 {
     rom: Vec<u8>,
+    /// Header of the cartridge, read once from the ROM whatever gets mapped later
+    header: CartridgeHeader,
     ram: Vec<u8>,
     rombank: usize,
     rambank: usize,
@@ -14,12 +23,20 @@ pub struct MBC5 {
     has_battery: bool,
     rombanks: usize,
     rambanks: usize,
+    /// The cartridge has a rumble motor, driven by bit 3 of the RAM bank register
+    #[serde(default)]
+    has_rumble: bool,
+    #[serde(default)]
+    rumble_on: bool,
+    #[serde(skip)]
+    rumble: Option<Box<dyn RumbleCallback>>,
 }
 
 impl MBC5 {
-    pub fn new(data: Vec<u8>) -> StrResult<MBC5> {
+    pub fn new(data: Vec<u8>) -> Result<MBC5> {
         let subtype = data[0x147];
         let has_battery = matches!(subtype, 0x1B | 0x1E);
+        let has_rumble = matches!(subtype, 0x1C..=0x1E);
         let rambanks = match subtype {
             0x1A | 0x1B | 0x1D | 0x1E => ram_banks(data[0x149]),
             _ => 0,
@@ -28,6 +45,7 @@ impl MBC5 {
         let rombanks = rom_banks(data[0x148]);
 
         let res = MBC5 {
+            header: CartridgeHeader::read(&data),
             rom: data,
             ram: std::iter::repeat_n(0u8, ramsize).collect(),
             rombank: 1,
@@ -37,6 +55,9 @@ impl MBC5 {
             has_battery,
             rombanks,
             rambanks,
+            has_rumble,
+            rumble_on: false,
+            rumble: None,
         };
 
         Ok(res)
@@ -54,7 +75,7 @@ impl Mbc for MBC5 {
         *self.rom.get(idx).unwrap_or(&0)
     }
     fn readram(&self, a: u16) -> u8 {
-        if !self.ram_on {
+        if !self.ram_on || self.ram.is_empty() {
             return 0;
         }
         self.ram[(self.rambank * 0x2000) | ((a as usize) & 0x1FFF)]
@@ -69,26 +90,51 @@ impl Mbc for MBC5 {
                 self.rombank =
                     ((self.rombank & 0x0FF) | (((v & 0x1) as usize) << 8)) % self.rombanks
             }
-            0x4000..=0x5FFF => self.rambank = ((v & 0x0F) as usize) % self.rambanks,
+            0x4000..=0x5FFF if self.has_rumble => {
+                self.rambank = ((v & 0x07) as usize) % self.rambanks.max(1);
+                let on = v & 0x08 != 0;
+                if on != self.rumble_on {
+                    self.rumble_on = on;
+                    if let Some(rumble) = self.rumble.as_mut() {
+                        rumble.call(on);
+                    }
+                }
+            }
+            0x4000..=0x5FFF => self.rambank = ((v & 0x0F) as usize) % self.rambanks.max(1),
             0x6000..=0x7FFF => { /* ? */ }
             _ => panic!("Could not write to {:04X} (MBC5)", a),
         }
     }
     fn writeram(&mut self, a: u16, v: u8) {
-        if !self.ram_on {
+        if !self.ram_on || self.ram.is_empty() {
             return;
         }
         self.ram[(self.rambank * 0x2000) | ((a as usize) & 0x1FFF)] = v;
         self.ram_updated = true;
     }
 
+    fn header(&self) -> CartridgeHeader {
+        self.header.clone()
+    }
+
+    fn take_rom(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.rom)
+    }
+
+    fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
     fn is_battery_backed(&self) -> bool {
         self.has_battery
     }
 
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
         if ramdata.len() != self.ram.len() {
-            return Err("Loaded RAM has incorrect length");
+            return Err(Error::InvalidRamSize {
+                expected: self.ram.len(),
+                actual: ramdata.len(),
+            });
         }
 
         self.ram = ramdata.to_vec();
@@ -105,4 +151,52 @@ impl Mbc for MBC5 {
         self.ram_updated = false;
         result
     }
+
+    fn rom_bank(&self) -> usize {
+        self.rombank
+    }
+
+    fn set_rumble_callback(&mut self, mut callback: Box<dyn RumbleCallback>) {
+        if self.has_rumble {
+            // bring the motor in line with this cartridge, e.g. after loading a state
+            callback.call(self.rumble_on);
+        }
+        self.rumble = Some(callback);
+    }
+
+    fn take_rumble_callback(&mut self) -> Option<Box<dyn RumbleCallback>> {
+        self.rumble.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::{MBC5, RumbleCallback};
+    use crate::mbc::Mbc;
+
+    struct Recorder(Arc<Mutex<Vec<bool>>>);
+
+    impl RumbleCallback for Recorder {
+        fn call(&mut self, on: bool) {
+            self.0.lock().unwrap().push(on);
+        }
+    }
+
+    #[test]
+    fn rumble_changes_are_reported() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x1E;
+        rom[0x149] = 0x03;
+        let mut mbc = MBC5::new(rom).unwrap();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        mbc.set_rumble_callback(Box::new(Recorder(changes.clone())));
+
+        mbc.writerom(0x4000, 0x0A);
+        mbc.writerom(0x4000, 0x0B);
+        mbc.writerom(0x4000, 0x01);
+        assert_eq!(*changes.lock().unwrap(), vec![false, true, false]);
+        assert_eq!(mbc.rambank, 1);
+    }
 }
@@ -0,0 +1,269 @@
+use serde::{Deserialize, Serialize};
+
+use crate::header::CartridgeHeader;
+use crate::mbc::{Mbc, ram_banks};
+use crate::{Error, Result};
+
+/// Offset of the cartridge type in a header
+const CART_TYPE: usize = 0x147;
+
+/// MMM01, used by multi-game compilations.
+///
+/// At power up the cartridge is unmapped: the last 32 KiB of the ROM, which hold the menu and
+/// the header of the cartridge, are mapped at 0x0000-0x7FFF. The menu configures which part of
+/// the ROM and RAM the selected game sees, then maps it, which locks the configuration until
+/// the next power cycle. From then on the game sees a MBC1 limited to its own banks.
+#[derive(Serialize, Deserialize)]
+pub struct MMM01 {
+    rom: Vec<u8>,
+    /// Header of the cartridge, read once from the ROM whatever gets mapped later
+    header: CartridgeHeader,
+    ram: Vec<u8>,
+    ram_on: bool,
+    ram_updated: bool,
+    has_battery: bool,
+    rombanks: usize,
+    rambanks: usize,
+    /// First of the two banks holding the menu
+    menu_bank: usize,
+    /// A game is mapped and the configuration is locked
+    mapped: bool,
+    /// Bits 0-4 of the ROM bank
+    rombank_lo: usize,
+    /// Bits 5-6 of the ROM bank, selected by the menu
+    rombank_mid: usize,
+    /// Bits 7-8 of the ROM bank, selected by the menu
+    rombank_hi: usize,
+    /// Bits of `rombank_lo` which the game cannot change
+    rombank_mask: usize,
+    /// Bits 0-1 of the RAM bank
+    rambank_lo: usize,
+    /// Bits 2-3 of the RAM bank, selected by the menu
+    rambank_hi: usize,
+    banking_mode: u8,
+    /// The menu prevented the game from changing the banking mode
+    mode_locked: bool,
+}
+
+/// Whether `data` is the ROM of a MMM01 cartridge, which has its header in the last 32 KiB
+pub fn is_mmm01(data: &[u8]) -> bool {
+    menu_header(data).is_some_and(|header| matches!(data[header + CART_TYPE], 0x0B..=0x0D))
+}
+
+/// Offset of the 32 KiB holding the menu and the cartridge header
+fn menu_header(data: &[u8]) -> Option<usize> {
+    (data.len() >= 0x10000).then(|| data.len() - 0x8000)
+}
+
+impl MMM01 {
+    pub fn new(data: Vec<u8>) -> Result<MMM01> {
+        let header = menu_header(&data)
+            .filter(|&h| matches!(data[h + CART_TYPE], 0x0B..=0x0D))
+            .unwrap_or(0);
+        let (has_battery, rambanks) = match data[header + CART_TYPE] {
+            0x0C => (false, ram_banks(data[header + 0x149])),
+            0x0D => (true, ram_banks(data[header + 0x149])),
+            _ => (false, 0),
+        };
+        let rombanks = (data.len() / 0x4000).max(2);
+
+        let res = MMM01 {
+            header: CartridgeHeader::read(&data[header..]),
+            rom: data,
+            ram: vec![0; rambanks * 0x2000],
+            ram_on: false,
+            ram_updated: false,
+            has_battery,
+            rombanks,
+            rambanks,
+            menu_bank: header / 0x4000,
+            mapped: false,
+            rombank_lo: 0,
+            rombank_mid: 0,
+            rombank_hi: 0,
+            rombank_mask: 0,
+            rambank_lo: 0,
+            rambank_hi: 0,
+            banking_mode: 0,
+            mode_locked: false,
+        };
+
+        Ok(res)
+    }
+
+    /// ROM bank selected by the menu, which the game sees as bank 0
+    fn game_base(&self) -> usize {
+        (self.rombank_hi << 7) | (self.rombank_mid << 5) | (self.rombank_lo & self.rombank_mask)
+    }
+
+    fn rombank(&self, a: u16) -> usize {
+        if !self.mapped {
+            return self.menu_bank + (a as usize >> 14);
+        }
+        if a < 0x4000 {
+            return self.game_base() % self.rombanks;
+        }
+        // as on the MBC1, bank 0 of the game is replaced by bank 1
+        let game_bits = self.rombank_lo & !self.rombank_mask & 0x1F;
+        let lo = if game_bits == 0 {
+            self.rombank_lo | 1
+        } else {
+            self.rombank_lo
+        };
+        ((self.rombank_hi << 7) | (self.rombank_mid << 5) | lo) % self.rombanks
+    }
+
+    fn ram_address(&self, a: u16) -> Option<usize> {
+        if !self.ram_on || self.rambanks == 0 {
+            return None;
+        }
+        let lo = if self.banking_mode == 1 {
+            self.rambank_lo
+        } else {
+            0
+        };
+        let rambank = ((self.rambank_hi << 2) | lo) % self.rambanks;
+        Some((rambank * 0x2000) | ((a as usize) & 0x1FFF))
+    }
+}
+
+#[typetag::serde]
+impl Mbc for MMM01 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = (self.rombank(a) * 0x4000) | ((a as usize) & 0x3FFF);
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        self.ram_address(a).map(|i| self.ram[i]).unwrap_or(0xFF)
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        let v = v as usize;
+        match a {
+            0x0000..=0x1FFF => {
+                self.ram_on = v & 0x0F == 0x0A;
+                if !self.mapped && v & 0x40 != 0 {
+                    self.mapped = true;
+                }
+            }
+            0x2000..=0x3FFF => {
+                if self.mapped {
+                    let mask = self.rombank_mask;
+                    self.rombank_lo = (self.rombank_lo & mask) | (v & 0x1F & !mask);
+                } else {
+                    self.rombank_lo = v & 0x1F;
+                    self.rombank_mid = (v >> 5) & 0x03;
+                }
+            }
+            0x4000..=0x5FFF => {
+                self.rambank_lo = v & 0x03;
+                if !self.mapped {
+                    self.rambank_hi = (v >> 2) & 0x03;
+                    self.rombank_hi = (v >> 4) & 0x03;
+                    self.mode_locked = v & 0x40 != 0;
+                }
+            }
+            0x6000..=0x7FFF => {
+                if !self.mode_locked {
+                    self.banking_mode = (v & 0x01) as u8;
+                }
+                if !self.mapped {
+                    self.rombank_mask = (v >> 1) & 0x1E;
+                }
+            }
+            _ => panic!("Could not write to {:04X} (MMM01)", a),
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if let Some(i) = self.ram_address(a) {
+            self.ram[i] = v;
+            self.ram_updated = true;
+        }
+    }
+
+    fn take_rom(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.rom)
+    }
+
+    fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        self.has_battery
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err(Error::InvalidRamSize {
+                expected: self.ram.len(),
+                actual: ramdata.len(),
+            });
+        }
+
+        self.ram = ramdata.to_vec();
+
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        std::mem::take(&mut self.ram_updated)
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rombank(0x4000)
+    }
+
+    /// Header of the compilation, which doesn't change when a game is mapped
+    fn header(&self) -> CartridgeHeader {
+        self.header.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MMM01, is_mmm01};
+    use crate::mbc::Mbc;
+
+    fn rom() -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..0x40u8)
+            .flat_map(|bank| std::iter::repeat_n(bank, 0x4000))
+            .collect();
+        let header = rom.len() - 0x8000;
+        rom[header + 0x134..header + 0x138].copy_from_slice(b"MENU");
+        rom[header + 0x138..header + 0x144].fill(0);
+        rom[header + 0x147] = 0x0D;
+        rom[header + 0x149] = 0x03;
+        rom
+    }
+
+    #[test]
+    fn menu_maps_a_game() {
+        let rom = rom();
+        assert!(is_mmm01(&rom));
+        let mut mbc = MMM01::new(rom).unwrap();
+        assert_eq!(mbc.readrom(0x0000), 0x3E);
+        assert_eq!(mbc.readrom(0x4000), 0x3F);
+        assert_eq!(mbc.romname(), "MENU");
+
+        // game at bank 0x28, with bit 3 of the low bits locked
+        mbc.writerom(0x6000, 0x08 << 1);
+        mbc.writerom(0x2000, 0x28);
+        mbc.writerom(0x0000, 0x40);
+        assert_eq!(mbc.readrom(0x0000), 0x28);
+        assert_eq!(mbc.readrom(0x4000), 0x29);
+
+        // the game only changes its own bank bits
+        mbc.writerom(0x2000, 0x03);
+        assert_eq!(mbc.readrom(0x4000), 0x2B);
+        mbc.writerom(0x2000, 0x08);
+        assert_eq!(mbc.readrom(0x4000), 0x29);
+        assert_eq!(mbc.romname(), "MENU");
+    }
+}
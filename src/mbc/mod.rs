@@ -1,16 +1,29 @@
+use std::ffi::OsString;
 use std::fs::{self, File};
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 use std::{io, path};
 
 use serde::{Deserialize, Serialize};
 
-use crate::StrResult;
+use crate::header::{CartridgeHeader, HEADER_END};
+use crate::{Error, Result};
 
+mod camera;
+mod huc1;
+mod huc3;
 mod mbc0;
 mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
+mod mmm01;
+mod rtc;
+mod tama5;
+
+pub use self::camera::{CAMERA_HEIGHT, CAMERA_WIDTH, CameraSource};
+pub use self::mbc5::RumbleCallback;
+pub use self::rtc::{RtcClock, RtcSource};
 
 #[typetag::serde(tag = "type")]
 pub trait Mbc: Send {
@@ -20,46 +33,133 @@ pub trait Mbc: Send {
     fn writeram(&mut self, a: u16, v: u8);
     fn check_and_reset_ram_updated(&mut self) -> bool;
 
+    /// ROM bank mapped at 0x4000-0x7FFF
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    /// Detach the ROM, e.g. to leave it out of a rewind snapshot
+    fn take_rom(&mut self) -> Vec<u8>;
+
+    /// Attach the ROM again, e.g. to a state restored without it
+    fn set_rom(&mut self, rom: Vec<u8>);
+
     fn is_battery_backed(&self) -> bool;
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()>;
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()>;
     fn dumpram(&self) -> Vec<u8>;
 
-    fn romname(&self) -> String {
-        const TITLE_START: u16 = 0x134;
-        const CGB_FLAG: u16 = 0x143;
+    /// Persist the battery backed RAM if it changed since the last flush.
+    ///
+    /// Returns whether anything was written. Only file backed cartridges persist anything.
+    fn flush(&mut self) -> Result<bool> {
+        Ok(false)
+    }
 
-        let title_size = match self.readrom(CGB_FLAG) & 0x80 {
-            0x80 => 11,
-            _ => 16,
-        };
+    /// Set how many backups of the previous battery save to keep when flushing
+    fn set_save_backups(&mut self, _count: usize) {}
 
-        let mut result = String::with_capacity(title_size as usize);
+    /// Whether the previous battery save was already backed up since it was loaded
+    fn save_backed_up(&self) -> bool {
+        false
+    }
 
-        for i in 0..title_size {
-            match self.readrom(TITLE_START + i) {
-                0 => break,
-                v => result.push(v as char),
-            }
-        }
+    /// Don't back up the previous battery save again, e.g. because it was already done before
+    /// a state was restored
+    fn set_save_backed_up(&mut self) {}
+
+    /// Forget about RAM changes not flushed yet, e.g. because the state is being replaced
+    fn discard_unflushed(&mut self) {}
+
+    /// Move the battery save to `path`, loading the save found there if any.
+    ///
+    /// Only file backed cartridges have a save file.
+    fn set_save_path(&mut self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// Path of the battery save, for file backed cartridges
+    fn save_path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Write the battery save to `path` from now on without loading it, e.g. to keep the path
+    /// of the state being replaced
+    fn restore_save_path(&mut self, _path: &Path) {}
+
+    /// Attach the source of the pictures taken by a Pocket Camera; ignored by other cartridges
+    fn set_camera_source(&mut self, _source: Box<dyn CameraSource>) {}
+
+    /// Detach the source of the pictures, e.g. to move it to a restored state
+    fn take_camera_source(&mut self) -> Option<Box<dyn CameraSource>> {
+        None
+    }
+
+    /// Attach the receiver of the rumble motor state; ignored by cartridges without a motor
+    fn set_rumble_callback(&mut self, _callback: Box<dyn RumbleCallback>) {}
+
+    /// Detach the receiver of the rumble motor state, e.g. to move it to a restored state
+    fn take_rumble_callback(&mut self) -> Option<Box<dyn RumbleCallback>> {
+        None
+    }
+
+    /// Advance the cartridge hardware by `ticks` cycles of the 4 MiHz clock, whatever the CPU
+    /// speed
+    fn do_cycle(&mut self, _ticks: u32) {}
+
+    /// Choose where the real time clock gets the time from; ignored by cartridges without one
+    fn set_rtc_clock(&mut self, _clock: RtcClock) {}
+
+    /// Detach the source of the time of the real time clock, e.g. to move it to a restored state
+    fn take_rtc_clock(&mut self) -> Option<RtcClock> {
+        None
+    }
+
+    /// Header of the cartridge, as found in the ROM rather than in the banks mapped now
+    fn header(&self) -> CartridgeHeader;
 
-        result
+    fn romname(&self) -> String {
+        self.header().title
     }
 }
 
-pub fn get_mbc(data: Vec<u8>, skip_checksum: bool) -> StrResult<Box<dyn Mbc + 'static>> {
-    if data.len() < 0x150 {
-        return Err("Rom size to small");
+/// Create the memory bank controller of the cartridge with the ROM `data`.
+///
+/// Unless `skip_checksum` is set, the header checksum and the ROM size declared in the header
+/// are checked. Over-dumped or trimmed ROMs are only warned about, and brought to the
+/// declared size.
+pub fn get_mbc(mut data: Vec<u8>, skip_checksum: bool) -> Result<Box<dyn Mbc + 'static>> {
+    if data.len() < HEADER_END {
+        return Err(Error::RomTooSmall { size: data.len() });
+    }
+    if mmm01::is_mmm01(&data) {
+        // the header of the cartridge is in the last 32 KiB, with the menu, so the ROM is
+        // left as it is
+        if !skip_checksum {
+            let header = &data[data.len() - 0x8000..];
+            check_checksum(header)?;
+            declared_rom_size(header, data.len());
+        }
+        return mmm01::MMM01::new(data).map(|v| Box::new(v) as Box<dyn Mbc>);
     }
     if !skip_checksum {
         check_checksum(&data)?;
+        if let Some(size) = declared_rom_size(&data, data.len()) {
+            // unmapped ROM reads as open bus
+            data.resize(size, 0xFF);
+        }
     }
     match data[0x147] {
         0x00 => mbc0::MBC0::new(data).map(|v| Box::new(v) as Box<dyn Mbc>),
         0x01..=0x03 => mbc1::MBC1::new(data).map(|v| Box::new(v) as Box<dyn Mbc>),
         0x05..=0x06 => mbc2::MBC2::new(data).map(|v| Box::new(v) as Box<dyn Mbc>),
+        0x0B..=0x0D => mmm01::MMM01::new(data).map(|v| Box::new(v) as Box<dyn Mbc>),
         0x0F..=0x13 => mbc3::MBC3::new(data).map(|v| Box::new(v) as Box<dyn Mbc>),
         0x19..=0x1E => mbc5::MBC5::new(data).map(|v| Box::new(v) as Box<dyn Mbc>),
-        _ => Err("Unsupported MBC type"),
+        0xFC => camera::PocketCamera::new(data).map(|v| Box::new(v) as Box<dyn Mbc>),
+        0xFD => tama5::Tama5::new(data).map(|v| Box::new(v) as Box<dyn Mbc>),
+        0xFE => huc3::HuC3::new(data).map(|v| Box::new(v) as Box<dyn Mbc>),
+        0xFF => huc1::HuC1::new(data).map(|v| Box::new(v) as Box<dyn Mbc>),
+        kind => Err(Error::UnsupportedMbc(kind)),
     }
 }
 
@@ -67,35 +167,65 @@ pub fn get_mbc(data: Vec<u8>, skip_checksum: bool) -> StrResult<Box<dyn Mbc + 's
 pub struct FileBackedMBC {
     rampath: path::PathBuf,
     mbc: Box<dyn Mbc>,
+    /// RAM changed since the last call to `check_and_reset_ram_updated`
+    #[serde(default)]
+    ram_updated: bool,
+    /// RAM changed since it was last written to `rampath`; a restored state may differ from it
+    #[serde(skip, default = "FileBackedMBC::restored_dirty")]
+    dirty: bool,
+    #[serde(skip)]
+    backups: usize,
+    /// The save file was backed up by an earlier flush, which only happens once per session
+    #[serde(skip)]
+    backed_up: bool,
+}
+
+/// Extension of the battery save, next to the ROM, unless another one is chosen
+const DEFAULT_SAVE_EXTENSION: &str = "gbsave";
+
+/// Read the battery save at `path`, if there is one
+fn read_save(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(source) => Err(Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }),
+    }
 }
 
 impl FileBackedMBC {
-    pub fn new(rompath: path::PathBuf, skip_checksum: bool) -> StrResult<FileBackedMBC> {
+    fn restored_dirty() -> bool {
+        true
+    }
+
+    pub fn new(rompath: path::PathBuf, skip_checksum: bool) -> Result<FileBackedMBC> {
         let mut data = vec![];
         File::open(&rompath)
             .and_then(|mut f| f.read_to_end(&mut data))
-            .map_err(|_| "Could not read ROM")?;
+            .map_err(|source| Error::Io {
+                path: rompath.clone(),
+                source,
+            })?;
         let mut mbc = get_mbc(data, skip_checksum)?;
 
-        let rampath = rompath.with_extension("gbsave");
-
-        if mbc.is_battery_backed() {
-            match fs::File::open(&rampath) {
-                Ok(mut file) => {
-                    let mut ramdata: Vec<u8> = vec![];
-                    match file.read_to_end(&mut ramdata) {
-                        Err(..) => return Err("Error while reading existing save file"),
-                        Ok(..) => {
-                            mbc.loadram(&ramdata)?;
-                        }
-                    }
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
-                Err(_) => return Err("Error loading existing save file"),
-            }
+        let rampath = rompath.with_extension(DEFAULT_SAVE_EXTENSION);
+
+        if mbc.is_battery_backed()
+            && let Some(ramdata) = read_save(&rampath)?
+        {
+            mbc.loadram(&ramdata)?;
         }
 
-        Ok(FileBackedMBC { rampath, mbc })
+        Ok(FileBackedMBC {
+            rampath,
+            mbc,
+            ram_updated: false,
+            dirty: false,
+            backups: 0,
+            backed_up: false,
+        })
     }
 }
 
@@ -115,15 +245,29 @@ impl Mbc for FileBackedMBC {
     }
 
     fn writeram(&mut self, a: u16, v: u8) {
-        self.mbc.writeram(a, v)
+        self.mbc.writeram(a, v);
+        if self.mbc.check_and_reset_ram_updated() {
+            self.ram_updated = true;
+            self.dirty = true;
+        }
+    }
+
+    fn take_rom(&mut self) -> Vec<u8> {
+        self.mbc.take_rom()
+    }
+
+    fn set_rom(&mut self, rom: Vec<u8>) {
+        self.mbc.set_rom(rom)
     }
 
     fn is_battery_backed(&self) -> bool {
         self.mbc.is_battery_backed()
     }
 
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
-        self.mbc.loadram(ramdata)
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        self.mbc.loadram(ramdata)?;
+        self.dirty = true;
+        Ok(())
     }
 
     fn dumpram(&self) -> Vec<u8> {
@@ -131,21 +275,164 @@ impl Mbc for FileBackedMBC {
     }
 
     fn check_and_reset_ram_updated(&mut self) -> bool {
-        self.mbc.check_and_reset_ram_updated()
+        std::mem::take(&mut self.ram_updated)
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.mbc.rom_bank()
+    }
+
+    fn flush(&mut self) -> Result<bool> {
+        if !self.dirty || !self.mbc.is_battery_backed() {
+            return Ok(false);
+        }
+
+        // the backups keep the saves of the previous sessions, not the last few seconds of this one
+        let backups = if self.backed_up { 0 } else { self.backups };
+        write_atomic(&self.rampath, &self.mbc.dumpram(), backups).map_err(|source| Error::Io {
+            path: self.rampath.clone(),
+            source,
+        })?;
+        self.dirty = false;
+        self.backed_up = true;
+        debug!("Battery RAM written to {}", self.rampath.display());
+
+        Ok(true)
+    }
+
+    fn set_save_backups(&mut self, count: usize) {
+        self.backups = count;
+    }
+
+    fn save_backed_up(&self) -> bool {
+        self.backed_up
+    }
+
+    fn set_save_backed_up(&mut self) {
+        self.backed_up = true;
+    }
+
+    fn discard_unflushed(&mut self) {
+        self.dirty = false;
+    }
+
+    fn set_save_path(&mut self, path: &Path) -> Result<()> {
+        if path == self.rampath {
+            return Ok(());
+        }
+        if self.mbc.is_battery_backed() {
+            match read_save(path)? {
+                Some(ramdata) => {
+                    self.mbc.loadram(&ramdata)?;
+                    self.dirty = false;
+                }
+                // carry the save over to the new file
+                None => self.dirty |= self.rampath.exists(),
+            }
+        }
+        self.rampath = path.to_path_buf();
+        self.backed_up = false;
+        Ok(())
+    }
+
+    fn save_path(&self) -> Option<&Path> {
+        Some(&self.rampath)
+    }
+
+    fn restore_save_path(&mut self, path: &Path) {
+        self.rampath = path.to_path_buf();
+    }
+
+    fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.mbc.set_camera_source(source)
+    }
+
+    fn take_camera_source(&mut self) -> Option<Box<dyn CameraSource>> {
+        self.mbc.take_camera_source()
+    }
+
+    fn set_rumble_callback(&mut self, callback: Box<dyn RumbleCallback>) {
+        self.mbc.set_rumble_callback(callback)
+    }
+
+    fn take_rumble_callback(&mut self) -> Option<Box<dyn RumbleCallback>> {
+        self.mbc.take_rumble_callback()
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        self.mbc.do_cycle(ticks);
+        // a running clock is saved along with the RAM, but isn't worth an early flush
+        if self.mbc.check_and_reset_ram_updated() {
+            self.dirty = true;
+        }
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.mbc.set_rtc_clock(clock)
+    }
+
+    fn take_rtc_clock(&mut self) -> Option<RtcClock> {
+        self.mbc.take_rtc_clock()
+    }
+
+    fn header(&self) -> CartridgeHeader {
+        self.mbc.header()
+    }
+
+    fn romname(&self) -> String {
+        self.mbc.romname()
     }
 }
 
 impl Drop for FileBackedMBC {
     fn drop(&mut self) {
-        if self.mbc.is_battery_backed() {
-            // TODO: error handling
-            let mut file = match fs::File::create(&self.rampath) {
-                Ok(f) => f,
-                Err(..) => return,
-            };
-            let _ = file.write_all(&self.mbc.dumpram());
+        if let Err(e) = self.flush() {
+            error!("Failed to write battery RAM: {e}");
+        }
+    }
+}
+
+/// Append `suffix` to the file name of `path`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Replace the file at `path` with `data` so that a crash never leaves a partially written file.
+///
+/// The data is written and synced to a temporary file, which is then renamed over `path`.
+/// If `backups` is not zero, the previous contents of `path` are kept in `path.bak1`,
+/// shifting older backups up to `path.bak<backups>`.
+pub(crate) fn write_atomic(path: &Path, data: &[u8], backups: usize) -> io::Result<()> {
+    let tmp_path = with_suffix(path, ".tmp");
+    {
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(data)?;
+        tmp.sync_all()?;
+    }
+
+    if backups > 0 && path.exists() {
+        for i in (1..backups).rev() {
+            match fs::rename(
+                with_suffix(path, &format!(".bak{i}")),
+                with_suffix(path, &format!(".bak{}", i + 1)),
+            ) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
         }
+        // copy rather than rename, so that `path` is never missing
+        fs::copy(path, with_suffix(path, ".bak1"))?;
+    }
+
+    fs::rename(&tmp_path, path)?;
+    // make the rename itself durable
+    if let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
     }
+
+    Ok(())
 }
 
 fn ram_banks(v: u8) -> usize {
@@ -169,14 +456,26 @@ fn rom_banks(v: u8) -> usize {
     if v <= 8 { 2 << v } else { 0 }
 }
 
-fn check_checksum(data: &[u8]) -> StrResult<()> {
+/// Size declared by `header`, when the ROM of `rom_size` bytes doesn't match it
+fn declared_rom_size(header: &[u8], rom_size: usize) -> Option<usize> {
+    let expected = CartridgeHeader::read(header)
+        .rom_size
+        .filter(|&size| size != rom_size)?;
+    warn!("ROM is {rom_size} bytes, but its header declares {expected} bytes");
+    Some(expected)
+}
+
+fn check_checksum(data: &[u8]) -> Result<()> {
     let mut value: u8 = 0;
     for item in data.iter().take(0x14D).skip(0x134) {
         value = value.wrapping_sub(*item).wrapping_sub(1);
     }
     match data[0x14D] == value {
         true => Ok(()),
-        false => Err("Cartridge checksum is invalid"),
+        false => Err(Error::InvalidChecksum {
+            expected: data[0x14D],
+            actual: value,
+        }),
     }
 }
 
@@ -189,6 +488,152 @@ mod test {
         super::check_checksum(&data).unwrap();
     }
 
+    #[test]
+    fn checksum_invalid() {
+        let mut data = vec![0; 0x150];
+        data[0x14D] = 0x42;
+        assert!(matches!(
+            super::check_checksum(&data),
+            Err(crate::Error::InvalidChecksum {
+                expected: 0x42,
+                actual: 0xE7
+            })
+        ));
+    }
+
+    #[test]
+    fn rom_is_resized_as_declared() {
+        let mut data = vec![0; 0x8000];
+        data[0x147] = 0x01; // MBC1
+        data[0x148] = 0x01; // 64 KiB
+        data[0x14D] = 0xE5;
+        let mut mbc = super::get_mbc(data.clone(), false).unwrap();
+        mbc.writerom(0x2000, 0x03);
+        assert_eq!(mbc.readrom(0x4000), 0xFF);
+        assert_eq!(mbc.take_rom().len(), 0x10000);
+        assert_eq!(super::get_mbc(data, true).unwrap().take_rom().len(), 0x8000);
+
+        // over-dumped
+        let mut data = vec![0; 0x10000];
+        data[0x14D] = 0xE7;
+        assert_eq!(
+            super::get_mbc(data, false).unwrap().take_rom().len(),
+            0x8000
+        );
+    }
+
+    #[test]
+    fn unsupported_mbc() {
+        let mut data = vec![0; 0x150];
+        data[0x147] = 0x20;
+        assert!(matches!(
+            super::get_mbc(data, true),
+            Err(crate::Error::UnsupportedMbc(0x20))
+        ));
+    }
+
+    #[test]
+    fn write_atomic_keeps_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game.gbsave");
+
+        for i in 0..4u8 {
+            super::write_atomic(&path, &[i], 2).unwrap();
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), vec![3]);
+        assert_eq!(
+            std::fs::read(dir.path().join("game.gbsave.bak1")).unwrap(),
+            vec![2]
+        );
+        assert_eq!(
+            std::fs::read(dir.path().join("game.gbsave.bak2")).unwrap(),
+            vec![1]
+        );
+        assert!(!dir.path().join("game.gbsave.bak3").exists());
+        assert!(!dir.path().join("game.gbsave.tmp").exists());
+    }
+
+    #[test]
+    fn flush_only_when_dirty() {
+        use super::Mbc;
+
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x149] = 0x02; // 8 KiB
+        std::fs::write(&rom_path, rom).unwrap();
+
+        let mut mbc = super::FileBackedMBC::new(rom_path, true).unwrap();
+        assert!(!mbc.flush().unwrap());
+
+        mbc.writerom(0x0000, 0x0A); // enable RAM
+        mbc.writeram(0xA000, 0x42);
+        assert!(mbc.check_and_reset_ram_updated());
+        assert!(mbc.flush().unwrap());
+        assert!(!mbc.flush().unwrap());
+
+        let ram = std::fs::read(dir.path().join("game.gbsave")).unwrap();
+        assert_eq!(ram.len(), 0x2000);
+        assert_eq!(ram[0], 0x42);
+    }
+
+    #[test]
+    fn save_moves_to_new_path() {
+        use super::Mbc;
+
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x149] = 0x02; // 8 KiB
+        std::fs::write(&rom_path, rom).unwrap();
+        std::fs::write(dir.path().join("game.gbsave"), [0x42; 0x2000]).unwrap();
+
+        // the previous save is carried over
+        let mut mbc = super::FileBackedMBC::new(rom_path.clone(), true).unwrap();
+        mbc.set_save_path(&dir.path().join("game.sav")).unwrap();
+        assert!(mbc.flush().unwrap());
+        assert_eq!(std::fs::read(dir.path().join("game.sav")).unwrap()[0], 0x42);
+
+        // an existing save at the new path takes precedence
+        std::fs::write(dir.path().join("game.sav"), [0x24; 0x2000]).unwrap();
+        let mut mbc = super::FileBackedMBC::new(rom_path, true).unwrap();
+        mbc.set_save_path(&dir.path().join("game.sav")).unwrap();
+        assert!(!mbc.flush().unwrap());
+        mbc.writerom(0x0000, 0x0A);
+        assert_eq!(mbc.readram(0xA000), 0x24);
+    }
+
+    #[test]
+    fn backups_rotate_once_per_session() {
+        use super::Mbc;
+
+        let dir = tempfile::tempdir().unwrap();
+        let rom_path = dir.path().join("game.gb");
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x149] = 0x02; // 8 KiB
+        std::fs::write(&rom_path, rom).unwrap();
+        std::fs::write(dir.path().join("game.gbsave"), [0x42; 0x2000]).unwrap();
+
+        let mut mbc = super::FileBackedMBC::new(rom_path, true).unwrap();
+        mbc.set_save_backups(2);
+        mbc.writerom(0x0000, 0x0A);
+        for v in 1..4 {
+            mbc.writeram(0xA000, v);
+            assert!(mbc.flush().unwrap());
+        }
+        assert!(mbc.save_backed_up());
+
+        assert_eq!(std::fs::read(dir.path().join("game.gbsave")).unwrap()[0], 3);
+        assert_eq!(
+            std::fs::read(dir.path().join("game.gbsave.bak1")).unwrap()[0],
+            0x42
+        );
+        assert!(!dir.path().join("game.gbsave.bak2").exists());
+    }
+
     #[test]
     fn checksum_ones() {
         let mut data = vec![1; 0x150];
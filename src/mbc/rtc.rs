@@ -0,0 +1,314 @@
+use std::time;
+
+use serde::{Deserialize, Serialize};
+
+/// Cycles of the 4 MiHz clock in one second
+const TICKS_PER_SECOND: u32 = 4_194_304;
+
+/// Size of the clock footer appended to the RAM in save files, with a 64 bit timestamp
+pub const FOOTER_SIZE: usize = 48;
+/// Size of the older variant of the footer, with a 32 bit timestamp
+pub const SHORT_FOOTER_SIZE: usize = 44;
+
+const SECONDS: usize = 0;
+const MINUTES: usize = 1;
+const HOURS: usize = 2;
+const DAYS_LO: usize = 3;
+const DAYS_HI: usize = 4;
+
+const DAY_BIT8: u8 = 0x01;
+const HALT: u8 = 0x40;
+const DAY_CARRY: u8 = 0x80;
+
+/// Source of the current time for [`RtcClock::Source`]
+pub trait RtcSource: Send {
+    /// Seconds since the unix epoch
+    fn now(&self) -> u64;
+}
+
+/// Where the real time clock of a cartridge gets the time from
+#[derive(Default)]
+pub enum RtcClock {
+    /// The clock of the host, so the cartridge clock keeps running while the emulator is closed
+    #[default]
+    Wall,
+    /// The emulated time: the clock stops when the emulation is paused and runs faster when
+    /// it is fast forwarded. Save states restore it exactly.
+    Emulated,
+    /// A fixed time, given in seconds since the unix epoch: the clock only changes when the
+    /// game sets it
+    Fixed(u64),
+    /// An injected source of time
+    Source(Box<dyn RtcSource>),
+}
+
+impl RtcClock {
+    /// Current time in seconds since the unix epoch, or `None` for the emulated time
+    fn now(&self) -> Option<u64> {
+        match self {
+            RtcClock::Wall => Some(system_now()),
+            RtcClock::Emulated => None,
+            RtcClock::Fixed(t) => Some(*t),
+            RtcClock::Source(source) => Some(source.now()),
+        }
+    }
+}
+
+fn system_now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|t| t.as_secs())
+        .unwrap_or_default()
+}
+
+/// Real time clock of the MBC3: seconds, minutes, hours and a 9 bit day counter with its
+/// carry, along with the copy latched for the game to read.
+#[derive(Serialize, Deserialize)]
+pub struct Rtc {
+    registers: [u8; 5],
+    latched: [u8; 5],
+    /// Time at which `registers` were last brought up to date, in seconds since the unix epoch
+    updated_at: u64,
+    /// Cycles counted towards the next second of the emulated time
+    ticks: u32,
+    /// `None` when restored from a save state until the clock is attached again, which then
+    /// behaves as the wall clock
+    #[serde(skip)]
+    clock: Option<RtcClock>,
+    /// The registers were loaded from a save file and have not caught up with the time since,
+    /// which is left to the clock attached next
+    #[serde(skip)]
+    loaded: bool,
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            registers: [0; 5],
+            latched: [0; 5],
+            updated_at: system_now(),
+            ticks: 0,
+            clock: Some(RtcClock::Wall),
+            loaded: false,
+        }
+    }
+
+    fn now(&self) -> Option<u64> {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => Some(system_now()),
+        }
+    }
+
+    /// Registers advanced to the current time, without updating the clock
+    fn current(&self) -> [u8; 5] {
+        let mut registers = self.registers;
+        if let Some(now) = self.now() {
+            advance(&mut registers, now.saturating_sub(self.updated_at));
+        }
+        registers
+    }
+
+    /// Bring the registers up to date with the clock
+    fn sync(&mut self) {
+        if let Some(now) = self.now() {
+            advance(&mut self.registers, now.saturating_sub(self.updated_at));
+            self.updated_at = now;
+        }
+        self.loaded = false;
+    }
+
+    /// Change where the time comes from, keeping the current time of the cartridge
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        // a clock restored from a save state or a save file catches up with the time elapsed
+        // since the save, unless it is the emulated time
+        let restored = self.clock.is_none() || self.loaded;
+        if !restored {
+            self.sync();
+        }
+        self.clock = Some(clock);
+        self.loaded = false;
+        if !restored && let Some(now) = self.now() {
+            self.updated_at = now;
+        }
+    }
+
+    pub fn take_clock(&mut self) -> Option<RtcClock> {
+        self.sync();
+        self.clock.take()
+    }
+
+    /// Advance the emulated time by `ticks` cycles of the 4 MiHz clock.
+    ///
+    /// Returns whether the registers changed.
+    pub fn do_cycle(&mut self, ticks: u32) -> bool {
+        if !matches!(self.clock, Some(RtcClock::Emulated)) {
+            return false;
+        }
+        self.ticks += ticks;
+        if self.ticks < TICKS_PER_SECOND {
+            return false;
+        }
+        let seconds = self.ticks / TICKS_PER_SECOND;
+        self.ticks %= TICKS_PER_SECOND;
+        advance(&mut self.registers, seconds as u64)
+    }
+
+    pub fn latch(&mut self) {
+        self.sync();
+        self.latched = self.registers;
+    }
+
+    pub fn read(&self, register: usize) -> u8 {
+        self.latched.get(register).copied().unwrap_or(0xFF)
+    }
+
+    pub fn write(&mut self, register: usize, v: u8) {
+        self.sync();
+        let mask = match register {
+            SECONDS | MINUTES => 0x3F,
+            HOURS => 0x1F,
+            DAYS_LO => 0xFF,
+            DAYS_HI => DAY_CARRY | HALT | DAY_BIT8,
+            _ => return,
+        };
+        self.registers[register] = v & mask;
+        if register == SECONDS {
+            // writing the seconds resets the divider counting towards the next second
+            self.ticks = 0;
+        }
+    }
+
+    /// Footer appended to the RAM in save files: the registers then the latched registers,
+    /// each as a 32 bit little endian value, then the time as a 64 bit unix timestamp
+    pub fn footer(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(FOOTER_SIZE);
+        for v in self.current().iter().chain(self.latched.iter()) {
+            data.extend_from_slice(&(*v as u32).to_le_bytes());
+        }
+        let now = self.now().unwrap_or_else(system_now);
+        data.extend_from_slice(&now.to_le_bytes());
+        data
+    }
+
+    /// Restore the clock from a save file footer, either 48 or 44 bytes long.
+    ///
+    /// The time elapsed since the save is added when the registers are next used, or by the
+    /// clock attached next with [`Rtc::set_clock`], so that the emulated time doesn't.
+    pub fn load_footer(&mut self, data: &[u8]) {
+        let word = |i: usize| data[i * 4];
+        for i in 0..5 {
+            self.registers[i] = word(i);
+            self.latched[i] = word(5 + i);
+        }
+        let mut timestamp = [0; 8];
+        let len = (data.len() - 40).min(8);
+        timestamp[..len].copy_from_slice(&data[40..40 + len]);
+        self.updated_at = u64::from_le_bytes(timestamp);
+        self.ticks = 0;
+        self.loaded = true;
+    }
+
+    /// Restore the clock from the time at which it was zero, as saved by older versions
+    pub fn load_zero(&mut self, zero: u64) {
+        let now = self.now().unwrap_or_else(system_now);
+        self.registers = [0; 5];
+        advance(&mut self.registers, now.saturating_sub(zero));
+        self.latched = self.registers;
+        self.updated_at = now;
+        self.ticks = 0;
+    }
+}
+
+/// Advance the clock `registers` by `seconds`, unless halted.
+///
+/// Returns whether the registers changed.
+fn advance(registers: &mut [u8; 5], seconds: u64) -> bool {
+    if seconds == 0 || registers[DAYS_HI] & HALT != 0 {
+        return false;
+    }
+
+    let seconds = registers[SECONDS] as u64 + seconds;
+    registers[SECONDS] = (seconds % 60) as u8;
+    let minutes = registers[MINUTES] as u64 + seconds / 60;
+    registers[MINUTES] = (minutes % 60) as u8;
+    let hours = registers[HOURS] as u64 + minutes / 60;
+    registers[HOURS] = (hours % 24) as u8;
+
+    let days_hi = registers[DAYS_HI];
+    let days = ((((days_hi & DAY_BIT8) as u64) << 8) | registers[DAYS_LO] as u64) + hours / 24;
+    let carry = if days >= 0x200 { DAY_CARRY } else { 0 };
+    registers[DAYS_LO] = days as u8;
+    registers[DAYS_HI] = (days_hi & !DAY_BIT8) | ((days >> 8) as u8 & DAY_BIT8) | carry;
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rtc, RtcClock, TICKS_PER_SECOND, advance};
+
+    #[test]
+    fn advance_carries_into_days() {
+        let mut registers = [59, 59, 23, 0xFF, 0x01];
+        advance(&mut registers, 1);
+        assert_eq!(registers, [0, 0, 0, 0x00, 0x80]);
+
+        registers[4] |= 0x40;
+        advance(&mut registers, 3600);
+        assert_eq!(registers, [0, 0, 0, 0x00, 0xC0]);
+    }
+
+    #[test]
+    fn emulated_clock_follows_cycles() {
+        let mut rtc = Rtc::new();
+        rtc.set_clock(RtcClock::Emulated);
+        for _ in 0..90 {
+            rtc.do_cycle(TICKS_PER_SECOND);
+        }
+        rtc.latch();
+        assert_eq!([rtc.read(0), rtc.read(1)], [30, 1]);
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let mut rtc = Rtc::new();
+        rtc.set_clock(RtcClock::Fixed(1_000_000));
+        rtc.write(2, 5);
+        rtc.latch();
+        let footer = rtc.footer();
+        assert_eq!(footer.len(), super::FOOTER_SIZE);
+        assert_eq!(&footer[40..], &1_000_000u64.to_le_bytes());
+
+        // one hour later
+        let mut other = Rtc::new();
+        other.set_clock(RtcClock::Fixed(1_003_600));
+        other.load_footer(&footer);
+        assert_eq!(other.read(2), 5);
+        other.latch();
+        assert_eq!(other.read(2), 6);
+    }
+
+    #[test]
+    fn emulated_clock_ignores_time_since_footer() {
+        let mut rtc = Rtc::new();
+        rtc.set_clock(RtcClock::Fixed(1_000_000));
+        rtc.write(2, 5);
+        rtc.latch();
+        let footer = rtc.footer();
+
+        // loaded as the device is created, with the wall clock, a day after the save
+        let mut other = Rtc::new();
+        other.set_clock(RtcClock::Fixed(1_086_400));
+        other.load_footer(&footer);
+        other.set_clock(RtcClock::Emulated);
+        other.latch();
+        assert_eq!([other.read(2), other.read(3)], [5, 0]);
+
+        let mut other = Rtc::new();
+        other.set_clock(RtcClock::Fixed(1_086_400));
+        other.load_footer(&footer);
+        other.set_clock(RtcClock::Wall);
+        other.latch();
+        assert!(other.read(3) > 0);
+    }
+}
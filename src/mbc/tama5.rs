@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+
+use crate::header::CartridgeHeader;
+use crate::mbc::Mbc;
+use crate::{Error, Result};
+
+/// Size of the battery backed memory of the TAMA5
+const RAM_SIZE: usize = 0x20;
+
+const REG_BANK_LO: usize = 0x0;
+const REG_BANK_HI: usize = 0x1;
+const REG_WRITE_LO: usize = 0x4;
+const REG_WRITE_HI: usize = 0x5;
+const REG_ADDR_HI: usize = 0x6;
+const REG_ADDR_LO: usize = 0x7;
+const REG_ACTIVE: usize = 0xA;
+const REG_READ_LO: usize = 0xC;
+const REG_READ_HI: usize = 0xD;
+
+/// Bandai TAMA5.
+///
+/// All the accesses go through two ports: writing 0xA001 selects a register, and 0xA000
+/// reads or writes a nibble of the selected register. The ROM bank and the 32 bytes of
+/// battery backed memory are accessed through these registers. The clock of the TAMA6 chip
+/// is not emulated.
+#[derive(Serialize, Deserialize)]
+pub struct Tama5 {
+    rom: Vec<u8>,
+    /// Header of the cartridge, read once from the ROM whatever gets mapped later
+    header: CartridgeHeader,
+    ram: Vec<u8>,
+    ram_updated: bool,
+    rombanks: usize,
+    register: usize,
+    registers: [u8; 0x10],
+}
+
+impl Tama5 {
+    pub fn new(data: Vec<u8>) -> Result<Tama5> {
+        let rombanks = (data.len() / 0x4000).max(1);
+
+        let res = Tama5 {
+            header: CartridgeHeader::read(&data),
+            rom: data,
+            ram: vec![0; RAM_SIZE],
+            ram_updated: false,
+            rombanks,
+            register: 0,
+            registers: [0; 0x10],
+        };
+
+        Ok(res)
+    }
+
+    fn rombank(&self) -> usize {
+        let bank = (self.registers[REG_BANK_LO] | (self.registers[REG_BANK_HI] << 4)) as usize;
+        bank % self.rombanks
+    }
+
+    /// Memory address selected by the address registers
+    fn address(&self) -> usize {
+        (((self.registers[REG_ADDR_HI] & 0x01) << 4) | self.registers[REG_ADDR_LO]) as usize
+    }
+
+    /// Operation selected by the upper bits of the high address register
+    fn command(&self) -> u8 {
+        self.registers[REG_ADDR_HI] >> 1
+    }
+
+    fn write_register(&mut self, v: u8) {
+        let Some(reg) = self.registers.get_mut(self.register) else {
+            return;
+        };
+        *reg = v & 0x0F;
+
+        // writing the low address starts the operation
+        if self.register == REG_ADDR_LO && self.command() == 0 {
+            let value = (self.registers[REG_WRITE_HI] << 4) | self.registers[REG_WRITE_LO];
+            let address = self.address();
+            self.ram[address] = value;
+            self.ram_updated = true;
+        }
+    }
+
+    fn read_register(&self) -> u8 {
+        match self.register {
+            // the chip is always ready
+            REG_ACTIVE => 0xF1,
+            REG_READ_LO | REG_READ_HI => {
+                let value = match self.command() {
+                    1 => self.ram[self.address()],
+                    _ => 0,
+                };
+                let nibble = if self.register == REG_READ_HI {
+                    value >> 4
+                } else {
+                    value & 0x0F
+                };
+                0xF0 | nibble
+            }
+            _ => 0xFF,
+        }
+    }
+}
+
+#[typetag::serde]
+impl Mbc for Tama5 {
+    fn readrom(&self, a: u16) -> u8 {
+        let bank = if a < 0x4000 { 0 } else { self.rombank() };
+        let idx = (bank * 0x4000) | ((a as usize) & 0x3FFF);
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        match a & 0x01 {
+            0 => self.read_register(),
+            _ => 0xFF,
+        }
+    }
+
+    fn writerom(&mut self, _a: u16, _v: u8) {
+        // all the registers are accessed through the RAM area
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        match a & 0x01 {
+            0 => self.write_register(v),
+            _ => self.register = (v & 0x0F) as usize,
+        }
+    }
+
+    fn header(&self) -> CartridgeHeader {
+        self.header.clone()
+    }
+
+    fn take_rom(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.rom)
+    }
+
+    fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err(Error::InvalidRamSize {
+                expected: self.ram.len(),
+                actual: ramdata.len(),
+            });
+        }
+
+        self.ram = ramdata.to_vec();
+
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        std::mem::take(&mut self.ram_updated)
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rombank()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Tama5;
+    use crate::mbc::Mbc;
+
+    fn write(mbc: &mut Tama5, register: u8, v: u8) {
+        mbc.writeram(0xA001, register);
+        mbc.writeram(0xA000, v);
+    }
+
+    fn read(mbc: &mut Tama5, register: u8) -> u8 {
+        mbc.writeram(0xA001, register);
+        mbc.readram(0xA000)
+    }
+
+    #[test]
+    fn banks_and_memory_through_registers() {
+        let rom: Vec<u8> = (0..0x20u8)
+            .flat_map(|bank| std::iter::repeat_n(bank, 0x4000))
+            .collect();
+        let mut mbc = Tama5::new(rom).unwrap();
+        assert_eq!(read(&mut mbc, 0x0A), 0xF1);
+
+        write(&mut mbc, 0x00, 0x03);
+        write(&mut mbc, 0x01, 0x01);
+        assert_eq!(mbc.readrom(0x4000), 0x13);
+
+        // write 0x5A at 0x12
+        write(&mut mbc, 0x04, 0x0A);
+        write(&mut mbc, 0x05, 0x05);
+        write(&mut mbc, 0x06, 0x01);
+        write(&mut mbc, 0x07, 0x02);
+        assert!(mbc.check_and_reset_ram_updated());
+        assert_eq!(mbc.dumpram()[0x12], 0x5A);
+
+        // read it back
+        write(&mut mbc, 0x06, 0x03);
+        write(&mut mbc, 0x07, 0x02);
+        assert_eq!(read(&mut mbc, 0x0C), 0xFA);
+        assert_eq!(read(&mut mbc, 0x0D), 0xF5);
+    }
+}
@@ -1,5 +1,5 @@
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
@@ -8,9 +8,9 @@ use std::sync::mpsc::{Receiver, TryRecvError};
 use std::time::{Duration, Instant};
 
 use font8x8::{BASIC_FONTS, UnicodeFonts};
-use rboy::KeypadKey;
 use rboy::framebuffer::Framebuffer;
-use rboy::input::KeyEvent;
+use rboy::input::{KeyEvent, PowerAction};
+use rboy::{CartridgeHeader, CgbSupport, KeypadKey};
 
 use crate::AppState;
 use crate::app_config::AppConfig;
@@ -37,7 +37,9 @@ pub struct AppMenu {
     config: Rc<AppConfig>,
     framebuffer: Rc<Framebuffer>,
     event_receiver: Receiver<rboy::input::Event>,
+    power_receiver: Receiver<PowerAction>,
     exit: Arc<AtomicBool>,
+    power_off: Arc<AtomicBool>,
     games: Vec<GameEntry>,
 }
 
@@ -59,7 +61,9 @@ impl AppMenu {
         config: Rc<AppConfig>,
         framebuffer: Rc<Framebuffer>,
         exit: Arc<AtomicBool>,
+        power_off: Arc<AtomicBool>,
         event_receiver: Receiver<rboy::input::Event>,
+        power_receiver: Receiver<PowerAction>,
     ) -> anyhow::Result<Self> {
         // scan directory
         let mut games = vec![];
@@ -88,6 +92,25 @@ impl AppMenu {
                         continue;
                     }
                 };
+                // the header tells the platform better than the extension
+                let platform = match read_header(&path) {
+                    Ok(header) => {
+                        debug!(
+                            "{}: {} ({})",
+                            path.display(),
+                            header.title,
+                            header.cartridge_type_name()
+                        );
+                        match header.cgb {
+                            CgbSupport::None => Platform::GameBoy,
+                            CgbSupport::Compatible | CgbSupport::Only => Platform::GameBoyColor,
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Failed to read the header of {}: {e}", path.display());
+                        platform
+                    }
+                };
                 // remove extension from name
                 let name = name
                     .trim_end_matches(".gb")
@@ -108,7 +131,9 @@ impl AppMenu {
         Ok(Self {
             config,
             event_receiver,
+            power_receiver,
             exit,
+            power_off,
             framebuffer,
             games,
         })
@@ -125,6 +150,17 @@ impl AppMenu {
                 return Ok(AppState::Exit);
             }
 
+            match self.power_receiver.try_recv() {
+                Ok(PowerAction::Shutdown) => {
+                    self.power_off.store(true, Ordering::Relaxed);
+                    return Ok(AppState::Exit);
+                }
+                Ok(PowerAction::Exit) => return Ok(AppState::Exit),
+                Ok(PowerAction::Command(command)) => crate::shutdown::spawn_command(&command),
+                // already in the menu and not emulating
+                Ok(PowerAction::Menu | PowerAction::Suspend) | Err(_) => {}
+            }
+
             if redraw {
                 self.redraw(selected);
                 redraw = false;
@@ -306,3 +342,10 @@ impl AppMenu {
         }
     }
 }
+
+/// Read the cartridge header of the ROM at `path`, without reading the whole ROM
+fn read_header(path: &Path) -> anyhow::Result<CartridgeHeader> {
+    let mut data = vec![];
+    File::open(path)?.take(0x150).read_to_end(&mut data)?;
+    Ok(CartridgeHeader::parse(&data)?)
+}
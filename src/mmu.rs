@@ -1,15 +1,21 @@
 use serde::{Deserialize, Serialize};
 
+use crate::debugger::{Access, Watchpoints};
 use crate::gbmode::{GbMode, GbSpeed};
 use crate::gpu::Gpu;
 use crate::keypad::Keypad;
+use crate::palette::DmgPalette;
 use crate::serial::{Serial, SerialCallback};
 use crate::sound::Sound;
 use crate::timer::Timer;
-use crate::{StrResult, mbc};
+use crate::{Error, Result, mbc};
 
 const WRAM_SIZE: usize = 0x8000;
 const ZRAM_SIZE: usize = 0x7F;
+/// Size of the DMG boot ROM, mapped over 0x0000-0x00FF
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+/// Size of the CGB boot ROM, mapped over 0x0000-0x00FF and 0x0200-0x08FF
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
 
 #[derive(PartialEq, Serialize, Deserialize)]
 enum DMAType {
@@ -20,8 +26,8 @@ enum DMAType {
 
 #[derive(Serialize, Deserialize)]
 pub struct Mmu {
-    #[serde(with = "serde_arrays")]
-    wram: [u8; WRAM_SIZE],
+    // Kept on the heap so that moving or deserializing a `Mmu` stays cheap on the stack
+    wram: Vec<u8>,
     #[serde(with = "serde_arrays")]
     zram: [u8; ZRAM_SIZE],
     hdma: [u8; 4],
@@ -43,6 +49,18 @@ pub struct Mmu {
     gbspeed: GbSpeed,
     speed_switch_req: bool,
     undocumented_cgb_regs: [u8; 3], // 0xFF72, 0xFF73, 0xFF75
+    /// Boot ROM, mapped until 0xFF50 is written
+    #[serde(default)]
+    boot_rom: Option<Vec<u8>>,
+    /// Colors replacing the ones picked by the CGB boot ROM once it hands over, if it selected
+    /// the compatibility mode
+    #[serde(skip)]
+    pub(crate) compat_colors: Option<DmgPalette>,
+    #[serde(skip)]
+    pub(crate) watchpoints: Watchpoints,
+    /// LY always reads 0x90, for the instruction trace
+    #[serde(skip)]
+    pub(crate) stub_ly: bool,
 }
 
 fn fill_random(slice: &mut [u8], start: u32) {
@@ -62,13 +80,13 @@ impl Mmu {
     pub fn new(
         cart: Box<dyn mbc::Mbc + 'static>,
         serial_callback: Option<Box<dyn SerialCallback>>,
-    ) -> StrResult<Mmu> {
+    ) -> Result<Mmu> {
         let serial = match serial_callback {
             Some(cb) => Serial::new_with_callback(cb),
             None => Serial::new(),
         };
         let mut res = Mmu {
-            wram: [0; WRAM_SIZE],
+            wram: vec![0; WRAM_SIZE],
             zram: [0; ZRAM_SIZE],
             hdma: [0; 4],
             wrambank: 1,
@@ -88,10 +106,14 @@ impl Mmu {
             hdma_status: DMAType::NoDMA,
             hdma_len: 0xFF,
             undocumented_cgb_regs: [0; 3],
+            boot_rom: None,
+            compat_colors: None,
+            watchpoints: Watchpoints::default(),
+            stub_ly: false,
         };
         fill_random(&mut res.wram, 42);
         if res.rb(0x0143) == 0xC0 {
-            return Err("This game does not work in Classic mode");
+            return Err(Error::CgbOnly);
         }
         res.set_initial();
         Ok(res)
@@ -100,13 +122,13 @@ impl Mmu {
     pub fn new_cgb(
         cart: Box<dyn mbc::Mbc + 'static>,
         serial_callback: Option<Box<dyn SerialCallback>>,
-    ) -> StrResult<Mmu> {
+    ) -> Result<Mmu> {
         let serial = match serial_callback {
             Some(cb) => Serial::new_with_callback(cb),
             None => Serial::new(),
         };
         let mut res = Mmu {
-            wram: [0; WRAM_SIZE],
+            wram: vec![0; WRAM_SIZE],
             zram: [0; ZRAM_SIZE],
             wrambank: 1,
             hdma: [0; 4],
@@ -126,6 +148,10 @@ impl Mmu {
             hdma_status: DMAType::NoDMA,
             hdma_len: 0xFF,
             undocumented_cgb_regs: [0; 3],
+            boot_rom: None,
+            compat_colors: None,
+            watchpoints: Watchpoints::default(),
+            stub_ly: false,
         };
         fill_random(&mut res.wram, 42);
         res.determine_mode();
@@ -172,10 +198,57 @@ impl Mmu {
             0x80 => GbMode::Color,
             _ => GbMode::ColorAsClassic,
         };
+        self.set_mode(mode);
+    }
+
+    fn set_mode(&mut self, mode: GbMode) {
         self.gbmode = mode;
         self.gpu.gbmode = mode;
     }
 
+    /// Map a boot ROM over the cartridge until it writes to 0xFF50.
+    ///
+    /// The boot ROM takes over the initialization of the hardware, so this must be called
+    /// before the first cycle. A CGB boot ROM starts in color mode and selects the mode itself
+    /// by writing to KEY0 (0xFF4C).
+    pub fn set_boot_rom(&mut self, data: Vec<u8>) -> Result<()> {
+        let expected = match self.gbmode {
+            GbMode::Classic => DMG_BOOT_ROM_SIZE,
+            GbMode::Color | GbMode::ColorAsClassic => CGB_BOOT_ROM_SIZE,
+        };
+        if data.len() != expected {
+            return Err(Error::InvalidBootRomSize {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        // undo the post-boot state, the boot ROM turns the LCD on by itself
+        self.wb(0xFF40, 0);
+        if self.gbmode == GbMode::ColorAsClassic {
+            self.set_mode(GbMode::Color);
+        }
+        self.boot_rom = Some(data);
+
+        Ok(())
+    }
+
+    /// A boot ROM is mapped and has not handed over to the game yet
+    pub fn boot_rom_running(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    fn boot_rom_mapped(&self, address: u16) -> Option<u8> {
+        let boot_rom = self.boot_rom.as_ref()?;
+        match address {
+            0x0000..=0x00FF => Some(boot_rom[address as usize]),
+            0x0200..=0x08FF if boot_rom.len() == CGB_BOOT_ROM_SIZE => {
+                Some(boot_rom[address as usize])
+            }
+            _ => None,
+        }
+    }
+
     pub fn do_cycle(&mut self, ticks: u32) -> u32 {
         let cpudivider = self.gbspeed as u32;
         let vramticks = self.perform_vramdma();
@@ -199,10 +272,24 @@ impl Mmu {
         self.intf |= self.serial.interrupt;
         self.serial.interrupt = 0;
 
+        self.mbc.do_cycle(gputicks);
+
         gputicks
     }
 
     pub fn rb(&mut self, address: u16) -> u8 {
+        let value = self.read(address);
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.check(address, value, Access::Read);
+        }
+        value
+    }
+
+    /// Read a byte without checking the watchpoints
+    pub(crate) fn read(&mut self, address: u16) -> u8 {
+        if let Some(value) = self.boot_rom_mapped(address) {
+            return value;
+        }
         match address {
             0x0000..=0x7FFF => self.mbc.readrom(address),
             0x8000..=0x9FFF => self.gpu.rb(address),
@@ -230,6 +317,7 @@ impl Mmu {
                     })
                     | (if self.speed_switch_req { 1 } else { 0 })
             }
+            0xFF44 if self.stub_ly => 0x90,
             0xFF40..=0xFF4F => self.gpu.rb(address),
             0xFF51..=0xFF55 => self.hdma_read(address),
             0xFF68..=0xFF6B => self.gpu.rb(address),
@@ -248,6 +336,9 @@ impl Mmu {
     }
 
     pub fn wb(&mut self, address: u16, value: u8) {
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.check(address, value, Access::Write);
+        }
         match address {
             0x0000..=0x7FFF => self.mbc.writerom(address, value),
             0x8000..=0x9FFF => self.gpu.wb(address, value),
@@ -262,6 +353,24 @@ impl Mmu {
             0xFF04..=0xFF07 => self.timer.wb(address, value),
             0xFF10..=0xFF3F => self.sound.as_mut().map_or((), |s| s.wb(address, value)),
             0xFF46 => self.oamdma(value),
+            // KEY0, written by the CGB boot ROM to select the compatibility mode
+            0xFF4C
+                if self.boot_rom.is_some()
+                    && self.gbmode == GbMode::Color
+                    && value & 0x0C == 0x04 =>
+            {
+                self.set_mode(GbMode::ColorAsClassic);
+                // keep the colors the boot ROM picked for the game
+                self.gpu.compat_palettes = true;
+            }
+            0xFF50 if value != 0 => {
+                if self.boot_rom.take().is_some()
+                    && self.gbmode == GbMode::ColorAsClassic
+                    && let Some(colors) = self.compat_colors.take()
+                {
+                    self.gpu.set_compat_colors(&colors);
+                }
+            }
             0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF6C | 0xFF70 | 0xFF76..=0xFF77
                 if self.gbmode != GbMode::Color => {}
             0xFF72..=0xFF73 | 0xFF75..=0xFF77 if self.gbmode == GbMode::Classic => {}
@@ -293,6 +402,11 @@ impl Mmu {
         self.wb(address + 1, (value >> 8) as u8);
     }
 
+    /// Work RAM bank mapped at 0xD000-0xDFFF
+    pub fn wram_bank(&self) -> usize {
+        self.wrambank
+    }
+
     pub fn switch_speed(&mut self) {
         if self.speed_switch_req {
             if self.gbspeed == GbSpeed::Double {
@@ -0,0 +1,109 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Colors given to the four shades of the classic Game Boy, as `0xRRGGBB` from lightest to
+/// darkest, for the background and the two object palettes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmgPalette {
+    pub bg: [u32; 4],
+    pub obj0: [u32; 4],
+    pub obj1: [u32; 4],
+}
+
+impl DmgPalette {
+    /// Palette using the same colors for the background and the objects
+    pub const fn uniform(colors: [u32; 4]) -> DmgPalette {
+        DmgPalette {
+            bg: colors,
+            obj0: colors,
+            obj1: colors,
+        }
+    }
+}
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        PalettePreset::Gray.palette()
+    }
+}
+
+/// Palettes shipped with the emulator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PalettePreset {
+    /// Plain gray scale
+    Gray,
+    /// Pea green of the original Game Boy screen
+    Dmg,
+    /// Olive gray of the Game Boy Pocket screen
+    Pocket,
+    /// Blue-green backlight of the Game Boy Light
+    Light,
+    /// Gray scale with darker mid tones, for screens washing out the middle shades
+    HighContrast,
+}
+
+impl PalettePreset {
+    /// All the presets
+    pub const ALL: [PalettePreset; 5] = [
+        Self::Gray,
+        Self::Dmg,
+        Self::Pocket,
+        Self::Light,
+        Self::HighContrast,
+    ];
+
+    /// Colors of the preset
+    pub const fn palette(self) -> DmgPalette {
+        DmgPalette::uniform(match self {
+            Self::Gray => [0xFFFFFF, 0xC0C0C0, 0x606060, 0x000000],
+            Self::Dmg => [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F],
+            Self::Pocket => [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F],
+            Self::Light => [0x00B581, 0x009A71, 0x00694A, 0x004F3B],
+            Self::HighContrast => [0xFFFFFF, 0x909090, 0x303030, 0x000000],
+        })
+    }
+}
+
+impl fmt::Display for PalettePreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Gray => "gray",
+            Self::Dmg => "dmg",
+            Self::Pocket => "pocket",
+            Self::Light => "light",
+            Self::HighContrast => "high-contrast",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for PalettePreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.to_string() == s)
+            .ok_or_else(|| format!("Invalid palette preset: {s}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DmgPalette, PalettePreset};
+
+    #[test]
+    fn default_is_gray() {
+        assert_eq!(DmgPalette::default(), PalettePreset::Gray.palette());
+    }
+
+    #[test]
+    fn parse_preset_names() {
+        for preset in PalettePreset::ALL {
+            assert_eq!(preset.to_string().parse(), Ok(preset));
+        }
+        assert_eq!("High-Contrast".parse(), Ok(PalettePreset::HighContrast));
+        assert!("sepia".parse::<PalettePreset>().is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+
+use rboy::DmgPalette;
+
+use crate::app_config::PaletteConfig;
+
+/// Palette chosen for a game, cycled by a hotkey and remembered in a file next to the ROM.
+///
+/// For `game.gb`, the name of the palette is stored in `game.palette`.
+pub struct PaletteSelector {
+    palettes: Vec<(String, DmgPalette)>,
+    current: usize,
+    path: PathBuf,
+}
+
+impl PaletteSelector {
+    /// Select the palette saved for `rom_file`, or the configured default
+    pub fn new(config: &PaletteConfig, rom_file: &Path) -> Self {
+        let palettes = config.palettes();
+        let path = rom_file.with_extension("palette");
+        let position = |name: &str| palettes.iter().position(|(n, _)| n == name.trim());
+
+        let saved = std::fs::read_to_string(&path).ok();
+        let current = match saved.as_deref().map(|name| (name, position(name))) {
+            Some((_, Some(index))) => index,
+            Some((name, None)) => {
+                warn!("Unknown palette {name:?} saved in {}", path.display());
+                position(&config.default).unwrap_or_default()
+            }
+            None => position(&config.default).unwrap_or_else(|| {
+                warn!("Unknown default palette {:?}", config.default);
+                0
+            }),
+        };
+
+        Self {
+            palettes,
+            current,
+            path,
+        }
+    }
+
+    /// Name of the selected palette
+    pub fn name(&self) -> &str {
+        &self.palettes[self.current].0
+    }
+
+    /// Colors of the selected palette
+    pub fn palette(&self) -> DmgPalette {
+        self.palettes[self.current].1
+    }
+
+    /// Select the next palette and save the choice for the game
+    pub fn next(&mut self) -> DmgPalette {
+        self.current = (self.current + 1) % self.palettes.len();
+        if let Err(e) = std::fs::write(&self.path, self.name()) {
+            error!("Failed to save palette to {}: {e}", self.path.display());
+        }
+        self.palette()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn config() -> PaletteConfig {
+        toml::from_str(
+            r##"
+default = "pocket"
+
+[[custom]]
+name = "lcd"
+bg = ["#E0F8D0", "#88C070", "#346856", "#081820"]
+"##,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_should_save_palette_per_rom() {
+        let dir = tempfile::tempdir().unwrap();
+        let rom = dir.path().join("game.gb");
+
+        let mut selector = PaletteSelector::new(&config(), &rom);
+        assert_eq!(selector.name(), "pocket");
+        assert_eq!(selector.next(), rboy::PalettePreset::Light.palette());
+
+        let selector = PaletteSelector::new(&config(), &rom);
+        assert_eq!(selector.name(), "light");
+        let other = PaletteSelector::new(&config(), &dir.path().join("other.gb"));
+        assert_eq!(other.name(), "pocket");
+    }
+
+    #[test]
+    fn test_should_cycle_through_custom_palettes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut selector = PaletteSelector::new(&config(), &dir.path().join("game.gb"));
+        selector.next();
+        selector.next();
+        selector.next();
+        assert_eq!(selector.name(), "lcd");
+        assert_eq!(selector.palette().obj1[3], 0x081820);
+        selector.next();
+        assert_eq!(selector.name(), "gray");
+    }
+}
@@ -0,0 +1,288 @@
+//! Writing of RGB24 pictures as PNG, and reading of PNG pictures without interlacing
+
+use crate::{Error, Result};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Picture in RGB24, like the screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+/// Compression level of the picture data, between 0 and 10
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// Encode a picture in RGB24 as a PNG, without filtering the rows
+pub(crate) fn encode(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut header = [0; 13];
+    header[0..4].copy_from_slice(&(width as u32).to_be_bytes());
+    header[4..8].copy_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per sample, RGB
+    header[8] = 8;
+    header[9] = 2;
+
+    let mut rows = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks_exact(width * 3).take(height) {
+        rows.push(0);
+        rows.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&rows, COMPRESSION_LEVEL);
+    write_chunk(&mut png, b"IDAT", &compressed);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// CRC-32 of the chunks, as in zip and gzip
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Decode a PNG picture; the checksums of the chunks are not verified
+pub(crate) fn decode(png: &[u8]) -> Result<Image> {
+    let invalid = |reason| Error::InvalidPng(reason);
+    let mut rest = png
+        .strip_prefix(&SIGNATURE)
+        .ok_or(invalid("missing signature"))?;
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut compressed = Vec::new();
+    loop {
+        if rest.len() < 12 {
+            return Err(invalid("truncated chunk"));
+        }
+        let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let kind = &rest[4..8];
+        let data = rest.get(8..8 + length).ok_or(invalid("truncated chunk"))?;
+        rest = rest.get(12 + length..).ok_or(invalid("truncated chunk"))?;
+        match kind {
+            b"IHDR" if data.len() == 13 => header = Some(data),
+            b"PLTE" => palette = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+
+    let header = header.ok_or(invalid("missing header"))?;
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+    let (depth, color_type) = (header[8] as usize, header[9]);
+    if header[12] != 0 {
+        return Err(invalid("interlaced pictures are not supported"));
+    }
+    let channels = match (color_type, depth) {
+        (0, 1 | 2 | 4 | 8 | 16) => 1,
+        (3, 1 | 2 | 4 | 8) => 1,
+        (2, 8 | 16) => 3,
+        (4, 8 | 16) => 2,
+        (6, 8 | 16) => 4,
+        _ => return Err(invalid("unsupported color type or bit depth")),
+    };
+
+    let mut raw = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed)
+        .map_err(|_| invalid("corrupt image data"))?;
+    let stride = (width * channels * depth).div_ceil(8);
+    if raw.len() < height * (stride + 1) {
+        return Err(invalid("truncated image data"));
+    }
+    let pixel_size = (channels * depth / 8).max(1);
+    unfilter(&mut raw, stride, height, pixel_size)?;
+
+    let mut data = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        let row = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        // value of a sample, keeping the high byte of 16 bit samples
+        let sample = |index: usize| -> u8 {
+            match depth {
+                8 => row[index],
+                16 => row[index * 2],
+                _ => {
+                    let bit = index * depth;
+                    let mask = (1u8 << depth) - 1;
+                    (row[bit / 8] >> (8 - depth - bit % 8)) & mask
+                }
+            }
+        };
+        for x in 0..width {
+            let pixel = match color_type {
+                0 => {
+                    let gray = match depth {
+                        8 | 16 => sample(x),
+                        _ => sample(x) * (255 / ((1u8 << depth) - 1)),
+                    };
+                    [gray; 3]
+                }
+                3 => {
+                    let index = sample(x) as usize * 3;
+                    palette
+                        .get(index..index + 3)
+                        .ok_or(invalid("color missing from the palette"))?
+                        .try_into()
+                        .unwrap()
+                }
+                4 => [sample(x * 2); 3],
+                _ => [
+                    sample(x * channels),
+                    sample(x * channels + 1),
+                    sample(x * channels + 2),
+                ],
+            };
+            data.extend_from_slice(&pixel);
+        }
+    }
+
+    Ok(Image {
+        width,
+        height,
+        data,
+    })
+}
+
+/// Undo the filters of the rows, each preceded by its filter type
+fn unfilter(raw: &mut [u8], stride: usize, height: usize, pixel_size: usize) -> Result<()> {
+    for y in 0..height {
+        let start = y * (stride + 1);
+        let filter = raw[start];
+        for i in 0..stride {
+            let at = start + 1 + i;
+            let left = if i >= pixel_size {
+                raw[at - pixel_size]
+            } else {
+                0
+            };
+            let up = if y > 0 { raw[at - stride - 1] } else { 0 };
+            let up_left = if y > 0 && i >= pixel_size {
+                raw[at - stride - 1 - pixel_size]
+            } else {
+                0
+            };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(Error::InvalidPng("unknown filter")),
+            };
+            raw[at] = raw[at].wrapping_add(predicted);
+        }
+    }
+    Ok(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Write a PNG picture with the given header and filtered rows
+#[cfg(test)]
+pub(crate) fn build(header: [u8; 13], palette: Option<&[u8]>, rows: &[u8]) -> Vec<u8> {
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    if let Some(palette) = palette {
+        write_chunk(&mut png, b"PLTE", palette);
+    }
+    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(rows, COMPRESSION_LEVEL);
+    write_chunk(&mut png, b"IDAT", &compressed);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Header of a picture without interlacing
+#[cfg(test)]
+pub(crate) fn header(width: u32, height: u32, depth: u8, color_type: u8) -> [u8; 13] {
+    let mut header = [0; 13];
+    header[0..4].copy_from_slice(&width.to_be_bytes());
+    header[4..8].copy_from_slice(&height.to_be_bytes());
+    header[8] = depth;
+    header[9] = color_type;
+    header
+}
+
+#[cfg(test)]
+mod test {
+    use super::{build, crc32, decode, encode, header};
+
+    #[test]
+    fn encoded_picture_is_decoded_back() {
+        let rgb: Vec<u8> = (0..3 * 5 * 3).map(|i| (i * 7) as u8).collect();
+        let png = encode(3, 5, &rgb);
+        // IEND chunk with its checksum
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+        let image = decode(&png).unwrap();
+        assert_eq!((image.width, image.height), (3, 5));
+        assert_eq!(image.data, rgb);
+    }
+
+    #[test]
+    fn crc_matches_reference() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn filtered_rgb_rows_are_restored() {
+        // 2x2 RGB: a row with the Sub filter, then a row with the Up filter
+        let rows = [
+            1, 10, 20, 30, 5, 5, 5, //
+            2, 1, 1, 1, 0, 0, 0,
+        ];
+        let image = decode(&build(header(2, 2, 8, 2), None, &rows)).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.data, [10, 20, 30, 15, 25, 35, 11, 21, 31, 15, 25, 35]);
+    }
+
+    #[test]
+    fn palette_indices_are_unpacked() {
+        let palette = [0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255];
+        // 4 pixels of 2 bits: 3, 2, 1, 0
+        let image = decode(&build(header(4, 1, 2, 3), Some(&palette), &[0, 0b11100100])).unwrap();
+        assert_eq!(image.data, [0, 0, 255, 0, 255, 0, 255, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert!(decode(b"not a png").is_err());
+        let mut png = build(header(1, 1, 8, 0), None, &[0, 0]);
+        png.truncate(40);
+        assert!(decode(&png).is_err());
+    }
+}
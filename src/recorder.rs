@@ -0,0 +1,397 @@
+//! Recording of the screen and the sound into an uncompressed AVI file.
+//!
+//! The frames are stored as 24 bit bitmaps and the sound as 16 bit stereo PCM, so that the
+//! file can be played or converted without loss by common tools.
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::gpu::{CLOCK_RATE, FRAME_DOTS, SCREEN_H, SCREEN_W};
+use crate::{Error, Result};
+
+/// Dots of a line, the tolerance on the time of a frame before it is recorded without VBlank
+const LINE_DOTS: u32 = 456;
+/// Bytes of a frame in 24 bit
+const FRAME_BYTES: usize = SCREEN_W * SCREEN_H * 3;
+/// Bytes of a stereo sample in 16 bit
+const SAMPLE_BYTES: u32 = 4;
+/// Size of the recorded data at which recording stops, below the limits of the AVI format
+const MAX_DATA_SIZE: u64 = 1 << 30;
+
+/// The index lists a key frame
+const AVIIF_KEYFRAME: u32 = 0x10;
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIF_ISINTERLEAVED: u32 = 0x100;
+
+/// Writer of an AVI file, with a frame for each frame of the screen and the samples played
+/// in between
+pub(crate) struct Recorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    /// Sample rate of the sound, if it is recorded
+    sample_rate: Option<u32>,
+    frames: u32,
+    samples: u32,
+    /// Size of the chunks written in the `movi` list
+    data_size: u64,
+    /// Entries of the `idx1` chunk
+    index: Vec<u8>,
+    /// Frame counter of the GPU when the last frame was recorded
+    frame_counter: u32,
+    /// Dots since the last frame was recorded
+    dots: u32,
+    finished: bool,
+}
+
+impl Recorder {
+    /// Create the file, replacing it
+    pub fn create(path: &Path, sample_rate: Option<u32>, frame_counter: u32) -> Result<Recorder> {
+        let io_error = |source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let file = File::create(path).map_err(io_error)?;
+        let mut recorder = Recorder {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(file),
+            sample_rate,
+            frames: 0,
+            samples: 0,
+            data_size: 0,
+            index: Vec::new(),
+            frame_counter,
+            dots: 0,
+            finished: false,
+        };
+        let header = recorder.header();
+        recorder.writer.write_all(&header).map_err(io_error)?;
+        Ok(recorder)
+    }
+
+    /// Follow the time of the emulation, returning true when a frame is to be recorded: when
+    /// the GPU completed a frame, or once the time of a frame went by with the LCD off
+    pub fn frame_due(&mut self, ticks: u32, frame_counter: u32) -> bool {
+        self.dots += ticks;
+        if frame_counter != self.frame_counter {
+            self.frame_counter = frame_counter;
+            self.dots = 0;
+            true
+        } else if self.dots >= FRAME_DOTS + LINE_DOTS {
+            self.dots -= FRAME_DOTS;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The file reached the largest size recorded
+    pub fn is_full(&self) -> bool {
+        self.data_size >= MAX_DATA_SIZE
+    }
+
+    /// Record a frame of the screen in RGB24, then the interleaved stereo samples played
+    /// since the previous frame
+    pub fn write_frame(&mut self, rgb: &[u8], samples: &[f32]) -> Result<()> {
+        // bitmaps are stored bottom up, in BGR
+        let mut bitmap = Vec::with_capacity(FRAME_BYTES);
+        for row in rgb.chunks_exact(SCREEN_W * 3).rev() {
+            for pixel in row.chunks_exact(3) {
+                bitmap.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            }
+        }
+        self.write_chunk(b"00db", &bitmap)?;
+        self.frames += 1;
+
+        if let Some(sample_rate) = self.sample_rate {
+            // whole stereo samples only
+            let mut pcm: Vec<u8> = samples[..samples.len() & !1]
+                .iter()
+                .flat_map(|sample| ((sample.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes())
+                .collect();
+            // no samples are played while the sound is off: pad with silence once they lag
+            // behind the frames by more than 100 ms
+            let expected = (self.frames as u64 * FRAME_DOTS as u64 * sample_rate as u64
+                / CLOCK_RATE as u64) as u32;
+            let received = self.samples + samples.len() as u32 / 2;
+            if received + sample_rate / 10 < expected {
+                pcm.resize(
+                    pcm.len() + ((expected - received) * SAMPLE_BYTES) as usize,
+                    0,
+                );
+            }
+            if !pcm.is_empty() {
+                self.write_chunk(b"01wb", &pcm)?;
+                self.samples += pcm.len() as u32 / SAMPLE_BYTES;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the index and the final sizes, completing the file
+    pub fn finish(mut self) -> Result<()> {
+        self.complete()
+    }
+
+    fn complete(&mut self) -> Result<()> {
+        self.finished = true;
+        let header = self.header();
+        let index = std::mem::take(&mut self.index);
+        let result = (|| {
+            self.writer.write_all(b"idx1")?;
+            self.writer.write_all(&(index.len() as u32).to_le_bytes())?;
+            self.writer.write_all(&index)?;
+            self.writer.seek(SeekFrom::Start(0))?;
+            self.writer.write_all(&header)?;
+            self.writer.flush()
+        })();
+        result.map_err(|source| Error::Io {
+            path: self.path.clone(),
+            source,
+        })
+    }
+
+    fn write_chunk(&mut self, id: &[u8; 4], data: &[u8]) -> Result<()> {
+        // offsets are counted from the type of the `movi` list
+        let offset = 4 + self.data_size as u32;
+        self.index.extend_from_slice(id);
+        self.index.extend_from_slice(&AVIIF_KEYFRAME.to_le_bytes());
+        self.index.extend_from_slice(&offset.to_le_bytes());
+        self.index
+            .extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+        let result = (|| {
+            self.writer.write_all(id)?;
+            self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+            self.writer.write_all(data)?;
+            if data.len() % 2 == 1 {
+                self.writer.write_all(&[0])?;
+            }
+            Ok(())
+        })();
+        self.data_size += 8 + data.len().next_multiple_of(2) as u64;
+        result.map_err(|source| Error::Io {
+            path: self.path.clone(),
+            source,
+        })
+    }
+
+    /// Headers up to the start of the `movi` data, with the counts written so far
+    fn header(&self) -> Vec<u8> {
+        let streams = if self.sample_rate.is_some() { 2 } else { 1 };
+        let audio_rate = self.sample_rate.unwrap_or(0) * SAMPLE_BYTES;
+
+        let avih = words(&[
+            (1_000_000u64 * FRAME_DOTS as u64 / CLOCK_RATE as u64) as u32,
+            FRAME_BYTES as u32 * 60 + audio_rate,
+            0,
+            AVIF_HASINDEX | AVIF_ISINTERLEAVED,
+            self.frames,
+            0,
+            streams,
+            FRAME_BYTES as u32,
+            SCREEN_W as u32,
+            SCREEN_H as u32,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        let mut video = Vec::new();
+        video.extend(chunk(
+            b"strh",
+            &stream_header(b"vids", FRAME_DOTS, CLOCK_RATE, self.frames, 0),
+        ));
+        video.extend(chunk(
+            b"strf",
+            &[
+                words(&[40, SCREEN_W as u32, SCREEN_H as u32]),
+                // 1 plane, 24 bits
+                vec![1, 0, 24, 0],
+                words(&[0, FRAME_BYTES as u32, 0, 0, 0, 0]),
+            ]
+            .concat(),
+        ));
+        let mut hdrl = chunk(b"avih", &avih);
+        hdrl.extend(list(b"strl", &video));
+
+        if let Some(sample_rate) = self.sample_rate {
+            let mut audio = chunk(
+                b"strh",
+                &stream_header(
+                    b"auds",
+                    SAMPLE_BYTES,
+                    audio_rate,
+                    self.samples,
+                    SAMPLE_BYTES,
+                ),
+            );
+            audio.extend(chunk(
+                b"strf",
+                &[
+                    // PCM, 2 channels
+                    vec![1, 0, 2, 0],
+                    words(&[sample_rate, audio_rate]),
+                    // 4 bytes per sample, 16 bits
+                    vec![4, 0, 16, 0],
+                ]
+                .concat(),
+            ));
+            hdrl.extend(list(b"strl", &audio));
+        }
+
+        let hdrl = list(b"hdrl", &hdrl);
+        let movi_size = 4 + self.data_size as u32;
+        let index_size = 8 + self.index.len() as u32;
+        let riff_size = 4 + hdrl.len() as u32 + 8 + movi_size + index_size;
+        let mut header = Vec::with_capacity(hdrl.len() + 24);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&riff_size.to_le_bytes());
+        header.extend_from_slice(b"AVI ");
+        header.extend(hdrl);
+        header.extend_from_slice(b"LIST");
+        header.extend_from_slice(&movi_size.to_le_bytes());
+        header.extend_from_slice(b"movi");
+        header
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if !self.finished
+            && let Err(e) = self.complete()
+        {
+            error!("Failed to complete recording: {e}");
+        }
+    }
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(8 + data.len());
+    chunk.extend_from_slice(id);
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    chunk
+}
+
+fn list(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    chunk(b"LIST", &[kind.as_slice(), content].concat())
+}
+
+/// `strh` chunk of a stream running at `rate / scale` units per second
+fn stream_header(kind: &[u8; 4], scale: u32, rate: u32, length: u32, sample_size: u32) -> Vec<u8> {
+    let mut header = kind.to_vec();
+    header.extend(words(&[
+        0,
+        0,
+        0,
+        0,
+        scale,
+        rate,
+        0,
+        length,
+        0,
+        u32::MAX,
+        sample_size,
+    ]));
+    // frame rectangle
+    header.extend(words(&[0, 0]));
+    header
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FRAME_BYTES, Recorder};
+    use crate::gpu::FRAME_DOTS;
+    use crate::gpu::{SCREEN_H, SCREEN_W};
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    /// Offset of the first chunk or list with the given identifier
+    fn find(data: &[u8], id: &[u8; 4]) -> usize {
+        data.windows(4).position(|window| window == id).unwrap()
+    }
+
+    #[test]
+    fn file_holds_frames_sound_and_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.avi");
+        let mut recorder = Recorder::create(&path, Some(48000), 0).unwrap();
+        let mut rgb = vec![0; FRAME_BYTES];
+        // top left pixel
+        rgb[..3].copy_from_slice(&[1, 2, 3]);
+        recorder.write_frame(&rgb, &[0.5, -0.5]).unwrap();
+        recorder.write_frame(&rgb, &[]).unwrap();
+        recorder.finish().unwrap();
+
+        let avi = std::fs::read(&path).unwrap();
+        assert_eq!(&avi[..4], b"RIFF");
+        assert_eq!(u32_at(&avi, 4) as usize, avi.len() - 8);
+        assert_eq!(&avi[8..12], b"AVI ");
+
+        let avih = find(&avi, b"avih");
+        assert_eq!(u32_at(&avi, avih + 8 + 16), 2);
+        assert_eq!(u32_at(&avi, avih + 8 + 24), 2);
+        let auds = find(&avi, b"auds");
+        // length in samples
+        assert_eq!(u32_at(&avi, auds + 32), 1);
+
+        let movi = find(&avi, b"movi");
+        let video = movi + 4;
+        assert_eq!(&avi[video..video + 4], b"00db");
+        assert_eq!(u32_at(&avi, video + 4) as usize, FRAME_BYTES);
+        // rows are bottom up, in BGR
+        let top_left = video + 8 + (SCREEN_H - 1) * SCREEN_W * 3;
+        assert_eq!(avi[top_left..top_left + 3], [3, 2, 1]);
+        let audio = video + 8 + FRAME_BYTES;
+        assert_eq!(&avi[audio..audio + 4], b"01wb");
+        assert_eq!(avi[audio + 8..audio + 12], [0xFF, 0x3F, 0x01, 0xC0]);
+
+        let idx1 = avi.len() - 8 - 3 * 16;
+        assert_eq!(&avi[idx1..idx1 + 4], b"idx1");
+        let second_video = idx1 + 8 + 2 * 16;
+        assert_eq!(&avi[second_video..second_video + 4], b"00db");
+        assert_eq!(
+            &avi[movi + u32_at(&avi, second_video + 8) as usize..][..4],
+            b"00db"
+        );
+    }
+
+    #[test]
+    fn silence_is_added_while_the_sound_is_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.avi");
+        let mut recorder = Recorder::create(&path, Some(48000), 0).unwrap();
+        let rgb = vec![0; FRAME_BYTES];
+        for _ in 0..10 {
+            recorder.write_frame(&rgb, &[]).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let avi = std::fs::read(&path).unwrap();
+        let auds = find(&avi, b"auds");
+        // 10 frames of 48 kHz are 8037 samples, the sound may lag by up to 4800 of them
+        let samples = u32_at(&avi, auds + 32);
+        assert!((3237..=8037).contains(&samples), "{samples} samples");
+    }
+
+    #[test]
+    fn frames_follow_the_screen_or_the_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut recorder = Recorder::create(&dir.path().join("test.avi"), None, 5).unwrap();
+        assert!(!recorder.frame_due(100, 5));
+        assert!(recorder.frame_due(100, 6));
+        // the LCD is off
+        assert!(!recorder.frame_due(FRAME_DOTS, 6));
+        assert!(recorder.frame_due(FRAME_DOTS, 6));
+    }
+}
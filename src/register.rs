@@ -67,6 +67,22 @@ impl Registers {
         }
     }
 
+    /// Registers at power on, before the boot ROM runs
+    pub fn new_boot() -> Registers {
+        Registers {
+            a: 0,
+            f: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            pc: 0x0000,
+            sp: 0x0000,
+        }
+    }
+
     pub fn af(&self) -> u16 {
         ((self.a as u16) << 8) | ((self.f & 0xF0) as u16)
     }
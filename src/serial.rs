@@ -55,6 +55,10 @@ impl Serial {
     pub fn unset_callback(&mut self) {
         self.callback = None;
     }
+
+    pub fn take_callback(&mut self) -> Option<Box<dyn SerialCallback>> {
+        self.callback.take()
+    }
 }
 
 impl Serial {
@@ -0,0 +1,91 @@
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::app_config::ShutdownConfig;
+
+/// Interval between two checks of the shutdown command status
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Run the configured shutdown command, killing it if it doesn't complete within the timeout
+pub fn run_command(config: &ShutdownConfig) -> anyhow::Result<()> {
+    let Some((program, args)) = config.command.split_first() else {
+        warn!("No shutdown command configured");
+        return Ok(());
+    };
+
+    info!("Running shutdown command: {:?}", config.command);
+    let mut child = Command::new(program)
+        .args(args)
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to execute shutdown command: {e}"))?;
+
+    let timeout = config.timeout();
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                anyhow::bail!("Shutdown command failed with {status}");
+            }
+            return Ok(());
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!(
+                "Shutdown command did not complete within {} ms",
+                timeout.as_millis()
+            );
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Start a custom command without waiting for it to complete.
+///
+/// The command is reaped on a background thread once it exits.
+pub fn spawn_command(command: &[String]) {
+    let Some((program, args)) = command.split_first() else {
+        warn!("Empty power switch command");
+        return;
+    };
+
+    info!("Running command: {command:?}");
+    let mut child = match Command::new(program).args(args).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Failed to execute command {command:?}: {e}");
+            return;
+        }
+    };
+    let command = command.to_vec();
+    thread::spawn(move || match child.wait() {
+        Ok(status) if !status.success() => warn!("Command {command:?} failed with {status}"),
+        Ok(_) => {}
+        Err(e) => error!("Failed to wait for command {command:?}: {e}"),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn config(command: &[&str], timeout_ms: u64) -> ShutdownConfig {
+        toml::from_str(&format!("command = {command:?}\ntimeout_ms = {timeout_ms}")).unwrap()
+    }
+
+    #[test]
+    fn test_should_run_command() {
+        assert!(run_command(&config(&["true"], 1000)).is_ok());
+        assert!(run_command(&config(&["false"], 1000)).is_err());
+        assert!(run_command(&config(&[], 1000)).is_ok());
+    }
+
+    #[test]
+    fn test_should_kill_command_after_timeout() {
+        let start = Instant::now();
+        assert!(run_command(&config(&["sleep", "10"], 100)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
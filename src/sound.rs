@@ -668,6 +668,8 @@ pub struct Sound {
     need_sync: bool,
     dmg_mode: bool,
     player: Box<dyn AudioPlayer>,
+    /// Interleaved stereo samples played since they were last taken, while recording
+    recorded: Option<Vec<f32>>,
 }
 
 impl Sound {
@@ -710,9 +712,27 @@ impl Sound {
             need_sync: false,
             dmg_mode,
             player,
+            recorded: None,
         }
     }
 
+    pub fn samples_rate(&self) -> u32 {
+        self.player.samples_rate()
+    }
+
+    /// Keep the samples played, to be taken by `take_recorded`
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recorded = recording.then(Vec::new);
+    }
+
+    /// Interleaved stereo samples played since the last call, while recording
+    pub fn take_recorded(&mut self) -> Vec<f32> {
+        self.recorded
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
     pub fn rb(&mut self, a: u16) -> u8 {
         self.run();
         match a {
@@ -920,6 +940,11 @@ impl Sound {
             debug_assert!(count1 == count4);
 
             self.player.play(&buf_left[..count1], &buf_right[..count1]);
+            if let Some(recorded) = self.recorded.as_mut() {
+                for (left, right) in buf_left[..count1].iter().zip(&buf_right[..count1]) {
+                    recorded.extend_from_slice(&[*left, *right]);
+                }
+            }
 
             outputted += count1;
         }
@@ -0,0 +1,211 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::debugger::CpuState;
+use crate::{Error, Result};
+
+/// Where the instruction trace goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceOutput {
+    /// Write the lines to a file, replacing it
+    File(PathBuf),
+    /// Keep the last lines in memory, read with
+    /// [`Device::trace_lines`](crate::device::Device::trace_lines)
+    Ring,
+}
+
+/// Configuration of the instruction trace, written in the format of Gameboy Doctor:
+///
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+///
+/// Each line holds the registers before an instruction runs, followed by the memory at PC.
+/// Halted cycles and interrupt dispatches are not traced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceConfig {
+    pub output: TraceOutput,
+    /// Most instructions traced: the file stops growing after it, while the ring buffer keeps
+    /// the last ones
+    pub limit: usize,
+    /// Read LY (0xFF44) as 0x90, like the emulator used for the reference logs of Gameboy
+    /// Doctor, so that they can be compared
+    pub stub_ly: bool,
+}
+
+enum Sink {
+    File {
+        path: PathBuf,
+        writer: BufWriter<File>,
+    },
+    Ring(VecDeque<String>),
+}
+
+/// Writer of the instruction trace
+pub(crate) struct Tracer {
+    sink: Sink,
+    limit: usize,
+    /// Lines written to the file
+    written: usize,
+}
+
+impl Tracer {
+    pub fn new(config: &TraceConfig) -> Result<Tracer> {
+        let sink = match &config.output {
+            TraceOutput::File(path) => {
+                let file = File::create(path).map_err(|source| Error::Io {
+                    path: path.clone(),
+                    source,
+                })?;
+                Sink::File {
+                    path: path.clone(),
+                    writer: BufWriter::new(file),
+                }
+            }
+            TraceOutput::Ring => Sink::Ring(VecDeque::with_capacity(config.limit.min(0x10000))),
+        };
+        Ok(Tracer {
+            sink,
+            limit: config.limit,
+            written: 0,
+        })
+    }
+
+    /// Whether no more lines will be traced, because the file reached the limit or the ring
+    /// buffer holds none
+    pub fn is_done(&self) -> bool {
+        match self.sink {
+            Sink::File { .. } => self.written >= self.limit,
+            Sink::Ring(_) => self.limit == 0,
+        }
+    }
+
+    /// Trace the instruction about to run, with `pcmem` the 4 bytes at PC
+    pub fn trace(&mut self, state: &CpuState, pcmem: [u8; 4]) {
+        if self.is_done() {
+            return;
+        }
+        let line = format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            state.a,
+            state.f,
+            state.b,
+            state.c,
+            state.d,
+            state.e,
+            state.h,
+            state.l,
+            state.sp,
+            state.pc,
+            pcmem[0],
+            pcmem[1],
+            pcmem[2],
+            pcmem[3]
+        );
+        match &mut self.sink {
+            Sink::File { path, writer } => {
+                self.written += 1;
+                if let Err(e) = writeln!(writer, "{line}") {
+                    error!("Failed to write trace to {}: {e}", path.display());
+                    // stop tracing
+                    self.written = self.limit;
+                } else if self.written == self.limit {
+                    let _ = writer.flush();
+                    info!("Trace limit of {} instructions reached", self.limit);
+                }
+            }
+            Sink::Ring(lines) => {
+                if lines.len() == self.limit {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+        }
+    }
+
+    /// Lines held by the ring buffer, oldest first
+    pub fn lines(&self) -> Vec<String> {
+        match &self.sink {
+            Sink::File { .. } => Vec::new(),
+            Sink::Ring(lines) => lines.iter().cloned().collect(),
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        match &mut self.sink {
+            Sink::File { path, writer } => writer.flush().map_err(|source| Error::Io {
+                path: path.clone(),
+                source,
+            }),
+            Sink::Ring(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TraceConfig, TraceOutput, Tracer};
+    use crate::debugger::CpuState;
+
+    fn state(pc: u16) -> CpuState {
+        CpuState {
+            a: 0x01,
+            f: 0xB0,
+            b: 0x00,
+            c: 0x13,
+            d: 0x00,
+            e: 0xD8,
+            h: 0x01,
+            l: 0x4D,
+            sp: 0xFFFE,
+            pc,
+            ime: false,
+            halted: false,
+            locked: false,
+            rom_bank: 1,
+            wram_bank: 1,
+            vram_bank: 0,
+        }
+    }
+
+    #[test]
+    fn ring_keeps_last_lines() {
+        let mut tracer = Tracer::new(&TraceConfig {
+            output: TraceOutput::Ring,
+            limit: 2,
+            stub_ly: false,
+        })
+        .unwrap();
+        for pc in 0x100..0x103 {
+            tracer.trace(&state(pc), [0x00, 0xC3, 0x13, 0x02]);
+        }
+        // the ring buffer keeps tracing past the limit
+        assert!(!tracer.is_done());
+        let lines = tracer.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:00,C3,13,02"
+        );
+    }
+
+    #[test]
+    fn file_stops_at_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.log");
+        let mut tracer = Tracer::new(&TraceConfig {
+            output: TraceOutput::File(path.clone()),
+            limit: 3,
+            stub_ly: false,
+        })
+        .unwrap();
+        for pc in 0x100..0x110 {
+            tracer.trace(&state(pc), [0; 4]);
+        }
+        assert!(tracer.is_done());
+        tracer.flush().unwrap();
+        let trace = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(trace.lines().count(), 3);
+        assert!(trace.lines().last().unwrap().contains("PC:0102"));
+    }
+}
@@ -0,0 +1,83 @@
+//! Test ROMs of the Blargg, Mooneye and acid2 suites, run headless.
+//!
+//! The ROMs and the reference pictures are read from `roms/`, where `make test` unpacks them.
+//! They are not part of the repository, so the tests are ignored unless run with
+//! `--include-ignored`, which `make test` does when `roms/` holds gzipped ROMs.
+
+use rboy::Renderer;
+use rboy::headless::{Check, Headless, Outcome};
+
+fn read(name: &str) -> Vec<u8> {
+    let path = format!("roms/{name}");
+    std::fs::read(&path).unwrap_or_else(|e| panic!("Could not read {path}: {e}"))
+}
+
+fn run(rom: &str, color: bool, renderer: Renderer, check: Check, max_frames: u32) {
+    let mut runner = Headless::new(read(rom), color).unwrap();
+    runner.device().set_renderer(renderer);
+    let outcome = runner.run(&check, max_frames).unwrap();
+    assert_eq!(
+        outcome,
+        Outcome::Passed,
+        "{rom} {outcome}\n{}",
+        runner.serial_output()
+    );
+}
+
+#[test]
+#[ignore = "needs the test ROMs in roms/"]
+fn instr_timing() {
+    run(
+        "instr_timing.gb",
+        false,
+        Renderer::Scanline,
+        Check::Serial,
+        600,
+    );
+}
+
+#[test]
+#[ignore = "needs the test ROMs in roms/"]
+fn mem_timing() {
+    run(
+        "mem_timing.gb",
+        false,
+        Renderer::Scanline,
+        Check::Serial,
+        600,
+    );
+}
+
+#[test]
+#[ignore = "needs the test ROMs in roms/"]
+fn halt_bug() {
+    run("halt_bug.gb", false, Renderer::Scanline, Check::Serial, 600);
+}
+
+#[test]
+#[ignore = "needs the test ROMs in roms/"]
+fn dmg_acid2() {
+    let reference = Check::Screen(read("dmg-acid2.png"));
+    run("dmg-acid2.gb", false, Renderer::Scanline, reference, 60);
+}
+
+#[test]
+#[ignore = "needs the test ROMs in roms/"]
+fn dmg_acid2_pixel_fifo() {
+    let reference = Check::Screen(read("dmg-acid2.png"));
+    run("dmg-acid2.gb", false, Renderer::PixelFifo, reference, 60);
+}
+
+#[test]
+#[ignore = "needs the test ROMs in roms/"]
+fn cgb_acid2() {
+    let reference = Check::Screen(read("cgb-acid2.png"));
+    run("cgb-acid2.gbc", true, Renderer::Scanline, reference, 60);
+}
+
+#[test]
+#[ignore = "needs the test ROMs in roms/"]
+fn cgb_acid2_pixel_fifo() {
+    let reference = Check::Screen(read("cgb-acid2.png"));
+    run("cgb-acid2.gbc", true, Renderer::PixelFifo, reference, 60);
+}